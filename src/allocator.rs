@@ -1,4 +1,4 @@
-/// Contains code for allocations.
+//! Contains code for allocations.
 
 pub struct Dummy();
 
@@ -62,6 +62,54 @@ pub fn init_heap(
 // static ALLOCATOR: LockedHeap = LockedHeap::empty();
 
 
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+// Bytes handed out and not freed yet, for the status bar.
+// The global allocator keeps it up to date.
+static HEAP_USED: AtomicUsize = AtomicUsize::new(0);
+
+/// How much of the heap is in use, counting what was asked for rather than
+/// the blocks that were handed out.
+pub fn heap_used() -> usize {
+	HEAP_USED.load(Ordering::Relaxed)
+}
+
+fn count_alloc(ptr: *mut u8, layout: &Layout) {
+	if !ptr.is_null() {
+		HEAP_USED.fetch_add(layout.size(), Ordering::Relaxed);
+	}
+}
+
+fn count_dealloc(layout: &Layout) {
+	HEAP_USED.fetch_sub(layout.size(), Ordering::Relaxed);
+}
+
+/// The `mem` command, for the shell.
+pub fn register_commands() {
+	use crate::shell::{register, Command, CommandError, CommandResult};
+	use alloc::{boxed::Box, string::String, vec::Vec};
+
+	async fn mem(args: Vec<String>) -> CommandResult {
+		if !args.is_empty() {
+			return Err(CommandError::Usage);
+		}
+		let used = heap_used();
+		crate::println!(
+			"heap: {} of {} KiB used ({}%), at {:p}",
+			used / 1024, HEAP_SIZE / 1024, used * 100 / HEAP_SIZE, HEAP_START,
+		);
+		Ok(())
+	}
+
+	register(Command {
+		name: "mem",
+		usage: "",
+		help: "how much of the heap is in use",
+		run: |args| Box::pin(mem(args)),
+	}).expect("there's a mem command already");
+}
+
+
 // Allocator designs
 
 pub mod bump;
@@ -79,7 +127,7 @@ impl<A> Locked<A> {
 		}
 	}
 
-	pub fn lock(&self) -> spin::MutexGuard<A> {
+	pub fn lock(&self) -> spin::MutexGuard<'_, A> {
 		self.inner.lock()
 	}
 }
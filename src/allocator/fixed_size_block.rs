@@ -46,7 +46,7 @@ use alloc::alloc::GlobalAlloc;
 unsafe impl GlobalAlloc for Locked<FixedSizeAllocator> {
 	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
 		let mut allocator = self.lock();
-		match allocator.list_index(&layout) {
+		let ptr = match allocator.list_index(&layout) {
 			None => allocator.fallback_alloc(layout),
 			Some(index) => {
 				match allocator.list_heads[index].take() {
@@ -65,11 +65,14 @@ unsafe impl GlobalAlloc for Locked<FixedSizeAllocator> {
 					}
 				}
 			}
-		}
+		};
+		super::count_alloc(ptr, &layout);
+		ptr
 	}
 
 	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
 		let mut allocator = self.lock();
+		super::count_dealloc(&layout);
 
 		match allocator.list_index(&layout) {
 			// size not in default blocks, dealloc using fallback
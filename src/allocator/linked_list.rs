@@ -66,7 +66,7 @@ impl LinkedListAllocator {
 		let mut current = &mut self.head;  // dummy
 
 		while let Some(ref mut region) = current.next {
-			if let Ok(alloc_start) = Self::alloc_from_region(&region, size, align) {
+			if let Ok(alloc_start) = Self::alloc_from_region(region, size, align) {
 				let new_next = region.next.take();
 				let ret = Some((current.next.take().unwrap(), alloc_start));
 				current.next = new_next;  // retopologise the list
@@ -0,0 +1,239 @@
+//! A small ANSI/VT100 escape sequence parser.
+//!
+//! Bytes are fed in one at a time and come out as `Action`s for whoever
+//! owns a screen to perform. It only knows about the grammar (UTF-8 text,
+//! C0 controls and CSI sequences), not what they mean.
+
+const MAX_PARAMS: usize = 8;
+const ESC: u8 = 0x1b;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+	/// A character to put on the screen
+	Print(char),
+	/// A C0 control character like `\n`, `\r`, `\t` or backspace
+	Execute(u8),
+	/// A complete `ESC [ ... final` sequence
+	Csi(Csi),
+	/// `ESC c`, a full terminal reset
+	Reset,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Csi {
+	params: [u16; MAX_PARAMS],
+	len: usize,
+	/// set for sequences like `ESC [ ? 25 l`
+	pub private: bool,
+	pub final_byte: u8,
+}
+
+impl Csi {
+	pub fn params(&self) -> &[u16] {
+		&self.params[..self.len]
+	}
+
+	/// Missing and zero parameters both mean "use the default".
+	pub fn param(&self, index: usize, default: u16) -> u16 {
+		match self.params().get(index) {
+			Some(&0) | None => default,
+			Some(&n) => n,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+	Ground,
+	Escape,
+	CsiParam,
+	// a malformed sequence, swallow bytes till the final byte
+	CsiIgnore,
+}
+
+// a multi-byte UTF-8 character we're halfway through
+#[derive(Debug, Clone, Copy)]
+struct Utf8 {
+	code: u32,
+	remaining: u8,
+	min: u32,  // anything below this was an overlong encoding
+}
+
+#[derive(Debug)]
+pub struct Parser {
+	state: State,
+	csi: Csi,
+	utf8: Utf8,
+}
+
+impl Parser {
+	pub const fn new() -> Self {
+		Parser {
+			state: State::Ground,
+			csi: Csi {
+				params: [0; MAX_PARAMS],
+				len: 0,
+				private: false,
+				final_byte: 0,
+			},
+			utf8: Utf8 {
+				code: 0,
+				remaining: 0,
+				min: 0,
+			},
+		}
+	}
+
+	pub fn advance(&mut self, byte: u8) -> Option<Action> {
+		// a character that was cut short is dropped
+		if self.utf8.remaining > 0 && !is_continuation(byte) {
+			self.utf8.remaining = 0;
+		}
+
+		// ESC always starts over, even in the middle of a sequence
+		if byte == ESC {
+			self.state = State::Escape;
+			return None;
+		}
+
+		// controls are executed wherever they show up, like a real VT100
+		if byte < 0x20 || byte == 0x7f {
+			return match byte {
+				0x7f => None,
+				_ => Some(Action::Execute(byte)),
+			};
+		}
+
+		match self.state {
+			State::Ground => match byte {
+				0x20..=0x7e => Some(Action::Print(char::from(byte))),
+				_ => self.utf8(byte),
+			},
+			State::Escape => match byte {
+				b'[' => {
+					self.csi.params = [0; MAX_PARAMS];
+					self.csi.len = 0;
+					self.csi.private = false;
+					self.state = State::CsiParam;
+					None
+				}
+				b'c' => {
+					self.state = State::Ground;
+					Some(Action::Reset)
+				}
+				_ => {
+					// other escapes aren't supported, drop them
+					self.state = State::Ground;
+					None
+				}
+			},
+			State::CsiParam => self.csi_param(byte),
+			State::CsiIgnore => {
+				if let 0x40..=0x7e = byte {
+					self.state = State::Ground;
+				}
+				None
+			}
+		}
+	}
+
+	fn csi_param(&mut self, byte: u8) -> Option<Action> {
+		let csi = &mut self.csi;
+		match byte {
+			b'0'..=b'9' => {
+				if csi.len == 0 {
+					csi.len = 1;
+				}
+				let param = &mut csi.params[csi.len - 1];
+				*param = param
+					.saturating_mul(10)
+					.saturating_add(u16::from(byte - b'0'));
+				None
+			}
+			b';' => {
+				if csi.len == 0 {
+					csi.len = 1;  // the empty first parameter
+				}
+				if csi.len == MAX_PARAMS {
+					self.state = State::CsiIgnore;
+				} else {
+					csi.len += 1;
+				}
+				None
+			}
+			b'?' if csi.len == 0 && !csi.private => {
+				csi.private = true;
+				None
+			}
+			0x40..=0x7e => {
+				csi.final_byte = byte;
+				self.state = State::Ground;
+				Some(Action::Csi(*csi))
+			}
+			_ => {
+				self.state = State::CsiIgnore;
+				None
+			}
+		}
+	}
+
+	fn utf8(&mut self, byte: u8) -> Option<Action> {
+		let utf8 = &mut self.utf8;
+
+		if utf8.remaining > 0 {
+			utf8.code = (utf8.code << 6) | u32::from(byte & 0x3f);
+			utf8.remaining -= 1;
+			if utf8.remaining > 0 {
+				return None;
+			}
+
+			// surrogates and overlong encodings aren't characters
+			let c = match utf8.code >= utf8.min {
+				true => char::from_u32(utf8.code),
+				false => None,
+			};
+			return Some(Action::Print(c.unwrap_or(char::REPLACEMENT_CHARACTER)));
+		}
+
+		let (code, remaining, min) = match byte {
+			0xc0..=0xdf => (byte & 0x1f, 1, 0x80),
+			0xe0..=0xef => (byte & 0x0f, 2, 0x800),
+			0xf0..=0xf4 => (byte & 0x07, 3, 0x1_0000),
+			// a stray continuation byte, or one that can't start a character
+			_ => return Some(Action::Print(char::REPLACEMENT_CHARACTER)),
+		};
+		*utf8 = Utf8 {
+			code: u32::from(code),
+			remaining,
+			min,
+		};
+		None
+	}
+}
+
+fn is_continuation(byte: u8) -> bool {
+	byte & 0xc0 == 0x80
+}
+
+#[test_case]
+fn test_utf8_split_across_writes() {
+	let mut parser = Parser::new();
+	let mut printed = ['\0'; 2];
+	let mut count = 0;
+	// 'é' followed by '─', fed a byte at a time
+	for &byte in "é─".as_bytes() {
+		if let Some(Action::Print(c)) = parser.advance(byte) {
+			printed[count] = c;
+			count += 1;
+		}
+	}
+	assert_eq!(&printed[..count], &['é', '─']);
+
+	// an overlong '/' and a truncated sequence
+	let bad = [0xc0, 0xaf, 0xe2, 0x94, b'x'];
+	let actions = [None, Some(Action::Print(char::REPLACEMENT_CHARACTER)),
+		None, None, Some(Action::Print('x'))];
+	for (&byte, &action) in bad.iter().zip(actions.iter()) {
+		assert_eq!(parser.advance(byte), action);
+	}
+}
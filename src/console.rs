@@ -0,0 +1,415 @@
+//! Where `print!` ends up.
+//!
+//! Output fans out to every enabled sink in the registry: the VGA kernel
+//! console, the serial ports and the Bochs/QEMU debug port to begin with.
+//! Anything else that can show text can `register` itself as a sink too.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use spin::Mutex;
+
+pub trait Sink: Sync {
+	fn name(&self) -> &'static str;
+
+	/// Sinks do their own locking, `print!` can be called from anywhere.
+	fn write_fmt(&self, args: fmt::Arguments);
+}
+
+pub type SinkId = usize;
+
+pub const MAX_SINKS: usize = 8;
+
+// the built in sinks always sit in these slots
+pub const VGA: SinkId = 0;
+pub const SERIAL: SinkId = 1;
+pub const DEBUGCON: SinkId = 2;
+// `register` starts after them, so their ids always mean them
+const BUILT_IN_SINKS: usize = 3;
+
+static VGA_SINK: VgaSink = VgaSink;
+pub static SERIAL_SINK: SerialSink = SerialSink { colors: AtomicU8::new(SerialColors::Ansi as u8) };
+static DEBUGCON_SINK: DebugconSink = DebugconSink;
+
+static SINKS: Mutex<[Option<&'static dyn Sink>; MAX_SINKS]> = Mutex::new([
+	Some(&VGA_SINK), Some(&SERIAL_SINK), Some(&DEBUGCON_SINK),
+	None, None, None, None, None,
+]);
+
+// kept outside the mutex so checking them never blocks
+// only the VGA is on to begin with, like before there were sinks
+static ENABLED: [AtomicBool; MAX_SINKS] = [
+	AtomicBool::new(true), AtomicBool::new(false), AtomicBool::new(false), AtomicBool::new(false),
+	AtomicBool::new(false), AtomicBool::new(false), AtomicBool::new(false), AtomicBool::new(false),
+];
+
+/// Adds a sink, it starts out disabled. Returns `None` when the registry is full.
+pub fn register(sink: &'static dyn Sink) -> Option<SinkId> {
+	use x86_64::instructions::interrupts;
+
+	interrupts::without_interrupts(|| {
+		let mut sinks = SINKS.lock();
+		let id = BUILT_IN_SINKS + sinks[BUILT_IN_SINKS..].iter().position(|slot| slot.is_none())?;
+		sinks[id] = Some(sink);
+		Some(id)
+	})
+}
+
+/// Takes a sink out of the registry, its slot goes to the next `register`.
+/// The built in sinks stay, they can only be disabled.
+pub fn unregister(id: SinkId) {
+	use x86_64::instructions::interrupts;
+
+	if id < BUILT_IN_SINKS {
+		return;
+	}
+	set_enabled(id, false);
+	interrupts::without_interrupts(|| {
+		if let Some(slot) = SINKS.lock().get_mut(id) {
+			*slot = None;
+		}
+	});
+}
+
+pub fn set_enabled(id: SinkId, enabled: bool) {
+	if let Some(flag) = ENABLED.get(id) {
+		flag.store(enabled, Ordering::Relaxed);
+	}
+}
+
+pub fn is_enabled(id: SinkId) -> bool {
+	ENABLED.get(id).is_some_and(|flag| flag.load(Ordering::Relaxed))
+}
+
+/// Looks a sink up by its name, so it can be toggled from a command line.
+pub fn find(name: &str) -> Option<SinkId> {
+	use x86_64::instructions::interrupts;
+
+	interrupts::without_interrupts(|| {
+		SINKS.lock().iter().position(|slot| match slot {
+			Some(sink) => sink.name() == name,
+			None => false,
+		})
+	})
+}
+
+
+// The built in sinks
+
+struct VgaSink;
+
+impl Sink for VgaSink {
+	fn name(&self) -> &'static str {
+		"vga"
+	}
+
+	fn write_fmt(&self, args: fmt::Arguments) {
+		use crate::vga_buffer::{graphics, CONSOLES, KERNEL_CONSOLE};
+		use fmt::Write;
+		CONSOLES[KERNEL_CONSOLE].lock().write_fmt(args).unwrap();
+		// the console isn't on screen in graphics mode, a copy is drawn there instead
+		if graphics::is_active() {
+			graphics::TEXT.lock().write_fmt(args).unwrap();
+		}
+	}
+}
+
+/// Mirrors the console to the serial log, and to the serial console if
+/// that's on another port.
+pub struct SerialSink {
+	colors: AtomicU8,
+}
+
+/// What the serial sink does with the colour escapes the console gets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SerialColors {
+	/// Passed on as they are, for a terminal with all 16 colours.
+	Ansi,
+	/// Translated for a terminal with only 8, see `BasicColors`.
+	Basic,
+	/// Taken out, a log file just gets garbage from them.
+	Strip,
+}
+
+impl SerialSink {
+	pub fn set_colors(&self, colors: SerialColors) {
+		self.colors.store(colors as u8, Ordering::Relaxed);
+	}
+
+	pub fn colors(&self) -> SerialColors {
+		match self.colors.load(Ordering::Relaxed) {
+			0 => SerialColors::Ansi,
+			1 => SerialColors::Basic,
+			_ => SerialColors::Strip,
+		}
+	}
+}
+
+impl Sink for SerialSink {
+	fn name(&self) -> &'static str {
+		"serial"
+	}
+
+	fn write_fmt(&self, args: fmt::Arguments) {
+		use crate::serial::{port, Channel, SerialOutput};
+		use fmt::Write;
+
+		let log = port(Channel::Log);
+		let console = port(Channel::Console).filter(|&console| Some(console) != log);
+		for com in log.into_iter().chain(console) {
+			// queued once the heap is up, the console doesn't wait for the UART
+			// only a `Display` impl can fail here, and panicking would just
+			// print through here again
+			let _ = match self.colors() {
+				SerialColors::Ansi => SerialOutput(com).write_fmt(args),
+				SerialColors::Basic => BasicColors::new(SerialOutput(com)).write_fmt(args),
+				SerialColors::Strip => StripAnsi::new(SerialOutput(com)).write_fmt(args),
+			};
+		}
+	}
+}
+
+/// Port 0xe9, Bochs and QEMU (with `-debugcon`) print whatever is written there.
+struct DebugconSink;
+
+impl Sink for DebugconSink {
+	fn name(&self) -> &'static str {
+		"debugcon"
+	}
+
+	fn write_fmt(&self, args: fmt::Arguments) {
+		use fmt::Write;
+		Debugcon.write_fmt(args).unwrap();
+	}
+}
+
+struct Debugcon;
+
+impl fmt::Write for Debugcon {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		use x86_64::instructions::port::Port;
+
+		let mut port = Port::new(0xe9);
+		for byte in s.bytes() {
+			unsafe { port.write(byte) };
+		}
+		Ok(())
+	}
+}
+
+/// Passes text through to `inner` with the escape sequences taken out.
+pub struct StripAnsi<W> {
+	inner: W,
+	parser: crate::ansi::Parser,
+}
+
+impl<W: fmt::Write> StripAnsi<W> {
+	pub fn new(inner: W) -> Self {
+		StripAnsi {
+			inner,
+			parser: crate::ansi::Parser::new(),
+		}
+	}
+}
+
+impl<W: fmt::Write> fmt::Write for StripAnsi<W> {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		use crate::ansi::Action;
+
+		for byte in s.bytes() {
+			match self.parser.advance(byte) {
+				Some(Action::Print(c)) => self.inner.write_char(c)?,
+				Some(Action::Execute(control)) => self.inner.write_char(char::from(control))?,
+				Some(Action::Csi(_)) | Some(Action::Reset) | None => {}
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Passes text through to `inner` with the colours put the way a terminal
+/// with only the 8 basic ones shows them. The bright colours become bold,
+/// which such terminals draw bright, and the plain ones turn bold off again
+/// like they do on the VGA. Other escape sequences go through unchanged.
+pub struct BasicColors<W> {
+	inner: W,
+	parser: crate::ansi::Parser,
+}
+
+impl<W: fmt::Write> BasicColors<W> {
+	pub fn new(inner: W) -> Self {
+		BasicColors {
+			inner,
+			parser: crate::ansi::Parser::new(),
+		}
+	}
+
+	fn write_csi(&mut self, csi: &crate::ansi::Csi) -> fmt::Result {
+		self.inner.write_str("\x1b[")?;
+		if csi.private {
+			self.inner.write_char('?')?;
+		}
+		let mut separator = "";
+		let mut param = |inner: &mut W, param: u16| {
+			let result = write!(inner, "{}{}", separator, param);
+			separator = ";";
+			result
+		};
+		for &p in csi.params() {
+			match (csi.final_byte, p) {
+				(b'm', 30..=37) => {
+					param(&mut self.inner, 22)?;
+					param(&mut self.inner, p)?;
+				}
+				(b'm', 90..=97) => {
+					param(&mut self.inner, 1)?;
+					param(&mut self.inner, p - 60)?;
+				}
+				// there's no bright background to be had
+				(b'm', 100..=107) => param(&mut self.inner, p - 60)?,
+				_ => param(&mut self.inner, p)?,
+			}
+		}
+		self.inner.write_char(char::from(csi.final_byte))
+	}
+}
+
+impl<W: fmt::Write> fmt::Write for BasicColors<W> {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		use crate::ansi::Action;
+
+		for byte in s.bytes() {
+			match self.parser.advance(byte) {
+				Some(Action::Print(c)) => self.inner.write_char(c)?,
+				Some(Action::Execute(control)) => self.inner.write_char(char::from(control))?,
+				Some(Action::Csi(csi)) => self.write_csi(&csi)?,
+				Some(Action::Reset) => self.inner.write_str("\x1bc")?,
+				None => {}
+			}
+		}
+		Ok(())
+	}
+}
+
+
+// Printing
+
+#[macro_export]
+macro_rules! println {
+	// no need to import print!() to use println!()
+	() => ($crate::print!("\n"));
+	($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! print {
+	($($arg:tt)*) => ($crate::console::_print(format_args!($($arg)*)));
+}
+
+/// `println!` in the theme's warning colour.
+#[macro_export]
+macro_rules! warnln {
+	($($arg:tt)*) => (
+		$crate::console::_print_colored($crate::vga_buffer::theme().warning, format_args!($($arg)*))
+	);
+}
+
+/// `println!` in the theme's error colour.
+#[macro_export]
+macro_rules! errorln {
+	($($arg:tt)*) => (
+		$crate::console::_print_colored($crate::vga_buffer::theme().error, format_args!($($arg)*))
+	);
+}
+
+#[doc(hidden)]
+pub fn _print_colored(color: crate::vga_buffer::ColorCode, args: fmt::Arguments) {
+	// back to the theme's colour before the newline, so a coloured
+	// background doesn't run on into the next line
+	_print(format_args!("{}{}\x1b[0m\n", color.ansi(), args));
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+	// if an interrupt occurs while one of the sinks is locked
+	// a deadlock occurs.
+	// Thus, we'll prevent the handling of interrupts while we print.
+	use x86_64::instructions::interrupts;
+	interrupts::without_interrupts(|| {
+		// a copy, so a sink can register another sink without deadlocking
+		let sinks = *SINKS.lock();
+		for (id, sink) in sinks.iter().enumerate() {
+			if let Some(sink) = sink {
+				if is_enabled(id) {
+					sink.write_fmt(args);
+				}
+			}
+		}
+	});
+}
+
+
+#[test_case]
+fn test_sinks_enable_and_disable() {
+	use core::sync::atomic::AtomicUsize;
+
+	struct Counter(AtomicUsize);
+
+	impl Sink for Counter {
+		fn name(&self) -> &'static str {
+			"test counter"
+		}
+
+		fn write_fmt(&self, args: fmt::Arguments) {
+			struct Count<'a>(&'a AtomicUsize);
+			impl fmt::Write for Count<'_> {
+				fn write_str(&mut self, s: &str) -> fmt::Result {
+					self.0.fetch_add(s.len(), Ordering::Relaxed);
+					Ok(())
+				}
+			}
+			fmt::Write::write_fmt(&mut Count(&self.0), args).unwrap();
+		}
+	}
+
+	static COUNTER: Counter = Counter(AtomicUsize::new(0));
+
+	let id = register(&COUNTER).expect("sink registry is full");
+	assert_eq!(find("test counter"), Some(id));
+	assert!(!is_enabled(id));
+
+	print!("not counted");
+	assert_eq!(COUNTER.0.load(Ordering::Relaxed), 0);
+
+	set_enabled(id, true);
+	println!("{}", 12345);
+	assert_eq!(COUNTER.0.load(Ordering::Relaxed), 6);
+
+	set_enabled(id, false);
+	print!("not counted");
+	assert_eq!(COUNTER.0.load(Ordering::Relaxed), 6);
+
+	unregister(id);
+	assert_eq!(find("test counter"), None);
+
+	// the built in ones keep their slots
+	unregister(DEBUGCON);
+	assert_eq!(find("debugcon"), Some(DEBUGCON));
+	let id = register(&COUNTER).expect("sink registry is full");
+	assert!(id >= BUILT_IN_SINKS);
+	unregister(id);
+}
+
+#[test_case]
+fn test_strip_ansi() {
+	use alloc::string::String;
+	use fmt::Write;
+
+	let mut text = String::new();
+	writeln!(StripAnsi::new(&mut text), "\x1b[91m[FAIL]\x1b[0m é").unwrap();
+	assert_eq!(text, "[FAIL] é\n");
+
+	let mut text = String::new();
+	write!(BasicColors::new(&mut text), "\x1b[91;104mA\x1b[37mB\x1b[2H").unwrap();
+	assert_eq!(text, "\x1b[1;31;44mA\x1b[22;37mB\x1b[2H");
+}
@@ -0,0 +1,81 @@
+//! QEMU's firmware configuration device, for boot parameters.
+//!
+//! `-fw_cfg name=opt/text_os/keyboard_layout,string=de` hands the kernel a
+//! file, `file` reads it back. Without QEMU there's no such device and no
+//! files either.
+
+use x86_64::instructions::port::Port;
+
+const SELECTOR: u16 = 0x510;
+const DATA: u16 = 0x511;
+
+// items, the files have theirs in the directory
+const SIGNATURE: u16 = 0x0000;
+const FILE_DIR: u16 = 0x0019;
+
+const NAME_LEN: usize = 56;
+
+// An item, read from its start one byte at a time.
+struct Item {
+	data: Port<u8>,
+}
+
+impl Item {
+	fn select(key: u16) -> Item {
+		unsafe { Port::<u16>::new(SELECTOR).write(key) };
+		Item { data: Port::new(DATA) }
+	}
+
+	fn read(&mut self, buf: &mut [u8]) {
+		for byte in buf.iter_mut() {
+			*byte = unsafe { self.data.read() };
+		}
+	}
+
+	// the directory is big endian, unlike everything else
+	fn read_u32(&mut self) -> u32 {
+		let mut bytes = [0; 4];
+		self.read(&mut bytes);
+		u32::from_be_bytes(bytes)
+	}
+
+	fn read_u16(&mut self) -> u16 {
+		let mut bytes = [0; 2];
+		self.read(&mut bytes);
+		u16::from_be_bytes(bytes)
+	}
+}
+
+/// Whether the device is there at all.
+pub fn is_present() -> bool {
+	let mut signature = [0; 4];
+	Item::select(SIGNATURE).read(&mut signature);
+	&signature == b"QEMU"
+}
+
+/// Copies the file called `name` to `buf` and says how long it is, cut off
+/// at the end of `buf`. `None` if QEMU wasn't given such a file.
+///
+/// The device has one selector for everyone, so this is for the boot code.
+pub fn file(name: &str, buf: &mut [u8]) -> Option<usize> {
+	if !is_present() {
+		return None;
+	}
+
+	let mut dir = Item::select(FILE_DIR);
+	for _ in 0..dir.read_u32() {
+		let size = dir.read_u32() as usize;
+		let key = dir.read_u16();
+		dir.read_u16();  // reserved
+		let mut entry = [0; NAME_LEN];
+		dir.read(&mut entry);
+
+		let len = entry.iter().position(|&byte| byte == 0).unwrap_or(NAME_LEN);
+		if &entry[..len] == name.as_bytes() {
+			let len = size.min(buf.len());
+			Item::select(key).read(&mut buf[..len]);
+			return Some(len);
+		}
+	}
+	None
+}
@@ -15,9 +15,8 @@ lazy_static! {
 				const STACK_SIZE: usize = 1<<16;
 				static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
 
-				let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-				let stack_end = stack_start + STACK_SIZE;
-				stack_end  // the stack grows from here
+				let stack_start = VirtAddr::from_ptr(&raw const STACK);
+				stack_start + STACK_SIZE  // the stack grows from here
 			};
 		tss
 	};
@@ -23,6 +23,12 @@ lazy_static! {
 			.set_handler_fn(timer_interrupt_handler);
 		idt[InterruptIndex::Keyboard.as_usize()]
 			.set_handler_fn(keyboard_interrupt_handler);
+		idt[InterruptIndex::Serial2.as_usize()]
+			.set_handler_fn(serial2_interrupt_handler);
+		idt[InterruptIndex::Serial1.as_usize()]
+			.set_handler_fn(serial1_interrupt_handler);
+		idt[InterruptIndex::Mouse.as_usize()]
+			.set_handler_fn(mouse_interrupt_handler);
 
 		idt
 	};
@@ -36,12 +42,15 @@ pub fn init_idt() {
 // Handling breakpoint exceptions
 
 use x86_64::structures::idt::InterruptStackFrame;
-use crate::println;
+use crate::log::Level;
+use crate::irq_log;
+
+// Handlers log through `irq_log!`, the interrupted code may hold a console lock.
 
 extern "x86-interrupt" fn breakpoint_handler(
 	stack_frame: InterruptStackFrame
 ) {
-	println!("WE JUSt HAD A BREAKPOINT EXCEPTION:\n{:#?}", stack_frame);
+	irq_log!(Level::Info, "WE JUSt HAD A BREAKPOINT EXCEPTION at {:?}", stack_frame.instruction_pointer);
 }
 
 #[test_case]
@@ -51,14 +60,14 @@ fn test_breakpoint_exception() {
 
 
 extern "x86-interrupt" fn double_fault_handler(
-	stack_frame: InterruptStackFrame, _error_code: u64
+	stack_frame: InterruptStackFrame, error_code: u64
 ) -> ! { // diverging, x64 does not allow returning from double faults
-	println!("The double fault exception handler was called.");
-
 	// We need to switch stacks to prevent a stack overflow
 
-
-	panic!("AAAAAAAAHHH!! DOUBLE FAULT!\n{:#?}", stack_frame);
+	// the fault may have hit while a console was locked, so no panic!/println!
+	crate::panic_console::fatal_exception(
+		"AAAAAAAAHHH!! DOUBLE FAULT", &stack_frame, format_args!("error code {:#x}", error_code)
+	);
 }
 
 
@@ -84,6 +93,11 @@ pub static PICS: spin::Mutex<ChainedPics> =  // Mutex ie bottleneck lol
 pub enum InterruptIndex {
 	Timer = PIC_1_OFFSET,
 	Keyboard,
+	// COM2 and COM4
+	Serial2 = PIC_1_OFFSET + 3,
+	// COM1 and COM3
+	Serial1,
+	Mouse = PIC_2_OFFSET + 4,
 }
 
 impl InterruptIndex {
@@ -96,11 +110,25 @@ impl InterruptIndex {
 	}
 }
 
+// Ticks since the timer started, the PIT fires about 18.2 times a second.
+use core::sync::atomic::{AtomicU64, Ordering};
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+pub fn ticks() -> u64 {
+	TICKS.load(Ordering::Relaxed)
+}
+
+/// How long the timer has been running. The PIT divides its 1.193182 MHz
+/// clock by 65536.
+pub fn uptime_millis() -> u64 {
+	ticks() * 65536 * 1000 / 1_193_182
+}
+
 extern "x86-interrupt" fn timer_interrupt_handler(
 	_stack_frame: InterruptStackFrame,
 ) {
-	use crate::print;
-	print!(".");
+	let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+	crate::task::timer::tick(now);
 
 	// Notify the PIC (not CPU) to end the interrupt and become available again
 	unsafe {
@@ -114,7 +142,6 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(
 ) {
 	// use crate::print;
 
-	use x86_64::instructions::port::Port;
 	// use pc_keyboard::{
 	// 	layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1
 	// };
@@ -127,8 +154,8 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(
 	// 		));
 	// }
 
-	let mut port = Port::new(0x60);  // port of keyboard? port of keyboard.
-	let scancode: u8 = unsafe { port.read() };
+	// handled asynchronously instead -> lower interrupt time
+	crate::ps2::handle_interrupt();
 	// let mut keyboard = KEYBOARD.lock();
 	// if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
 	// 	if let Some(key) = keyboard.process_keyevent(key_event) {
@@ -139,9 +166,6 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(
 	// 	}
 	// }
 
-	// handled asynchronously instead -> lower interrupt time
-	crate::task::keyboard::update_scancode_queue(scancode);
-
 	// Notify the PIC (not CPU) to end the interrupt and become available again
 	// ----***  Don't forget to use the correct interrupt index!  ***----
 	unsafe {
@@ -150,6 +174,50 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(
 	}
 }
 
+extern "x86-interrupt" fn serial2_interrupt_handler(
+	_stack_frame: InterruptStackFrame,
+) {
+	crate::serial::handle_interrupt(3);
+
+	unsafe {
+		PICS.lock()
+			.notify_end_of_interrupt(InterruptIndex::Serial2.as_u8());
+	}
+}
+
+extern "x86-interrupt" fn serial1_interrupt_handler(
+	_stack_frame: InterruptStackFrame,
+) {
+	crate::serial::handle_interrupt(4);
+
+	unsafe {
+		PICS.lock()
+			.notify_end_of_interrupt(InterruptIndex::Serial1.as_u8());
+	}
+}
+
+extern "x86-interrupt" fn mouse_interrupt_handler(
+	_stack_frame: InterruptStackFrame,
+) {
+	crate::ps2::handle_interrupt();
+
+	// both PICs want to hear about it, notify_end_of_interrupt sees to that
+	unsafe {
+		PICS.lock()
+			.notify_end_of_interrupt(InterruptIndex::Mouse.as_u8());
+	}
+}
+
+/// Lets an IRQ through its PIC, in case the firmware left it masked.
+/// The IDT must have a handler for it.
+pub(crate) unsafe fn unmask_irq(irq: u8) {
+	use x86_64::instructions::port::Port;
+
+	let mut mask = Port::<u8>::new(if irq < 8 { 0x21 } else { 0xa1 });
+	let masked = mask.read();
+	mask.write(masked & !(1 << (irq % 8)));
+}
+
 use x86_64::structures::idt::PageFaultErrorCode;
 
 extern "x86-interrupt" fn page_fault_handler(
@@ -158,11 +226,10 @@ extern "x86-interrupt" fn page_fault_handler(
 ) {
 	// use x86_64::registers::control::Cr3;  // points to current page table
 	use x86_64::registers::control::Cr2;  // points to the bad address
-	use crate::hlt_loop;
 
-	println!("AAAAAAAAHHH! PAGE FAULT!");
-	println!("{:?}", error_code);
-	println!("Accessed address {:?}", Cr2::read());
-	println!("{:#?}", stack_frame);
-	hlt_loop();
+	crate::panic_console::fatal_exception(
+		"AAAAAAAAHHH! PAGE FAULT",
+		&stack_frame,
+		format_args!("{:?}\nAccessed address {:?}", error_code, Cr2::read()),
+	);
 }
@@ -8,7 +8,9 @@
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
-#![feature(const_mut_refs)]
+// the `new`s here aren't defaults, and the unsafe functions say why they are
+// in their first line
+#![allow(clippy::new_without_default, clippy::missing_safety_doc)]
 
 use core::panic::PanicInfo;
 
@@ -53,8 +55,21 @@ entry_point!(test_kernel_main);
 
 // Entry point
 #[cfg(test)]  // necessary because not always a test
-fn test_kernel_main(_boot_info: &'static BootInfo) -> ! {
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+
     init();
+
+    // some of the unit tests need a heap, and the whole VGA memory
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(physical_memory_offset) };
+    vga_buffer::map_vga_memory(physical_memory_offset);
+    let mut frame_allocator = unsafe {
+        memory::BootInfoFrameAllocator::init(&boot_info.memory_map)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("Heap initialisation failed.");
+
     test_main();
     hlt_loop();
 }
@@ -90,19 +105,38 @@ pub fn exit_qemu(exit_code: QemuExitCode) {
 }
 
 pub mod serial;
+pub mod console;
 pub mod vga_buffer;
+pub mod ansi;
+pub mod log;
+pub mod panic_console;
+pub mod status_bar;
+pub mod rtc;
+pub mod tui;
+pub mod line_editor;
+pub mod shell;
+pub mod fw_cfg;
 
 
 // Exceptions and Interrupts
 
 pub mod interrupts;
+pub mod ps2;
 
 // idt and all other things will be initialised here.
 pub fn init() {
     gdt::init();
     interrupts::init_idt();
 
-    unsafe { interrupts::PICS.lock().initialize() };
+    unsafe {
+        interrupts::PICS.lock().initialize();
+        // the mouse comes in through the second PIC
+        interrupts::unmask_irq(2);
+        interrupts::unmask_irq(12);
+    }
+    if let Err(err) = ps2::init() {
+        crate::warn!("the PS/2 controller isn't working: {}", err);
+    }
     x86_64::instructions::interrupts::enable();  // `sti` intrinsic, CPU will now listen for interrupts
 }
 
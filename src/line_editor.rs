@@ -0,0 +1,525 @@
+//! Reading a line from the keyboard, with the editing keys a shell has:
+//!
+//! - Left/Right, Home/End and Ctrl+A/E to move around
+//! - Backspace/Delete, and Ctrl+K/U/W to cut to the end, to the start or a word
+//! - Up/Down to go through the lines read before
+//! - Tab to complete the word before the cursor, if there's a completer
+//! - Ctrl+C to give up
+//!
+//! The line is drawn on a console through its `Writer` and can wrap over
+//! as many rows as it needs. It can be drawn on the serial console's
+//! terminal too, for a shell that's used from there.
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use pc_keyboard::{DecodedKey, KeyCode};
+
+use crate::serial::ComPort;
+use crate::task::keyboard::{KeyEvent, KeyStream};
+use crate::vga_buffer::CONSOLES;
+
+// how many lines Up can go back by default
+const HISTORY_LINES: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+	/// Ctrl+C.
+	Interrupted,
+	/// Something else has the keyboard, like another `LineEditor`.
+	KeyboardBusy,
+}
+
+/// Gets the text before the cursor, returns what the word before the cursor
+/// could be. Whole words, not just the rest of them.
+pub type Completer = Box<dyn Fn(&str) -> Vec<String>>;
+
+/// The lines read so far, oldest first. Only the newest ones are kept.
+pub struct History {
+	lines: VecDeque<String>,
+	capacity: usize,
+}
+
+impl History {
+	pub fn new(capacity: usize) -> History {
+		History {
+			lines: VecDeque::with_capacity(capacity),
+			capacity,
+		}
+	}
+
+	/// Remembers a line, unless it's blank or the same as the last one.
+	pub fn push(&mut self, line: &str) {
+		if line.trim().is_empty() || self.lines.back().map(String::as_str) == Some(line) {
+			return;
+		}
+		if self.lines.len() == self.capacity {
+			self.lines.pop_front();
+		}
+		if self.capacity > 0 {
+			self.lines.push_back(String::from(line));
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.lines.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.lines.is_empty()
+	}
+
+	/// `back` lines back, 1 is the newest.
+	pub fn get(&self, back: usize) -> Option<&str> {
+		let index = self.lines.len().checked_sub(back)?;
+		self.lines.get(index).map(String::as_str)
+	}
+}
+
+// the line being edited, in chars so the cursor can't land inside one
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Line {
+	chars: Vec<char>,
+	cursor: usize,
+}
+
+impl Line {
+	fn set(&mut self, text: &str) {
+		self.chars = text.chars().collect();
+		self.cursor = self.chars.len();
+	}
+
+	fn text(&self) -> String {
+		self.chars.iter().collect()
+	}
+
+	fn insert(&mut self, c: char) {
+		self.chars.insert(self.cursor, c);
+		self.cursor += 1;
+	}
+
+	fn backspace(&mut self) {
+		if self.cursor > 0 {
+			self.cursor -= 1;
+			self.chars.remove(self.cursor);
+		}
+	}
+
+	fn delete(&mut self) {
+		if self.cursor < self.chars.len() {
+			self.chars.remove(self.cursor);
+		}
+	}
+
+	fn kill_to_end(&mut self) {
+		self.chars.truncate(self.cursor);
+	}
+
+	fn kill_to_start(&mut self) {
+		self.chars.drain(..self.cursor);
+		self.cursor = 0;
+	}
+
+	// where the word before the cursor starts, spaces right before it count
+	// as part of it
+	fn word_start(&self) -> usize {
+		let before = &self.chars[..self.cursor];
+		let word_end = before.iter().rposition(|c| !c.is_whitespace()).map_or(0, |i| i + 1);
+		before[..word_end].iter().rposition(|c| c.is_whitespace()).map_or(0, |i| i + 1)
+	}
+
+	fn kill_word(&mut self) {
+		let start = self.word_start();
+		self.chars.drain(start..self.cursor);
+		self.cursor = start;
+	}
+
+	// completes the word before the cursor as far as all the candidates
+	// agree, true if that changed anything
+	fn complete(&mut self, candidates: &[String]) -> bool {
+		let start = self.chars[..self.cursor].iter().rposition(|c| c.is_whitespace()).map_or(0, |i| i + 1);
+		let word = &self.chars[start..self.cursor];
+
+		let mut common: Vec<char> = match candidates.first() {
+			Some(first) => first.chars().collect(),
+			None => return false,
+		};
+		for candidate in &candidates[1..] {
+			let same = common.iter().zip(candidate.chars()).take_while(|(a, b)| **a == *b).count();
+			common.truncate(same);
+		}
+		if common.len() < word.len() || common[..word.len()] != *word {
+			return false;  // the completer wants to replace the word, not extend it
+		}
+
+		let unique = candidates.len() == 1;
+		if common.len() == word.len() && !unique {
+			return false;
+		}
+		self.chars.splice(start..self.cursor, common.iter().copied());
+		self.cursor = start + common.len();
+		if unique && self.chars.get(self.cursor) != Some(&' ') {
+			self.insert(' ');
+		}
+		true
+	}
+}
+
+// where the line is on the console, to draw it again after every key
+struct Display {
+	console: usize,
+	start: (usize, usize),  // the first cell of the prompt
+	drawn: usize,  // cells drawn last time, prompt included
+	terminal: Option<ComPort>,  // drawn there as well
+}
+
+impl Display {
+	fn new(console: usize, terminal: Option<ComPort>) -> Display {
+		use x86_64::instructions::interrupts;
+
+		let start = interrupts::without_interrupts(|| CONSOLES[console].lock().position());
+		Display {
+			console,
+			start,
+			drawn: 0,
+			terminal,
+		}
+	}
+
+	// a terminal can only be told how far to move, not where to, so the
+	// line is drawn again from the start of the row the cursor is on.
+	// One wider than the terminal leaves its first rows behind.
+	fn draw_terminal(&self, prompt: &str, line: &Line) {
+		use core::fmt::Write;
+
+		let com = match self.terminal {
+			Some(com) => com,
+			None => return,
+		};
+		let mut text = String::from("\r");
+		text.push_str(prompt);
+		text.extend(line.chars.iter());
+		text.push_str("\x1b[K");
+		let back = line.chars.len() - line.cursor;
+		if back > 0 {
+			let _ = write!(text, "\x1b[{}D", back);
+		}
+		crate::serial::send(com, text.as_bytes());
+	}
+
+	// plain text for the terminal, which wants \r\n for a new line
+	fn print_terminal(&self, text: &str) {
+		if let Some(com) = self.terminal {
+			crate::serial::send(com, text.replace('\n', "\r\n").as_bytes());
+		}
+	}
+
+	// draws the prompt and the line over what was drawn before,
+	// and puts the cursor where it is in the line
+	fn draw(&mut self, prompt: &str, line: &Line) {
+		use x86_64::instructions::interrupts;
+
+		interrupts::without_interrupts(|| {
+			let mut writer = CONSOLES[self.console].lock();
+			let (width, height) = (writer.width(), writer.height());
+
+			let prompt_len = prompt.chars().count();
+			let len = prompt_len + line.chars.len();
+			// right after a full row the position is one past the edge
+			let (mut row, col) = (self.start.0 + self.start.1 / width, self.start.1 % width);
+
+			// scroll up first if the line, and the cursor after it, don't fit
+			let rows = (col + len.max(self.drawn)) / width + 1;
+			if row + rows > height {
+				let scroll = (row + rows - height).min(row);
+				writer.set_position(height - 1, 0);
+				for _ in 0..scroll {
+					writer.write_byte(b'\n');
+				}
+				row -= scroll;
+			}
+			self.start = (row, col);
+
+			writer.set_position(row, col);
+			writer.write_string(prompt);
+			let mut buffer = [0; 4];
+			for c in &line.chars {
+				writer.write_string(c.encode_utf8(&mut buffer));
+			}
+			// blank whatever's left of a longer line
+			for _ in len..self.drawn {
+				writer.write_byte(b' ');
+			}
+			self.drawn = len;
+
+			let cursor = col + prompt_len + line.cursor;
+			writer.set_position(row + cursor / width, cursor % width);
+		});
+		self.draw_terminal(prompt, line);
+	}
+
+	// puts the cursor after the line and starts a new one below it
+	fn finish(&mut self, prompt: &str, line: &mut Line, end: &str) {
+		use x86_64::instructions::interrupts;
+
+		line.cursor = line.chars.len();
+		self.draw(prompt, line);
+		interrupts::without_interrupts(|| {
+			let mut writer = CONSOLES[self.console].lock();
+			// a line that fills its last row has the cursor on the next one already
+			let end = match writer.position().1 == 0 && self.drawn > 0 {
+				true => end.strip_prefix('\n').unwrap_or(end),
+				false => end,
+			};
+			writer.write_string(end);
+		});
+		self.print_terminal(end);
+	}
+
+	// prints `text` below the line, which is drawn again after it
+	fn print_below(&mut self, prompt: &str, line: &Line, text: &str) {
+		use x86_64::instructions::interrupts;
+
+		self.finish(prompt, &mut line.clone(), "\n");
+		interrupts::without_interrupts(|| {
+			let mut writer = CONSOLES[self.console].lock();
+			writer.write_string(text);
+			writer.write_byte(b'\n');
+			self.start = writer.position();
+		});
+		self.print_terminal(text);
+		self.print_terminal("\n");
+		self.drawn = 0;
+		self.draw(prompt, line);
+	}
+}
+
+/// Reads lines on one console, and remembers them for Up and Down.
+///
+/// The first `read_line` grabs the keyboard and the editor keeps it until
+/// it's dropped, so what's typed between two lines is read by the next one.
+/// `take_keys` lends it to whatever runs in between. Keys typed while
+/// another console is on screen aren't for the editor and are dropped.
+pub struct LineEditor {
+	console: usize,
+	history: History,
+	completer: Option<Completer>,
+	keys: Option<KeyStream>,
+	terminal: bool,
+}
+
+impl LineEditor {
+	pub fn new(console: usize) -> LineEditor {
+		LineEditor {
+			console,
+			history: History::new(HISTORY_LINES),
+			completer: None,
+			keys: None,
+			terminal: false,
+		}
+	}
+
+	/// Draws the line on the serial console's terminal as well, see
+	/// `serial::Channel::Console`.
+	pub fn set_terminal(&mut self, on: bool) {
+		self.terminal = on;
+	}
+
+	/// What Tab asks for candidates, see `Completer`.
+	pub fn set_completer(&mut self, completer: Completer) {
+		self.completer = Some(completer);
+	}
+
+	pub fn history(&self) -> &History {
+		&self.history
+	}
+
+	/// The keyboard, if the editor has it grabbed. The next `read_line`
+	/// grabs it again, unless it's given back with `give_keys` first.
+	pub fn take_keys(&mut self) -> Option<KeyStream> {
+		self.keys.take()
+	}
+
+	pub fn give_keys(&mut self, keys: KeyStream) {
+		self.keys = Some(keys);
+	}
+
+	/// Shows `prompt` where the console's cursor is and lets the user edit a
+	/// line after it, until Enter. The prompt is plain text, no escape sequences.
+	pub async fn read_line(&mut self, prompt: &str) -> Result<String, Error> {
+		use crate::vga_buffer::active_console;
+		use futures_util::StreamExt;
+
+		let mut keys = match self.keys.take() {
+			Some(keys) => keys,
+			None => KeyStream::grab().ok_or(Error::KeyboardBusy)?,
+		};
+		let terminal = match self.terminal {
+			true => crate::serial::port(crate::serial::Channel::Console),
+			false => None,
+		};
+		let mut display = Display::new(self.console, terminal);
+		let mut line = Line::default();
+		// how far back in the history we are, and the line from before we went there
+		let mut back = 0;
+		let mut edited = String::new();
+
+		display.draw(prompt, &line);
+		let result = loop {
+			let event = match keys.next().await {
+				Some(event) => event,
+				None => break Err(Error::KeyboardBusy),
+			};
+			if active_console() != self.console {
+				continue;
+			}
+			match self.handle_key(&event, &mut line, &mut back, &mut edited, &mut display, prompt) {
+				Some(Ok(())) => {
+					display.finish(prompt, &mut line, "\n");
+					let text = line.text();
+					self.history.push(&text);
+					break Ok(text);
+				}
+				Some(Err(err)) => {
+					display.finish(prompt, &mut line, "^C\n");
+					break Err(err);
+				}
+				None => display.draw(prompt, &line),
+			}
+		};
+		self.keys = Some(keys);
+		result
+	}
+
+	// edits the line, `Some` once reading is over
+	fn handle_key(
+		&mut self, event: &KeyEvent, line: &mut Line, back: &mut usize, edited: &mut String,
+		display: &mut Display, prompt: &str,
+	) -> Option<Result<(), Error>> {
+		let key = event.decoded?;
+		if event.modifiers.ctrl {
+			match key {
+				DecodedKey::Unicode(c) => match c.to_ascii_lowercase() {
+					'a' => line.cursor = 0,
+					'e' => line.cursor = line.chars.len(),
+					'k' => line.kill_to_end(),
+					'u' => line.kill_to_start(),
+					'w' => line.kill_word(),
+					'c' => return Some(Err(Error::Interrupted)),
+					_ => {}
+				},
+				DecodedKey::RawKey(_) => {}
+			}
+			return None;
+		}
+
+		match key {
+			DecodedKey::Unicode('\n') => return Some(Ok(())),
+			DecodedKey::Unicode('\x08') => line.backspace(),
+			DecodedKey::Unicode('\x7f') => line.delete(),
+			DecodedKey::Unicode('\t') => self.complete(line, display, prompt),
+			DecodedKey::Unicode(c) if !c.is_control() => line.insert(c),
+			DecodedKey::RawKey(KeyCode::ArrowLeft) => line.cursor = line.cursor.saturating_sub(1),
+			DecodedKey::RawKey(KeyCode::ArrowRight) => line.cursor = (line.cursor + 1).min(line.chars.len()),
+			DecodedKey::RawKey(KeyCode::Home) => line.cursor = 0,
+			DecodedKey::RawKey(KeyCode::End) => line.cursor = line.chars.len(),
+			DecodedKey::RawKey(KeyCode::ArrowUp) if *back < self.history.len() => {
+				if *back == 0 {
+					*edited = line.text();
+				}
+				*back += 1;
+				line.set(self.history.get(*back).unwrap_or(""));
+			}
+			DecodedKey::RawKey(KeyCode::ArrowDown) if *back > 0 => {
+				*back -= 1;
+				match *back {
+					0 => line.set(edited),
+					_ => line.set(self.history.get(*back).unwrap_or("")),
+				}
+			}
+			_ => {}
+		}
+		None
+	}
+
+	fn complete(&self, line: &mut Line, display: &mut Display, prompt: &str) {
+		let completer = match &self.completer {
+			Some(completer) => completer,
+			None => return,
+		};
+		let before: String = line.chars[..line.cursor].iter().collect();
+		let candidates = completer(&before);
+		if line.complete(&candidates) || candidates.len() < 2 {
+			return;
+		}
+
+		// nothing in common, so show them all
+		let mut list = String::new();
+		for candidate in &candidates {
+			list.push_str(candidate);
+			list.push_str("  ");
+		}
+		display.print_below(prompt, line, list.trim_end());
+	}
+}
+
+
+#[test_case]
+fn test_line_editing() {
+	let mut line = Line::default();
+	line.set("echo hello  world");
+	line.kill_word();
+	assert_eq!(line.text(), "echo hello  ");
+	line.kill_word();
+	assert_eq!(line.text(), "echo ");
+
+	line.cursor = 2;
+	line.insert('x');
+	line.delete();
+	line.backspace();
+	assert_eq!((line.text().as_str(), line.cursor), ("eco ", 2));
+	line.kill_to_start();
+	line.cursor = 1;
+	line.kill_to_end();
+	assert_eq!(line.text(), "o");
+
+	line.set("he");
+	let candidates = [String::from("help"), String::from("hello")];
+	assert!(line.complete(&candidates));
+	assert_eq!(line.text(), "hel");
+	assert!(!line.complete(&candidates));
+	assert!(line.complete(&candidates[..1]));
+	assert_eq!(line.text(), "help ");
+
+	let mut history = History::new(2);
+	for text in &["one", "", "two", "two", "three"] {
+		history.push(text);
+	}
+	assert_eq!((history.len(), history.get(1), history.get(2)), (2, Some("three"), Some("two")));
+}
+
+#[test_case]
+fn test_wrapped_line() {
+	use x86_64::instructions::interrupts;
+
+	let console = crate::vga_buffer::KERNEL_CONSOLE + 5;
+	let width = interrupts::without_interrupts(|| {
+		let mut writer = CONSOLES[console].lock();
+		writer.write_string("\x1b[2J\x1b[1;1H");
+		writer.width()
+	});
+
+	let mut display = Display::new(console, None);
+	let mut line = Line::default();
+	line.set(&"x".repeat(width + 3));
+	display.draw("> ", &line);
+	line.set("short");
+	display.draw("> ", &line);
+
+	interrupts::without_interrupts(|| {
+		let writer = CONSOLES[console].lock();
+		crate::assert_screen!(writer.snapshot(), "> short");
+		assert_eq!(writer.position(), (0, 7));
+	});
+}
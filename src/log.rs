@@ -0,0 +1,439 @@
+//! Kernel logging.
+//!
+//! `error!`, `warn!`, `info!`, `debug!` and `trace!` tag a message with its
+//! level, the module it came from and the tick it happened on. Messages that
+//! pass the filter are printed and kept in a fixed ring buffer that `dmesg`
+//! reads back, so nothing needs the heap.
+
+use core::fmt;
+use spin::Mutex;
+
+pub mod irq;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+	Error = 1,
+	Warn,
+	Info,
+	Debug,
+	Trace,
+}
+
+impl Level {
+	fn from_name(name: &str) -> Option<Level> {
+		match name {
+			"error" => Some(Level::Error),
+			"warn" => Some(Level::Warn),
+			"info" => Some(Level::Info),
+			"debug" => Some(Level::Debug),
+			"trace" => Some(Level::Trace),
+			_ => None,
+		}
+	}
+
+	fn name(self) -> &'static str {
+		match self {
+			Level::Error => "ERROR",
+			Level::Warn => "WARN",
+			Level::Info => "INFO",
+			Level::Debug => "DEBUG",
+			Level::Trace => "TRACE",
+		}
+	}
+
+	// from the console theme, the serial sink can strip it
+	fn color(self) -> crate::vga_buffer::ColorCode {
+		let theme = crate::vga_buffer::theme();
+		match self {
+			Level::Error => theme.error,
+			Level::Warn => theme.warning,
+			Level::Info => theme.text,
+			Level::Debug | Level::Trace => theme.muted,
+		}
+	}
+}
+
+impl fmt::Display for Level {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.pad(self.name())  // so `{:5}` lines them up
+	}
+}
+
+
+// Filtering
+
+const MAX_RULES: usize = 16;
+// a rule's module is copied in here, the filter is set before there's a heap
+const MODULE_LEN: usize = 64;
+
+// the most verbose level that gets through, 0 lets nothing through
+type MaxLevel = u8;
+const OFF: MaxLevel = 0;
+
+#[derive(Clone, Copy)]
+struct Rule {
+	module: [u8; MODULE_LEN],
+	len: usize,
+	max: MaxLevel,
+}
+
+impl Rule {
+	const fn none() -> Rule {
+		Rule { module: [0; MODULE_LEN], len: 0, max: OFF }
+	}
+
+	fn new(module: &str, max: MaxLevel) -> Option<Rule> {
+		let mut rule = Rule { len: module.len(), max, ..Rule::none() };
+		rule.module.get_mut(..module.len())?.copy_from_slice(module.as_bytes());
+		Some(rule)
+	}
+
+	fn module(&self) -> &str {
+		// copied from a whole `str`
+		core::str::from_utf8(&self.module[..self.len]).unwrap_or("")
+	}
+}
+
+struct Filter {
+	default: MaxLevel,
+	rules: [Rule; MAX_RULES],
+	rule_count: usize,
+}
+
+impl Filter {
+	const fn new() -> Filter {
+		Filter {
+			default: Level::Info as MaxLevel,
+			rules: [Rule::none(); MAX_RULES],
+			rule_count: 0,
+		}
+	}
+
+	fn max_level(&self, module: &str) -> MaxLevel {
+		// the longest matching module prefix wins
+		let mut best: Option<(&str, MaxLevel)> = None;
+		for rule in &self.rules[..self.rule_count] {
+			let (prefix, max) = (rule.module(), rule.max);
+			let matches = match module.strip_prefix(prefix) {
+				Some(rest) => rest.is_empty() || rest.starts_with("::"),
+				None => false,
+			};
+			if matches && best.is_none_or(|(best, _)| prefix.len() > best.len()) {
+				best = Some((prefix, max));
+			}
+		}
+
+		match best {
+			Some((_, max)) => max,
+			None => self.default,
+		}
+	}
+}
+
+static FILTER: Mutex<Filter> = Mutex::new(Filter::new());
+
+/// The part of a filter spec that couldn't be understood.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError<'a>(pub &'a str);
+
+/// Sets which messages get through, from a spec like
+/// `"info,text_os::task::keyboard=warn,text_os::allocator=off"`.
+///
+/// A bare level is the default for everything, `module=level` applies to
+/// that module and everything under it. Nothing changes if the spec is bad,
+/// or a module is longer than 64 bytes.
+pub fn set_filter(spec: &str) -> Result<(), ParseError<'_>> {
+	fn parse_level(name: &str) -> Option<MaxLevel> {
+		match name {
+			"off" => Some(OFF),
+			name => Level::from_name(name).map(|level| level as MaxLevel),
+		}
+	}
+
+	let mut filter = Filter::new();
+
+	for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+		let mut parts = directive.splitn(2, '=');
+		let first = parts.next().unwrap_or("");
+		match parts.next() {
+			None => {
+				filter.default = parse_level(first).ok_or(ParseError(directive))?;
+			}
+			Some(level) => {
+				if filter.rule_count == MAX_RULES {
+					return Err(ParseError(directive));
+				}
+				let max = parse_level(level.trim()).ok_or(ParseError(directive))?;
+				filter.rules[filter.rule_count] = Rule::new(first.trim(), max).ok_or(ParseError(directive))?;
+				filter.rule_count += 1;
+			}
+		}
+	}
+
+	use x86_64::instructions::interrupts;
+	interrupts::without_interrupts(|| *FILTER.lock() = filter);
+	Ok(())
+}
+
+pub fn enabled(level: Level, module: &str) -> bool {
+	use x86_64::instructions::interrupts;
+	let max = interrupts::without_interrupts(|| FILTER.lock().max_level(module));
+	level as MaxLevel <= max
+}
+
+
+// The ring buffer
+
+pub const LOG_RECORDS: usize = 128;
+/// Longer messages are cut off in the ring buffer, not on the console.
+pub const MESSAGE_LEN: usize = 160;
+
+#[derive(Clone, Copy)]
+struct Slot {
+	sequence: u64,
+	level: Level,
+	ticks: u64,
+	module: &'static str,
+	message: [u8; MESSAGE_LEN],
+	len: usize,
+}
+
+struct Ring {
+	slots: [Slot; LOG_RECORDS],
+	next_sequence: u64,  // the sequence number of the next record, starts at 1
+}
+
+static RING: Mutex<Ring> = Mutex::new(Ring {
+	slots: [const {
+		Slot {
+			sequence: 0,
+			level: Level::Trace,
+			ticks: 0,
+			module: "",
+			message: [0; MESSAGE_LEN],
+			len: 0,
+		}
+	}; LOG_RECORDS],
+	next_sequence: 1,
+});
+
+/// A message as `dmesg` hands it out.
+#[derive(Debug, Clone, Copy)]
+pub struct Record<'a> {
+	/// counts up from 1 and never repeats, even when old records are overwritten
+	pub sequence: u64,
+	pub level: Level,
+	pub ticks: u64,
+	pub module: &'static str,
+	pub message: &'a str,
+}
+
+impl fmt::Display for Record<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "[{:>8}] {:5} {}: {}", self.ticks, self.level, self.module, self.message)
+	}
+}
+
+// fills a fixed message buffer, cutting it off at a character boundary
+struct MessageWriter<'a> {
+	buffer: &'a mut [u8],
+	len: &'a mut usize,
+}
+
+impl fmt::Write for MessageWriter<'_> {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		let start = *self.len;
+		let mut len = s.len().min(self.buffer.len() - start);
+		while !s.is_char_boundary(len) {
+			len -= 1;
+		}
+		self.buffer[start..start + len].copy_from_slice(&s.as_bytes()[..len]);
+		*self.len += len;
+		Ok(())
+	}
+}
+
+fn record(level: Level, module: &'static str, ticks: u64, args: fmt::Arguments) {
+	use fmt::Write;
+	use x86_64::instructions::interrupts;
+
+	interrupts::without_interrupts(|| {
+		let mut ring = RING.lock();
+		let sequence = ring.next_sequence;
+		ring.next_sequence += 1;
+
+		let slot = &mut ring.slots[(sequence % LOG_RECORDS as u64) as usize];
+		*slot = Slot {
+			sequence,
+			level,
+			ticks,
+			module,
+			len: 0,
+			..*slot
+		};
+		// the writer itself never fails
+		let _ = MessageWriter {
+			buffer: &mut slot.message,
+			len: &mut slot.len,
+		}.write_fmt(args);
+	});
+}
+
+/// Calls `f` with every record newer than `after`, oldest first.
+/// Pass 0 to see everything that's still in the ring.
+///
+/// `f` runs with the ring locked, so it must not log anything itself.
+pub fn dmesg_since(after: u64, mut f: impl FnMut(&Record)) {
+	use x86_64::instructions::interrupts;
+
+	interrupts::without_interrupts(|| {
+		let ring = RING.lock();
+		let oldest = ring.next_sequence.saturating_sub(LOG_RECORDS as u64).max(1);
+		for sequence in (after + 1).max(oldest)..ring.next_sequence {
+			let slot = &ring.slots[(sequence % LOG_RECORDS as u64) as usize];
+			let record = Record {
+				sequence: slot.sequence,
+				level: slot.level,
+				ticks: slot.ticks,
+				module: slot.module,
+				// MessageWriter only ever cuts at character boundaries
+				message: core::str::from_utf8(&slot.message[..slot.len]).unwrap_or(""),
+			};
+			f(&record);
+		}
+	});
+}
+
+pub fn dmesg(f: impl FnMut(&Record)) {
+	dmesg_since(0, f);
+}
+
+/// Prints the whole ring buffer.
+pub fn print_dmesg() {
+	dmesg(|record| crate::println!("{}", record));
+}
+
+
+// Logging
+
+#[doc(hidden)]
+pub fn _log(level: Level, module: &'static str, args: fmt::Arguments) {
+	if !enabled(level, module) {
+		return;
+	}
+
+	emit(level, module, crate::interrupts::ticks(), args);
+}
+
+// the filter has been checked by now
+fn emit(level: Level, module: &'static str, ticks: u64, args: fmt::Arguments) {
+	use crate::vga_buffer::{CONSOLES, KERNEL_CONSOLE};
+	use x86_64::instructions::interrupts;
+
+	record(level, module, ticks, args);
+	// back to the colour from before, which may be a caller's `push_color`
+	let previous = interrupts::without_interrupts(|| CONSOLES[KERNEL_CONSOLE].lock().color());
+	crate::println!(
+		"{}[{:>8}] {:5} {}: {}{}",
+		level.color().ansi(), ticks, level, module, args, previous.ansi()
+	);
+}
+
+#[macro_export]
+macro_rules! log {
+	($level:expr, $($arg:tt)*) => (
+		$crate::log::_log($level, module_path!(), format_args!($($arg)*))
+	);
+}
+
+#[macro_export]
+macro_rules! error {
+	($($arg:tt)*) => ($crate::log!($crate::log::Level::Error, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! warn {
+	($($arg:tt)*) => ($crate::log!($crate::log::Level::Warn, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! info {
+	($($arg:tt)*) => ($crate::log!($crate::log::Level::Info, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! debug {
+	($($arg:tt)*) => ($crate::log!($crate::log::Level::Debug, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! trace {
+	($($arg:tt)*) => ($crate::log!($crate::log::Level::Trace, $($arg)*));
+}
+
+
+#[test_case]
+fn test_filter_rules() {
+	set_filter("warn, text_os::task=debug, text_os::task::keyboard=off")
+		.expect("filter should parse");
+
+	assert!(enabled(Level::Warn, "text_os::memory"));
+	assert!(!enabled(Level::Info, "text_os::memory"));
+	assert!(enabled(Level::Debug, "text_os::task::better_executor"));
+	assert!(!enabled(Level::Trace, "text_os::task"));
+	assert!(!enabled(Level::Error, "text_os::task::keyboard"));
+	// a prefix has to end at a module boundary
+	assert!(!enabled(Level::Debug, "text_os::tasks"));
+
+	assert_eq!(set_filter("info,text_os=loud"), Err(ParseError("text_os=loud")));
+	assert!(!enabled(Level::Info, "text_os::memory"));  // left as it was
+
+	// from a string that's gone once the filter is set
+	let spec = alloc::format!("info,{}=off", "text_os::memory");
+	set_filter(&spec).expect("filter should parse");
+	drop(spec);
+	assert!(!enabled(Level::Error, "text_os::memory"));
+
+	set_filter("info").expect("filter should parse");
+}
+
+#[test_case]
+fn test_log_keeps_color() {
+	use crate::vga_buffer::{Color, ColorCode, CONSOLES, KERNEL_CONSOLE};
+	use x86_64::instructions::interrupts;
+
+	let scoped = ColorCode::new(Color::Yellow, Color::Blue);
+	interrupts::without_interrupts(|| CONSOLES[KERNEL_CONSOLE].lock().push_color(scoped));
+	crate::info!("in a colour scope");
+	interrupts::without_interrupts(|| {
+		let mut writer = CONSOLES[KERNEL_CONSOLE].lock();
+		assert_eq!(writer.color(), scoped);
+		writer.pop_color();
+	});
+}
+
+#[test_case]
+fn test_dmesg_ring() {
+	let mut last = 0;
+	dmesg(|record| last = record.sequence);
+
+	crate::info!("dmesg test {}", 1);
+	crate::trace!("filtered out");
+	let mut seen = 0;
+	dmesg_since(last, |record| {
+		assert_eq!(record.message, "dmesg test 1");
+		assert_eq!(record.level, Level::Info);
+		assert_eq!(record.module, module_path!());
+		seen += 1;
+	});
+	assert_eq!(seen, 1);
+
+	// a full ring drops the oldest records
+	for i in 0..LOG_RECORDS {
+		record(Level::Debug, module_path!(), 0, format_args!("filler {}", i));
+	}
+	let mut oldest = None;
+	dmesg(|record| { oldest.get_or_insert(record.sequence); });
+	assert_eq!(oldest, Some(last + 2));
+}
@@ -0,0 +1,198 @@
+//! Logging from interrupt handlers.
+//!
+//! A handler can't take any of the console locks: the code it interrupted
+//! might be holding one. `irq_log!` formats the message into one of a fixed
+//! set of slots instead, claimed with a compare-exchange, and the `drain`
+//! task hands it to the logger later. Nothing here locks or allocates.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::sync::atomic::{AtomicU8, AtomicU64, AtomicUsize, Ordering};
+use super::{Level, MessageWriter, MESSAGE_LEN};
+
+const IRQ_SLOTS: usize = 32;
+
+// slot states
+const EMPTY: u8 = 0;
+const WRITING: u8 = 1;
+const READY: u8 = 2;
+
+/// A message waiting to be logged.
+#[derive(Clone, Copy)]
+pub struct Message {
+	sequence: u64,
+	pub level: Level,
+	pub module: &'static str,
+	pub ticks: u64,
+	text: [u8; MESSAGE_LEN],
+	len: usize,
+}
+
+impl Message {
+	pub fn text(&self) -> &str {
+		// MessageWriter only ever cuts at character boundaries
+		core::str::from_utf8(&self.text[..self.len]).unwrap_or("")
+	}
+}
+
+struct Slot {
+	state: AtomicU8,
+	// only touched by whoever moved `state` away from EMPTY
+	message: UnsafeCell<Message>,
+}
+
+unsafe impl Sync for Slot {}
+
+static SLOTS: [Slot; IRQ_SLOTS] = [const {
+	Slot {
+		state: AtomicU8::new(EMPTY),
+		message: UnsafeCell::new(Message {
+			sequence: 0,
+			level: Level::Trace,
+			module: "",
+			ticks: 0,
+			text: [0; MESSAGE_LEN],
+			len: 0,
+		}),
+	}
+}; IRQ_SLOTS];
+static NEXT_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+use futures_util::task::AtomicWaker;
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Queues a message for the logger. Safe to call from any interrupt handler.
+/// When every slot is taken the message is dropped and counted.
+#[doc(hidden)]
+pub fn _irq_log(level: Level, module: &'static str, args: fmt::Arguments) {
+	use fmt::Write;
+
+	let sequence = NEXT_SEQUENCE.fetch_add(1, Ordering::Relaxed);
+	let start = (sequence % IRQ_SLOTS as u64) as usize;
+
+	for i in 0..IRQ_SLOTS {
+		let slot = &SLOTS[(start + i) % IRQ_SLOTS];
+		if slot.state.compare_exchange(EMPTY, WRITING, Ordering::Acquire, Ordering::Relaxed).is_err() {
+			continue;
+		}
+
+		let message = unsafe { &mut *slot.message.get() };
+		message.sequence = sequence;
+		message.level = level;
+		message.module = module;
+		message.ticks = crate::interrupts::ticks();
+		message.len = 0;
+		let _ = MessageWriter {
+			buffer: &mut message.text,
+			len: &mut message.len,
+		}.write_fmt(args);
+
+		slot.state.store(READY, Ordering::Release);
+		WAKER.wake();
+		return;
+	}
+
+	DROPPED.fetch_add(1, Ordering::Relaxed);
+	WAKER.wake();
+}
+
+#[macro_export]
+macro_rules! irq_log {
+	($level:expr, $($arg:tt)*) => (
+		$crate::log::irq::_irq_log($level, module_path!(), format_args!($($arg)*))
+	);
+}
+
+// Takes the oldest finished message out of its slot.
+// There must only ever be one of these running at a time.
+fn pop_oldest() -> Option<Message> {
+	let slot = SLOTS.iter()
+		.filter(|slot| slot.state.load(Ordering::Acquire) == READY)
+		.min_by_key(|slot| unsafe { (*slot.message.get()).sequence })?;
+
+	let message = unsafe { *slot.message.get() };
+	slot.state.store(EMPTY, Ordering::Release);
+	Some(message)
+}
+
+pub struct IrqMessageStream {
+	_private: ()
+}
+
+use core::sync::atomic::AtomicBool;
+static STREAM_TAKEN: AtomicBool = AtomicBool::new(false);
+
+impl IrqMessageStream {
+	/// There's only one consumer, so this panics the second time.
+	pub fn new() -> Self {
+		if STREAM_TAKEN.swap(true, Ordering::Relaxed) {
+			panic!("IrqMessageStream must be created only once");
+		}
+		IrqMessageStream{_private: ()}
+	}
+}
+
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures_util::Stream;
+impl Stream for IrqMessageStream {
+	type Item = Message;
+
+	fn poll_next(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Option<Message>> {
+		if let Some(message) = pop_oldest() {
+			return Poll::Ready(Some(message));
+		}
+
+		WAKER.register(ctx.waker());
+
+		match pop_oldest() {
+			Some(message) => {
+				WAKER.take();
+				Poll::Ready(Some(message))
+			}
+			None => Poll::Pending,
+		}
+	}
+}
+
+/// Passes messages from interrupt handlers on to the logger. Spawn it once.
+pub async fn drain() {
+	use futures_util::StreamExt;
+
+	let mut stream = IrqMessageStream::new();
+	while let Some(message) = stream.next().await {
+		if super::enabled(message.level, message.module) {
+			super::emit(message.level, message.module, message.ticks, format_args!("{}", message.text()));
+		}
+
+		let dropped = DROPPED.swap(0, Ordering::Relaxed);
+		if dropped > 0 {
+			crate::warn!("{} messages from interrupt handlers were dropped", dropped);
+		}
+	}
+}
+
+
+#[test_case]
+fn test_irq_log_slots() {
+	// whatever earlier tests left behind
+	while pop_oldest().is_some() {}
+
+	_irq_log(Level::Warn, "first", format_args!("scancode {:#04x}", 0x1c));
+	_irq_log(Level::Error, "second", format_args!("second"));
+
+	let message = pop_oldest().expect("the message should be queued");
+	assert_eq!((message.level, message.module, message.text()), (Level::Warn, "first", "scancode 0x1c"));
+	assert_eq!(pop_oldest().map(|message| message.module), Some("second"));
+	assert!(pop_oldest().is_none());
+
+	// overflowing the slots drops messages instead of blocking
+	let dropped = DROPPED.swap(0, Ordering::Relaxed);
+	for i in 0..IRQ_SLOTS + 2 {
+		_irq_log(Level::Info, "flood", format_args!("{}", i));
+	}
+	assert_eq!(DROPPED.swap(dropped, Ordering::Relaxed), 2);
+	assert_eq!(pop_oldest().map(|message| message.text().len()), Some(1));  // "0" went first
+	while pop_oldest().is_some() {}
+}
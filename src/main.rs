@@ -5,23 +5,44 @@
 #![test_runner(text_os::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
-mod vga_buffer;
-mod serial;
-
 use core::panic::PanicInfo;
-// use text_os::println;
+use text_os::{println, info, debug};
 
 // static HELLO: &[u8] = b"Hello,_World!";  // this is where our string lives
 
 use bootloader::{BootInfo, entry_point};
 use text_os::allocator;
 
+// which log messages make it out, see `text_os::log::set_filter`
+const LOG_FILTER: &str = "info,text_os::task=warn";
+
+// the keyboard layout to start with is a boot parameter, QEMU passes it with
+// `-fw_cfg name=opt/text_os/keyboard_layout,string=de`. See
+// `text_os::task::keyboard::LAYOUTS` for the names.
+const KEYBOARD_LAYOUT: &str = "opt/text_os/keyboard_layout";
+
+// how many lines Shift+PageUp can go back
+const SCROLLBACK_LINES: usize = 200;
+// the other consoles get less, the heap is small
+const CONSOLE_SCROLLBACK_LINES: usize = 25;
+
 // creates a declaration for an entry point function.
 // defines _start here itself
 entry_point!(kernel_main);
 
 // entry point before init of runtime
 fn kernel_main(boot_info: &'static BootInfo) -> ! {  // '!' never returns
+    // mirror everything to the serial log, so headless runs can follow along too
+    text_os::console::set_enabled(text_os::console::SERIAL, true);
+    text_os::log::set_filter(LOG_FILTER).expect("bad log filter");
+    let mut layout = [0; 16];
+    if let Some(len) = text_os::fw_cfg::file(KEYBOARD_LAYOUT, &mut layout) {
+        let layout = core::str::from_utf8(&layout[..len]).unwrap_or("").trim_end_matches('\0').trim();
+        if text_os::task::keyboard::set_layout(layout).is_err() {
+            text_os::warn!("there's no keyboard layout called {:?}, keeping us", layout);
+        }
+    }
+
     println!("Hello, {}", "World!");
 
     text_os::init();  // calls all the init methods
@@ -42,9 +63,9 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {  // '!' never returns
 
     let ptr = 0x22259b as *mut u32;
     unsafe { let _x = core::ptr::read_unaligned(ptr); }
-    println!("Read from the instruction pointer worked!");
+    debug!("Read from the instruction pointer worked!");
 
-    println!(
+    info!(
         "We're mapping physical memory to virtual memory using this offset: {:x}",
         boot_info.physical_memory_offset
     );
@@ -57,6 +78,14 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {  // '!' never returns
     let physical_memory_offset =
         VirtAddr::new(boot_info.physical_memory_offset);
     let mut mapper = unsafe { memory::init(physical_memory_offset) };
+    // the BIOS wrote down where the COM ports are
+    text_os::serial::init(physical_memory_offset);
+    text_os::vga_buffer::map_vga_memory(physical_memory_offset);
+    {
+        use text_os::vga_buffer::{self, font, Font};
+        let font = Font::from_psf(font::DEFAULT_PSF).expect("the built-in font is broken");
+        vga_buffer::set_font(font).expect("the VGA memory was just mapped");
+    }
     // let mut frame_allocator = memory::EmptyFrameAllocator;
     let mut frame_allocator = unsafe {
         memory::BootInfoFrameAllocator::init(&boot_info.memory_map)
@@ -78,7 +107,7 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {  // '!' never returns
 
     // (physical frame, flags)
     let (level_4_page_table, _) = Cr3::read();
-    println!("Physical Address of the current page table: {:?}",
+    info!("Physical Address of the current page table: {:?}",
         level_4_page_table.start_address());
 
     let addresses = [
@@ -100,7 +129,7 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {  // '!' never returns
     for &address in &addresses {  // also what's this for loop syntax
         let virt = VirtAddr::new(address);
         let phys = mapper.translate_addr(virt);
-        println!("{:?} -> {:?}", virt, phys);
+        debug!("{:?} -> {:?}", virt, phys);
     }
 
 
@@ -108,28 +137,45 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {  // '!' never returns
 
     allocator::init_heap(&mut mapper, &mut frame_allocator)
         .expect("Heap initialisation failed.");
+
+    // the serial sink stops waiting for the UART, the transmit interrupt sends for it
+    text_os::serial::buffer_output();
+
+    // the scrollback lives on the heap, so it can only start now
+    {
+        use text_os::vga_buffer::{CONSOLES, KERNEL_CONSOLE};
+        for (i, console) in CONSOLES.iter().enumerate() {
+            let lines = match i {
+                KERNEL_CONSOLE => SCROLLBACK_LINES,
+                _ => CONSOLE_SCROLLBACK_LINES,
+            };
+            x86_64::instructions::interrupts::without_interrupts(|| {
+                console.lock().enable_scrollback(lines);
+            });
+        }
+    }
     extern crate alloc;
     use alloc::{boxed::Box, vec::Vec, rc::Rc};
     use alloc::vec;
 
     // Box
     let heap_value = Box::new(42);
-    println!("Value at the heap: {:p}.", heap_value);
+    debug!("Value at the heap: {:p}.", heap_value);
 
     // Vec
     let mut vec = Vec::new();
     for i in 0..500 {
         vec.push(i);
     }
-    println!("vec is at {:p}", vec.as_slice());
+    debug!("vec is at {:p}", vec.as_slice());
 
     // Rc
     let ref_counted = Rc::new(vec![1, 2, 3]);
     let cloned_ref  = ref_counted.clone();
-    println!("Before dropping, reference count = {}", Rc::strong_count(&cloned_ref));
+    debug!("Before dropping, reference count = {}", Rc::strong_count(&cloned_ref));
     core::mem::drop(ref_counted);
-    println!("After dropping,  reference count = {}", Rc::strong_count(&cloned_ref));
-    println!("rc is at {:p}", cloned_ref);
+    debug!("After dropping,  reference count = {}", Rc::strong_count(&cloned_ref));
+    debug!("rc is at {:p}", cloned_ref);
 
     // Async Stuff
 
@@ -139,12 +185,26 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {  // '!' never returns
     use text_os::task::{Task, better_executor::Executor};
     let mut executor = Executor::new();
 
-    println!("Will sleep when there's nothing to do.");
+    debug!("Will sleep when there's nothing to do.");
 
     executor.spawn(Task::new(another_example()));
 
-    use text_os::task::keyboard::print_keypresses;
+    // messages from interrupt handlers get printed from here
+    executor.spawn(Task::new(text_os::log::irq::drain()));
+
+    use text_os::task::keyboard::{decode_keys, print_keypresses};
+    executor.spawn(Task::new(decode_keys()));
     executor.spawn(Task::new(print_keypresses()));
+    executor.spawn(Task::new(text_os::task::mouse::track_pointer()));
+    // a terminal on the serial console types just like the keyboard
+    executor.spawn(Task::new(text_os::task::serial::decode_serial()));
+
+    executor.spawn(Task::new(text_os::status_bar::run()));
+
+    // the shell's commands, subsystems bring their own
+    text_os::shell::register_builtins();
+    text_os::allocator::register_commands();
+    executor.spawn(Task::new(text_os::shell::run()));
 
     executor.run();
 }
@@ -153,9 +213,8 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {  // '!' never returns
 #[panic_handler]
 #[cfg(not(test))]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
-
-    text_os::hlt_loop();
+    // println! could deadlock if we panicked with the console locked
+    text_os::panic_console::panic(info);
 }
 
 // specified because no std
@@ -0,0 +1,282 @@
+//! The last thing the kernel prints.
+//!
+//! A panic can happen while the console or serial locks are held, even
+//! inside `Writer::write_fmt` itself, so nothing here takes a lock. The
+//! screen is painted straight into VGA memory and the log's UART is driven
+//! directly. The serial dump is one `key=value` per line between two
+//! markers, so scripts can pick it apart.
+
+use core::fmt;
+use core::panic::{Location, PanicInfo};
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::structures::idt::InterruptStackFrame;
+
+/// What the CPU looked like when things went wrong.
+#[derive(Debug, Clone, Copy)]
+pub struct Registers {
+	pub rip: Option<u64>,  // a panic only knows its source location
+	pub rsp: u64,
+	pub rbp: u64,
+	pub rflags: u64,
+	pub cs: u64,
+	pub ss: u64,
+	pub cr0: u64,
+	pub cr2: u64,
+	pub cr3: u64,
+	pub cr4: u64,
+}
+
+impl Registers {
+	/// The registers right here, in the panic path.
+	pub fn capture() -> Registers {
+		use core::arch::asm;
+		use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+		use x86_64::registers::rflags;
+		use x86_64::instructions::segmentation::{Segment, CS, SS};
+
+		let (rsp, rbp): (u64, u64);
+		unsafe {
+			asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags));
+			asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags));
+		}
+
+		Registers {
+			rip: None,
+			rsp,
+			rbp,
+			rflags: rflags::read_raw(),
+			cs: u64::from(CS::get_reg().0),
+			ss: u64::from(SS::get_reg().0),
+			cr0: Cr0::read_raw(),
+			cr2: Cr2::read().as_u64(),
+			cr3: Cr3::read().0.start_address().as_u64(),
+			cr4: Cr4::read_raw(),
+		}
+	}
+
+	/// The registers the exception interrupted, as far as the CPU saved them.
+	pub fn from_frame(frame: &InterruptStackFrame) -> Registers {
+		Registers {
+			rip: Some(frame.instruction_pointer.as_u64()),
+			rsp: frame.stack_pointer.as_u64(),
+			rflags: frame.cpu_flags,
+			cs: frame.code_segment,
+			ss: frame.stack_segment,
+			..Registers::capture()
+		}
+	}
+
+	fn fields(&self) -> [(&'static str, u64); 9] {
+		[
+			("rsp", self.rsp), ("rbp", self.rbp), ("rflags", self.rflags),
+			("cs", self.cs), ("ss", self.ss),
+			("cr0", self.cr0), ("cr2", self.cr2), ("cr3", self.cr3), ("cr4", self.cr4),
+		]
+	}
+}
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// For the `#[panic_handler]`.
+pub fn panic(info: &PanicInfo) -> ! {
+	// the location has a line of its own
+	emergency(format_args!("{}", info.message()), info.location(), &Registers::capture())
+}
+
+/// For exceptions there's no coming back from, like double faults.
+pub fn fatal_exception(
+	name: &str, frame: &InterruptStackFrame, details: fmt::Arguments
+) -> ! {
+	emergency(format_args!("{}: {}", name, details), None, &Registers::from_frame(frame))
+}
+
+fn emergency(message: fmt::Arguments, location: Option<&Location>, registers: &Registers) -> ! {
+	x86_64::instructions::interrupts::disable();
+
+	// panicking while we paint the panic screen, the screen is probably the problem
+	if PANICKING.swap(true, Ordering::SeqCst) {
+		if let Some(mut serial) = serial() {
+			let _ = fmt::Write::write_str(&mut serial, "\n---NESTED KERNEL PANIC---\n");
+		}
+		crate::hlt_loop();
+	}
+
+	paint_screen(message, location, registers);
+	dump_to_serial(message, location, registers);
+	crate::hlt_loop();
+}
+
+
+// The screen
+
+const PANIC_COLOR: u8 = 0x4f;  // white on red
+const LABEL_COLOR: u8 = 0x4e;  // yellow on red
+
+// Writes cells straight into VGA memory, no Writer, no lock.
+struct PanicScreen {
+	row: usize,
+	col: usize,
+	color: u8,
+	width: usize,
+	height: usize,
+}
+
+impl PanicScreen {
+	fn clear() -> PanicScreen {
+		use crate::vga_buffer::registers::{Crtc, CRTC_CURSOR_START, CRTC_START_HIGH, CRTC_START_LOW};
+
+		// the panic screen is text, even if an application was drawing
+		crate::vga_buffer::emergency_text_mode();
+
+		// whatever text mode we're in, the atomics are always readable
+		let mode = crate::vga_buffer::text_mode();
+		let mut screen = PanicScreen {
+			row: 0,
+			col: 0,
+			color: PANIC_COLOR,
+			width: mode.width(),
+			height: mode.height(),
+		};
+		for row in 0..screen.height {
+			for col in 0..screen.width {
+				screen.put(row, col, b' ');
+			}
+		}
+
+		// no blinking cursor somewhere in the middle, and the display
+		// back at the start of the memory, where we just painted
+		let mut crtc = Crtc::new();
+		unsafe {
+			crtc.write(CRTC_START_HIGH, 0);
+			crtc.write(CRTC_START_LOW, 0);
+			let start = crtc.read(CRTC_CURSOR_START);
+			crtc.write(CRTC_CURSOR_START, start | 0x20);
+		}
+		screen
+	}
+
+	fn put(&mut self, row: usize, col: usize, glyph: u8) {
+		let vga = crate::vga_buffer::vga_memory();
+		let cell = (u16::from(self.color) << 8) | u16::from(glyph);
+		unsafe { vga.add(row * self.width + col).write_volatile(cell) };
+	}
+}
+
+impl fmt::Write for PanicScreen {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		use crate::vga_buffer::cp437;
+
+		for c in s.chars() {
+			if c == '\n' || self.col == self.width {
+				self.row += 1;
+				self.col = 0;
+			}
+			// whatever doesn't fit is in the serial dump
+			if self.row == self.height {
+				return Ok(());
+			}
+			if c == '\n' {
+				continue;
+			}
+
+			let glyph = cp437::from_char(c).unwrap_or(cp437::REPLACEMENT);
+			self.put(self.row, self.col, glyph);
+			self.col += 1;
+		}
+		Ok(())
+	}
+}
+
+fn paint_screen(message: fmt::Arguments, location: Option<&Location>, registers: &Registers) {
+	use fmt::Write;
+
+	let mut screen = PanicScreen::clear();
+	screen.color = LABEL_COLOR;
+	let _ = writeln!(screen, " KERNEL PANIC\n");
+	screen.color = PANIC_COLOR;
+	let _ = writeln!(screen, "{}", message);
+	if let Some(location) = location {
+		let _ = writeln!(screen, "at {}", location);
+	}
+
+	// registers go at the bottom, three to a line
+	screen.row = screen.height - 5;
+	screen.col = 0;
+	screen.color = LABEL_COLOR;
+	let _ = writeln!(screen, " Registers");
+	screen.color = PANIC_COLOR;
+	if let Some(rip) = registers.rip {
+		let _ = write!(screen, " rip    {:#018x}", rip);
+	}
+	for (i, (name, value)) in registers.fields().iter().enumerate() {
+		if (i + 1) % 3 == 0 {
+			let _ = writeln!(screen);
+		}
+		let _ = write!(screen, " {:<6} {:#018x}", name, value);
+	}
+}
+
+
+// The serial dump
+
+// Straight to the log's UART, there's no lock to get stuck on and the
+// interrupts that would empty the queue may never come.
+fn serial() -> Option<crate::serial::Uart> {
+	use crate::serial::{existing, port, Channel};
+
+	// what was queued before the panic comes first
+	crate::serial::flush();
+	// not `uart`, that locks the configs the first time round
+	port(Channel::Log).and_then(existing)
+}
+
+// Escapes newlines and backslashes, so every value fits on its own line.
+struct OneLine<W>(W);
+
+impl<W: fmt::Write> fmt::Write for OneLine<W> {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		for c in s.chars() {
+			match c {
+				'\n' => self.0.write_str("\\n")?,
+				'\\' => self.0.write_str("\\\\")?,
+				c => self.0.write_char(c)?,
+			}
+		}
+		Ok(())
+	}
+}
+
+fn dump_to_serial(message: fmt::Arguments, location: Option<&Location>, registers: &Registers) {
+	use fmt::Write;
+
+	let mut serial = match serial() {
+		Some(serial) => serial,
+		None => return,
+	};
+	let _ = writeln!(serial, "\n---BEGIN KERNEL PANIC---");
+	let _ = write!(serial, "message=");
+	let _ = write!(OneLine(&mut serial), "{}", message);
+	let _ = writeln!(serial);
+	if let Some(location) = location {
+		let _ = writeln!(serial, "location={}", location);
+	}
+	let _ = writeln!(serial, "ticks={}", crate::interrupts::ticks());
+	if let Some(rip) = registers.rip {
+		let _ = writeln!(serial, "rip={:#018x}", rip);
+	}
+	for (name, value) in registers.fields().iter() {
+		let _ = writeln!(serial, "{}={:#018x}", name, value);
+	}
+	let _ = writeln!(serial, "---END KERNEL PANIC---");
+}
+
+
+#[test_case]
+fn test_one_line_escaping() {
+	use alloc::string::String;
+	use fmt::Write;
+
+	let mut text = String::new();
+	write!(OneLine(&mut text), "a\\b\n{}", 42).unwrap();
+	assert_eq!(text, "a\\\\b\\n42");
+}
@@ -0,0 +1,545 @@
+//! The 8042 PS/2 controller and the devices on its two ports.
+//!
+//! `init` tests the controller, finds out what's plugged in and sets the
+//! devices up: the keyboard gets scancode set 2 without the controller's
+//! translation, our repeat rate and the lock LEDs, a mouse gets its wheel
+//! turned on. Until it has run the firmware's setup is all there is.
+//!
+//! Everything here polls the controller with interrupts off, so it's not
+//! for interrupt handlers, they call `handle_interrupt`.
+
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+// controller commands, written to the command port
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const DISABLE_SECOND: u8 = 0xa7;
+const ENABLE_SECOND: u8 = 0xa8;
+const TEST_SECOND: u8 = 0xa9;
+const SELF_TEST: u8 = 0xaa;
+const TEST_FIRST: u8 = 0xab;
+const DISABLE_FIRST: u8 = 0xad;
+const ENABLE_FIRST: u8 = 0xae;
+const WRITE_SECOND: u8 = 0xd4;  // the next data byte goes to the second port
+const PULSE_RESET: u8 = 0xfe;  // the line to the CPU's reset pin
+
+// status register
+const OUTPUT_FULL: u8 = 0x01;
+const INPUT_FULL: u8 = 0x02;
+const FROM_SECOND: u8 = 0x20;
+
+// configuration byte
+const FIRST_IRQ: u8 = 0x01;
+const SECOND_IRQ: u8 = 0x02;
+const SECOND_CLOCK_OFF: u8 = 0x20;
+const TRANSLATION: u8 = 0x40;
+
+// device commands and what devices say back
+const SET_LEDS: u8 = 0xed;
+const SCANCODE_SET: u8 = 0xf0;
+const IDENTIFY: u8 = 0xf2;
+const SET_TYPEMATIC: u8 = 0xf3;
+const SET_SAMPLE_RATE: u8 = 0xf3;  // the same command, for a mouse
+const ENABLE_SCANNING: u8 = 0xf4;  // data reporting, for a mouse
+const DISABLE_SCANNING: u8 = 0xf5;
+const RESET: u8 = 0xff;
+const ACK: u8 = 0xfa;
+const RESEND: u8 = 0xfe;
+const SELF_TEST_PASSED: u8 = 0xaa;
+
+// how many times to look at the status before giving up, a read takes
+// about a microsecond
+const TIMEOUT: u32 = 100_000;
+// a reset can take the device most of a second
+const RESET_TIMEOUT: u32 = 1_000_000;
+// how many bytes of keys already on their way may come before a command's
+// reply, Pause alone is 8
+const STRAY_BYTES: usize = 16;
+
+// the repeat rate `init` sets
+const TYPEMATIC_DELAY_MILLIS: u32 = 500;
+const TYPEMATIC_RATE: u32 = 20;  // repeats a second
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Port {
+	First,
+	Second,
+}
+
+impl Ps2Port {
+	// which port the byte waiting in the controller came from
+	fn from_status(status: u8) -> Ps2Port {
+		match status & FROM_SECOND {
+			0 => Ps2Port::First,
+			_ => Ps2Port::Second,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+	/// The controller or a device didn't answer in time.
+	Timeout,
+	/// The controller's self-test gave this instead of 0x55.
+	SelfTestFailed(u8),
+	/// The port's interface test gave this instead of 0.
+	PortTestFailed(Ps2Port, u8),
+	/// The device didn't take a command, it gave this instead of an ACK.
+	NotAcknowledged(u8),
+	/// `init` hasn't found a controller, or a device on the port.
+	NoDevice,
+}
+
+/// What `init` found on a port, from what it answered to "identify".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+	/// The old AT keyboards don't answer at all.
+	AtKeyboard,
+	Mf2Keyboard,
+	Mouse,
+	ScrollMouse,
+	FiveButtonMouse,
+	Unknown(u8, u8),
+}
+
+impl Device {
+	fn from_id(id: &[u8]) -> Device {
+		match *id {
+			[] => Device::AtKeyboard,
+			[0xab, _] => Device::Mf2Keyboard,
+			[0x00] => Device::Mouse,
+			[0x03] => Device::ScrollMouse,
+			[0x04] => Device::FiveButtonMouse,
+			[first] => Device::Unknown(first, 0),
+			[first, second, ..] => Device::Unknown(first, second),
+		}
+	}
+
+	pub fn is_keyboard(self) -> bool {
+		matches!(self, Device::AtKeyboard | Device::Mf2Keyboard)
+	}
+
+	pub fn is_mouse(self) -> bool {
+		matches!(self, Device::Mouse | Device::ScrollMouse | Device::FiveButtonMouse)
+	}
+}
+
+/// The keyboard LEDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Leds {
+	pub scroll_lock: bool,
+	pub num_lock: bool,
+	pub caps_lock: bool,
+}
+
+impl Leds {
+	fn bits(self) -> u8 {
+		self.scroll_lock as u8 | (self.num_lock as u8) << 1 | (self.caps_lock as u8) << 2
+	}
+}
+
+struct Controller {
+	data: Port<u8>,
+	status_command: Port<u8>,  // the status when read, commands when written
+}
+
+impl Controller {
+	const fn new() -> Controller {
+		Controller {
+			data: Port::new(0x60),
+			status_command: Port::new(0x64),
+		}
+	}
+
+	fn status(&mut self) -> u8 {
+		unsafe { self.status_command.read() }
+	}
+
+	fn wait_for(&mut self, ready: impl Fn(u8) -> bool, timeout: u32) -> Result<u8, Error> {
+		for _ in 0..timeout {
+			let status = self.status();
+			if ready(status) {
+				return Ok(status);
+			}
+		}
+		Err(Error::Timeout)
+	}
+
+	fn command(&mut self, command: u8) -> Result<(), Error> {
+		self.wait_for(|status| status & INPUT_FULL == 0, TIMEOUT)?;
+		unsafe { self.status_command.write(command) };
+		Ok(())
+	}
+
+	fn write(&mut self, byte: u8) -> Result<(), Error> {
+		self.wait_for(|status| status & INPUT_FULL == 0, TIMEOUT)?;
+		unsafe { self.data.write(byte) };
+		Ok(())
+	}
+
+	// the next byte from the controller and the port it came from
+	fn read(&mut self, timeout: u32) -> Result<(u8, Ps2Port), Error> {
+		let status = self.wait_for(|status| status & OUTPUT_FULL != 0, timeout)?;
+		Ok((unsafe { self.data.read() }, Ps2Port::from_status(status)))
+	}
+
+	fn command_with_reply(&mut self, command: u8) -> Result<u8, Error> {
+		self.command(command)?;
+		self.read(TIMEOUT).map(|(byte, _)| byte)
+	}
+
+	fn config(&mut self) -> Result<u8, Error> {
+		self.command_with_reply(READ_CONFIG)
+	}
+
+	fn set_config(&mut self, config: u8) -> Result<(), Error> {
+		self.command(WRITE_CONFIG)?;
+		self.write(config)
+	}
+
+	fn flush(&mut self) {
+		while self.status() & OUTPUT_FULL != 0 {
+			unsafe { self.data.read() };
+		}
+	}
+
+	// the next byte from `port`, anything the other port says meanwhile is
+	// passed on like the interrupt handler would
+	fn read_from(&mut self, port: Ps2Port, timeout: u32) -> Result<u8, Error> {
+		loop {
+			match self.read(timeout)? {
+				(byte, from) if from == port => return Ok(byte),
+				(byte, from) => dispatch(byte, from),
+			}
+		}
+	}
+
+	fn send(&mut self, port: Ps2Port, byte: u8) -> Result<(), Error> {
+		if port == Ps2Port::Second {
+			self.command(WRITE_SECOND)?;
+		}
+		self.write(byte)
+	}
+
+	// sends a byte to a device and waits for the ACK, resending if it asks
+	fn device_command(&mut self, port: Ps2Port, byte: u8) -> Result<(), Error> {
+		let mut reply = RESEND;
+		for _ in 0..3 {
+			self.send(port, byte)?;
+			reply = self.read_reply(port)?;
+			if reply != RESEND {
+				break;
+			}
+		}
+		match reply {
+			ACK => Ok(()),
+			other => Err(Error::NotAcknowledged(other)),
+		}
+	}
+
+	// the ACK or RESEND for a command. A key pressed just before may still
+	// be on its way, that's passed on like the interrupt handler would.
+	// Whatever comes last if there's no reply in `STRAY_BYTES`.
+	fn read_reply(&mut self, port: Ps2Port) -> Result<u8, Error> {
+		let mut byte = self.read_from(port, TIMEOUT)?;
+		for _ in 1..STRAY_BYTES {
+			if byte == ACK || byte == RESEND {
+				break;
+			}
+			dispatch(byte, port);
+			byte = self.read_from(port, TIMEOUT)?;
+		}
+		Ok(byte)
+	}
+
+	fn reset_device(&mut self, port: Ps2Port) -> Result<(), Error> {
+		self.device_command(port, RESET)?;
+		match self.read_from(port, RESET_TIMEOUT)? {
+			SELF_TEST_PASSED => {}
+			other => return Err(Error::NotAcknowledged(other)),
+		}
+		// mice send their ID after the self-test, the identify asks again
+		let _ = self.read_from(port, TIMEOUT);
+		Ok(())
+	}
+
+	fn identify(&mut self, port: Ps2Port) -> Result<Device, Error> {
+		self.device_command(port, DISABLE_SCANNING)?;
+		self.device_command(port, IDENTIFY)?;
+		let mut id = [0; 2];
+		let mut len = 0;
+		while len < id.len() {
+			match self.read_from(port, TIMEOUT) {
+				Ok(byte) => id[len] = byte,
+				Err(_) => break,
+			}
+			len += 1;
+		}
+		Ok(Device::from_id(&id[..len]))
+	}
+
+	// a mouse with a wheel only admits to it after this knock
+	fn enable_wheel(&mut self, port: Ps2Port) -> Result<Device, Error> {
+		for &rate in &[200, 100, 80] {
+			self.device_command(port, SET_SAMPLE_RATE)?;
+			self.device_command(port, rate)?;
+		}
+		self.identify(port)
+	}
+
+	fn set_scancode_set(&mut self, set: u8) -> Result<(), Error> {
+		self.device_command(Ps2Port::First, SCANCODE_SET)?;
+		self.device_command(Ps2Port::First, set)?;
+		// ask which one it's using now, some keyboards ignore the switch
+		self.device_command(Ps2Port::First, SCANCODE_SET)?;
+		self.device_command(Ps2Port::First, 0)?;
+		match self.read_from(Ps2Port::First, TIMEOUT)? {
+			used if used == set => Ok(()),
+			other => Err(Error::NotAcknowledged(other)),
+		}
+	}
+}
+
+static CONTROLLER: Mutex<Controller> = Mutex::new(Controller::new());
+static PRESENT: AtomicBool = AtomicBool::new(false);
+static DEVICES: Mutex<[Option<Device>; 2]> = Mutex::new([None; 2]);
+// translation turns set 2 into set 1, so that's what firmware leaves us with
+static SCANCODES: AtomicU8 = AtomicU8::new(1);
+
+/// Sets the controller and its devices up, see the top of this file.
+/// Call with interrupts off, before the keyboard's interrupt gets going.
+/// If it fails the keyboard is left typing the way the firmware had it.
+pub fn init() -> Result<(), Error> {
+	let mut controller = CONTROLLER.lock();
+	let controller = &mut *controller;
+
+	// keep the devices quiet while we talk to the controller
+	controller.command(DISABLE_FIRST)?;
+	controller.command(DISABLE_SECOND)?;
+	controller.flush();
+
+	let config = controller.config()? & !(FIRST_IRQ | SECOND_IRQ | TRANSLATION);
+	match set_up(controller, config) {
+		Ok(config) => controller.set_config(config),
+		Err(err) => {
+			// set 1 is translated from set 2 unless the keyboard got to use set 2
+			let translation = if scancode_set() == 2 { 0 } else { TRANSLATION };
+			let _ = controller.set_config(config | FIRST_IRQ | translation);
+			let _ = controller.command(ENABLE_FIRST);
+			let _ = controller.device_command(Ps2Port::First, ENABLE_SCANNING);
+			Err(err)
+		}
+	}
+}
+
+// everything `init` does between quieting the devices and the final
+// configuration byte, which it gives back
+fn set_up(controller: &mut Controller, config: u8) -> Result<u8, Error> {
+	controller.set_config(config)?;
+
+	match controller.command_with_reply(SELF_TEST)? {
+		0x55 => {}
+		other => return Err(Error::SelfTestFailed(other)),
+	}
+	// the self-test resets some controllers
+	controller.set_config(config)?;
+
+	// the second clock only comes on if there is a second port
+	controller.command(ENABLE_SECOND)?;
+	let dual = controller.config()? & SECOND_CLOCK_OFF == 0;
+	controller.command(DISABLE_SECOND)?;
+
+	let mut ports = [Some(Ps2Port::First), None];
+	if dual {
+		ports[1] = Some(Ps2Port::Second);
+	}
+	for &(port, test) in &[(Ps2Port::First, TEST_FIRST), (Ps2Port::Second, TEST_SECOND)] {
+		if !dual && port == Ps2Port::Second {
+			continue;
+		}
+		match controller.command_with_reply(test)? {
+			0 => {}
+			other => {
+				crate::warn!("{}", Error::PortTestFailed(port, other));
+				ports[port as usize] = None;
+			}
+		}
+	}
+	PRESENT.store(true, Ordering::Relaxed);
+
+	let mut config = config;
+	let mut devices = [None; 2];
+	for port in ports.iter().flatten().copied() {
+		let (enable, irq) = match port {
+			Ps2Port::First => (ENABLE_FIRST, FIRST_IRQ),
+			Ps2Port::Second => (ENABLE_SECOND, SECOND_IRQ),
+		};
+		controller.command(enable)?;
+		let device = controller.reset_device(port)
+			.and_then(|()| controller.identify(port))
+			.and_then(|device| match device.is_mouse() {
+				true => controller.enable_wheel(port),
+				false => Ok(device),
+			});
+		match device {
+			Ok(device) => {
+				crate::debug!("{:?} PS/2 port: {:?}", port, device);
+				devices[port as usize] = Some(device);
+				config |= irq;
+			}
+			// an empty port times out, that's fine
+			Err(Error::Timeout) => {}
+			Err(err) => crate::warn!("the {:?} PS/2 port doesn't work: {}", port, err),
+		}
+	}
+
+	if devices[0].is_some_and(Device::is_keyboard) {
+		let keyboard = Ps2Port::First;
+		match controller.set_scancode_set(2) {
+			Ok(()) => SCANCODES.store(2, Ordering::Relaxed),
+			Err(err) => {
+				// the keyboard is most likely still on set 2, let the
+				// controller make set 1 out of it like the firmware did
+				crate::warn!("the keyboard won't switch to scancode set 2: {}", err);
+				config |= TRANSLATION;
+			}
+		}
+		// the keyboard types just as well at its own rate
+		let typematic = controller.device_command(keyboard, SET_TYPEMATIC)
+			.and_then(|()| {
+				let byte = typematic_byte(TYPEMATIC_DELAY_MILLIS, TYPEMATIC_RATE);
+				controller.device_command(keyboard, byte)
+			});
+		if let Err(err) = typematic {
+			crate::warn!("the keyboard won't take our repeat rate: {}", err);
+		}
+		controller.device_command(keyboard, ENABLE_SCANNING)?;
+	}
+	if devices[1].is_some_and(Device::is_mouse) {
+		controller.device_command(Ps2Port::Second, ENABLE_SCANNING)?;
+	}
+
+	*DEVICES.lock() = devices;
+	Ok(config)
+}
+
+/// Whatever `init` found on `port`.
+pub fn device(port: Ps2Port) -> Option<Device> {
+	DEVICES.lock()[port as usize]
+}
+
+/// Resets the machine through the controller. Without a controller that
+/// answers, a triple fault does it instead.
+pub fn reboot() -> ! {
+	use x86_64::instructions::{interrupts, tables};
+	use x86_64::structures::DescriptorTablePointer;
+
+	interrupts::disable();
+	// not CONTROLLER, whoever was interrupted may have it locked
+	let mut controller = Controller::new();
+	if controller.command(PULSE_RESET).is_ok() {
+		for _ in 0..RESET_TIMEOUT {
+			core::hint::spin_loop();
+		}
+	}
+
+	// with no IDT the breakpoint can't be handled, and neither can the faults after it
+	let no_idt = DescriptorTablePointer { limit: 0, base: x86_64::VirtAddr::zero() };
+	unsafe { tables::lidt(&no_idt) };
+	interrupts::int3();
+	crate::hlt_loop();
+}
+
+/// The scancode set the keyboard's bytes come in, 1 or 2.
+pub fn scancode_set() -> u8 {
+	SCANCODES.load(Ordering::Relaxed)
+}
+
+/// Passes the byte the controller has on to the keyboard or the mouse, for
+/// the interrupt handlers. Nothing to do if a command already took it.
+pub(crate) fn handle_interrupt() {
+	let (byte, port) = {
+		let mut controller = CONTROLLER.lock();
+		let status = controller.status();
+		if status & OUTPUT_FULL == 0 {
+			return;
+		}
+		(unsafe { controller.data.read() }, Ps2Port::from_status(status))
+	};
+	dispatch(byte, port);
+}
+
+fn dispatch(byte: u8, port: Ps2Port) {
+	match port {
+		Ps2Port::First => crate::task::keyboard::update_scancode_queue(byte),
+		Ps2Port::Second => crate::task::mouse::update_packet_queue(byte),
+	}
+}
+
+// sends a command with one byte of data to the keyboard
+fn keyboard_command(command: u8, data: u8) -> Result<(), Error> {
+	use x86_64::instructions::interrupts;
+
+	if !PRESENT.load(Ordering::Relaxed) || !device(Ps2Port::First).is_some_and(Device::is_keyboard) {
+		return Err(Error::NoDevice);
+	}
+	// the interrupt handler would take the ACK
+	interrupts::without_interrupts(|| {
+		let mut controller = CONTROLLER.lock();
+		controller.device_command(Ps2Port::First, command)?;
+		controller.device_command(Ps2Port::First, data)
+	})
+}
+
+pub fn set_leds(leds: Leds) -> Result<(), Error> {
+	keyboard_command(SET_LEDS, leds.bits())
+}
+
+/// How long a key has to be held before it repeats, and how often it
+/// repeats then. The keyboard only has a few of each, so it gets the
+/// closest ones: 250 to 1000 ms, and 2 to 30 repeats a second.
+pub fn set_typematic(delay_millis: u32, repeats_per_second: u32) -> Result<(), Error> {
+	keyboard_command(SET_TYPEMATIC, typematic_byte(delay_millis, repeats_per_second))
+}
+
+fn typematic_byte(delay_millis: u32, repeats_per_second: u32) -> u8 {
+	let delay = (delay_millis.clamp(250, 1000) + 125) / 250 - 1;
+	// the period is (8 + low 3 bits) * 2^(next 2 bits) * 4.17 ms
+	let period_micros = 1_000_000 / repeats_per_second.max(1);
+	let rate = (0..32u32)
+		.min_by_key(|rate| {
+			let period = (8 + (rate & 7)) * (1 << (rate >> 3)) * 4170;
+			(period as i32 - period_micros as i32).abs()
+		})
+		.unwrap_or(0);
+	(delay << 5 | rate) as u8
+}
+
+impl core::fmt::Display for Error {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		match self {
+			Error::Timeout => write!(f, "no answer in time"),
+			Error::SelfTestFailed(reply) => write!(f, "the controller self-test failed with {:#04x}", reply),
+			Error::PortTestFailed(port, reply) => write!(f, "the {:?} port test failed with {:#04x}", port, reply),
+			Error::NotAcknowledged(reply) => write!(f, "the device said {:#04x} instead of ACK", reply),
+			Error::NoDevice => write!(f, "there's no such device"),
+		}
+	}
+}
+
+
+#[test_case]
+fn test_ps2_keyboard() {
+	// the firmware default and both ends
+	assert_eq!(typematic_byte(500, 11), 0x2b);
+	assert_eq!(typematic_byte(250, 30), 0x00);
+	assert_eq!(typematic_byte(1000, 2), 0x7f);
+
+	// QEMU has a keyboard and a mouse
+	assert_eq!(device(Ps2Port::First), Some(Device::Mf2Keyboard));
+	assert!(device(Ps2Port::Second).is_some_and(Device::is_mouse));
+	assert_eq!(scancode_set(), 2);
+	assert_eq!(set_leds(Leds { num_lock: true, ..Leds::default() }), Ok(()));
+}
@@ -0,0 +1,91 @@
+//! The CMOS real time clock, for the time of day.
+//!
+//! The clock keeps whatever the firmware set it to, usually local time or
+//! UTC, and we can't tell which.
+
+use core::fmt;
+use x86_64::instructions::port::Port;
+
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const STATUS_A: u8 = 0x0a;
+const STATUS_B: u8 = 0x0b;
+
+const UPDATING: u8 = 0x80;  // in status A
+const BINARY: u8 = 0x04;  // in status B, otherwise BCD
+const HOURS_24: u8 = 0x02;  // in status B
+const PM: u8 = 0x80;  // in the hours, with the 12 hour clock
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Time {
+	pub hours: u8,
+	pub minutes: u8,
+	pub seconds: u8,
+}
+
+impl fmt::Display for Time {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{:02}:{:02}:{:02}", self.hours, self.minutes, self.seconds)
+	}
+}
+
+fn read(register: u8) -> u8 {
+	use x86_64::instructions::interrupts;
+
+	let mut index = Port::<u8>::new(0x70);
+	let mut data = Port::<u8>::new(0x71);
+	// the index has to stay put till the data is read
+	interrupts::without_interrupts(|| unsafe {
+		index.write(register);
+		data.read()
+	})
+}
+
+pub fn time() -> Time {
+	// a read during an update can mix the old and the new time,
+	// so we read until two in a row agree
+	let raw = || {
+		while read(STATUS_A) & UPDATING != 0 {}
+		[read(SECONDS), read(MINUTES), read(HOURS)]
+	};
+	let mut time = raw();
+	loop {
+		let again = raw();
+		if again == time {
+			break;
+		}
+		time = again;
+	}
+
+	let [seconds, minutes, hours] = time;
+	let format = read(STATUS_B);
+	let decode = |value: u8| match format & BINARY {
+		0 => (value >> 4) * 10 + (value & 0x0f),
+		_ => value,
+	};
+
+	let mut hour = decode(hours & !PM);
+	if format & HOURS_24 == 0 {
+		// 12 AM is midnight
+		hour %= 12;
+		if hours & PM != 0 {
+			hour += 12;
+		}
+	}
+
+	Time {
+		hours: hour,
+		minutes: decode(minutes),
+		seconds: decode(seconds),
+	}
+}
+
+
+#[test_case]
+fn test_time_is_sane() {
+	let time = time();
+	assert!(time.hours < 24);
+	assert!(time.minutes < 60);
+	assert!(time.seconds < 60);
+}
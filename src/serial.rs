@@ -1,31 +1,34 @@
+//! COM1 to COM4.
+//!
+//! `init` finds the ports in the BIOS data area and sets each one up with its
+//! own `Config`. What goes over which port is up to the channels: the log,
+//! GDB and the console each get a port of their own, see `assign`.
 
-use uart_16550::SerialPort;
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU8, Ordering};
+use crossbeam_queue::ArrayQueue;
+use conquer_once::spin::OnceCell;
 use spin::Mutex;
-use lazy_static::lazy_static;
-
-lazy_static!{
-	// Uart interface. We use a mutex for safety.
-	pub static ref SERIAL1: Mutex<SerialPort> = {
-		// 0x3f8 is the standard address for first interface
-		let mut port = unsafe { SerialPort::new(0x3F8) };
-		port.init();
-		Mutex::new(port)
-	};
-}
+use x86_64::VirtAddr;
+
+mod uart;
 
-// a function to print into SERIAL1
+pub use uart::{Config, Error, FifoThreshold, Parity, StopBits, Uart};
+
+// prints to the log channel, waiting for the UART
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
 	use core::fmt::Write;
-	// if an interrupt occurs while the mutex is locked
-	// a deadlock occurs.
-	// Thus, we'll prevent the handling of interrupts while the mutex is locked.
+	// an interrupt in the middle could queue its own bytes in between,
+	// so we'll prevent the handling of interrupts while we print.
 	use x86_64::instructions::interrupts;
 	interrupts::without_interrupts( || {
-		SERIAL1
-			.lock()  // to be able to use the mutex wrapped object
-			.write_fmt(args)  // SerialPort provides self.write_fmt(args) ie implements fmt::Write
-			.expect("Serial Printing Failed");  // on error
+		if let Some(com) = port(Channel::Log) {
+			// whatever the sinks queued goes first, or the two get mixed up
+			drain_output(com);
+			if let Some(mut uart) = uart(com) {
+				uart.write_fmt(args).expect("Serial Printing Failed");
+			}
+		}
 	});
 }
 
@@ -42,3 +45,353 @@ macro_rules! serial_println {
 	($fmt:expr) => ($crate::serial_print!(concat!($fmt, "\n")));
 	($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(concat!($fmt, "\n"), $($arg)*));
 }
+
+
+// The ports
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComPort {
+	Com1,
+	Com2,
+	Com3,
+	Com4,
+}
+
+impl ComPort {
+	pub const ALL: [ComPort; 4] = [ComPort::Com1, ComPort::Com2, ComPort::Com3, ComPort::Com4];
+
+	fn index(self) -> usize {
+		self as usize
+	}
+
+	/// COM1 and COM3 share IRQ4, COM2 and COM4 share IRQ3.
+	pub fn irq(self) -> u8 {
+		match self {
+			ComPort::Com1 | ComPort::Com3 => 4,
+			ComPort::Com2 | ComPort::Com4 => 3,
+		}
+	}
+}
+
+impl core::fmt::Display for ComPort {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		write!(f, "COM{}", self.index() + 1)
+	}
+}
+
+// the BIOS keeps the base ports of COM1 to COM4 here, 0 for a missing one
+const BDA_COM_PORTS: u64 = 0x400;
+
+// COM1 is taken for granted until `init` has looked, there's printing to do
+static BASES: [AtomicU16; 4] = [
+	AtomicU16::new(0x3f8), AtomicU16::new(0), AtomicU16::new(0), AtomicU16::new(0),
+];
+static SET_UP: [AtomicBool; 4] = [const { AtomicBool::new(false) }; 4];
+
+// 8N1 at 115200 everywhere, but the console wants every key straight away,
+// not once the FIFO fills up
+const CONSOLE_CONFIG: Config = Config { fifo_threshold: FifoThreshold::One, ..Config::DEFAULT };
+static CONFIGS: Mutex<[Config; 4]> = Mutex::new([
+	Config::DEFAULT, Config::DEFAULT, CONSOLE_CONFIG, Config::DEFAULT,
+]);
+
+/// Finds the ports the BIOS found and sets them up. Then the log goes to
+/// COM1, GDB to COM2 and the console to COM3, or to COM1 without a COM3.
+pub fn init(physical_memory_offset: VirtAddr) {
+	use x86_64::instructions::interrupts;
+
+	let bda: *const u16 = (physical_memory_offset + BDA_COM_PORTS).as_ptr();
+	let configs = interrupts::without_interrupts(|| *CONFIGS.lock());
+
+	for &com in ComPort::ALL.iter() {
+		let base = unsafe { bda.add(com.index()).read_volatile() };
+		if base == 0 {
+			BASES[com.index()].store(0, Ordering::Relaxed);
+			continue;
+		}
+
+		let uart = unsafe { Uart::new(base) };
+		let result = interrupts::without_interrupts(|| {
+			let result = uart.init(&configs[com.index()]);
+			BASES[com.index()].store(if result.is_ok() { base } else { 0 }, Ordering::Relaxed);
+			SET_UP[com.index()].store(result.is_ok(), Ordering::Release);
+			result
+		});
+		match result {
+			Ok(()) => crate::info!("{} at {:#x}, {}", com, base, configs[com.index()]),
+			Err(err) => crate::warn!("{} at {:#x} isn't working: {}", com, base, err),
+		}
+	}
+
+	let present = |com: &ComPort| BASES[com.index()].load(Ordering::Relaxed) != 0;
+	let log = Some(ComPort::Com1).filter(present);
+	assign(Channel::Log, log);
+	assign(Channel::Gdb, Some(ComPort::Com2).filter(present));
+	assign(Channel::Console, Some(ComPort::Com3).filter(present).or(log));
+
+	for &channel in Channel::ALL.iter() {
+		if let Some(com) = port(channel) {
+			crate::info!("serial {} on {}", channel.name(), com);
+		}
+	}
+	for com in ComPort::ALL.iter().filter(|com| present(com)) {
+		unsafe { crate::interrupts::unmask_irq(com.irq()) };
+	}
+}
+
+/// Where `com` is, `None` if there's no such port. The first time it's set up.
+pub fn uart(com: ComPort) -> Option<Uart> {
+	let uart = existing(com)?;
+	if !SET_UP[com.index()].swap(true, Ordering::AcqRel) {
+		let config = x86_64::instructions::interrupts::without_interrupts(|| CONFIGS.lock()[com.index()]);
+		let _ = uart.init(&config);
+	}
+	Some(uart)
+}
+
+// without setting anything up or locking, for the interrupt handler and panics
+pub(crate) fn existing(com: ComPort) -> Option<Uart> {
+	match BASES[com.index()].load(Ordering::Relaxed) {
+		0 => None,
+		base => Some(unsafe { Uart::new(base) }),
+	}
+}
+
+/// How `com` is set up, or would be if it were there.
+pub fn config(com: ComPort) -> Config {
+	x86_64::instructions::interrupts::without_interrupts(|| CONFIGS.lock()[com.index()])
+}
+
+/// Sets `com` up again with another speed or framing. What's queued for it
+/// goes out first, the old way.
+pub fn configure(com: ComPort, config: Config) -> Result<(), Error> {
+	use x86_64::instructions::interrupts;
+
+	let uart = existing(com).ok_or(Error::NoUart)?;
+	interrupts::without_interrupts(|| {
+		drain_output(com);
+		uart.init(&config)?;
+		uart.set_interrupts(wanted_interrupts(com));
+		SET_UP[com.index()].store(true, Ordering::Release);
+		CONFIGS.lock()[com.index()] = config;
+		Ok(())
+	})
+}
+
+
+// Channels
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+	/// `serial_print!`, the serial sink and the panic dump.
+	Log,
+	/// Kept free for a GDB stub. Nothing speaks GDB yet, so it stays quiet.
+	Gdb,
+	/// A terminal. What's typed there is decoded into key events by
+	/// `task::serial`, and the serial sink mirrors the console to it.
+	Console,
+}
+
+impl Channel {
+	pub const ALL: [Channel; 3] = [Channel::Log, Channel::Gdb, Channel::Console];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Channel::Log => "log",
+			Channel::Gdb => "gdb",
+			Channel::Console => "console",
+		}
+	}
+}
+
+const UNASSIGNED: u8 = u8::MAX;
+// an index into `ComPort::ALL` for each channel, COM1 does everything until `init`
+static CHANNELS: [AtomicU8; 3] = [AtomicU8::new(0), AtomicU8::new(UNASSIGNED), AtomicU8::new(0)];
+
+/// The port a channel goes over, `None` if it's switched off.
+pub fn port(channel: Channel) -> Option<ComPort> {
+	let index = CHANNELS[channel as usize].load(Ordering::Relaxed);
+	ComPort::ALL.get(usize::from(index)).copied()
+}
+
+/// Moves a channel to another port, or switches it off with `None`. Two
+/// channels can share a port.
+pub fn assign(channel: Channel, com: Option<ComPort>) {
+	use x86_64::instructions::interrupts;
+
+	let index = com.map_or(UNASSIGNED, |com| com.index() as u8);
+	interrupts::without_interrupts(|| {
+		CHANNELS[channel as usize].store(index, Ordering::Relaxed);
+		// the console's port is the one that listens
+		for &com in ComPort::ALL.iter() {
+			if let Some(uart) = existing(com) {
+				if SET_UP[com.index()].load(Ordering::Acquire) {
+					uart.set_interrupts(wanted_interrupts(com));
+				}
+			}
+		}
+	});
+}
+
+// what `com` should interrupt for: incoming keys on the console, and room
+// to send while there's something queued
+fn wanted_interrupts(com: ComPort) -> u8 {
+	let mut interrupts = 0;
+	if port(Channel::Console) == Some(com) {
+		interrupts |= uart::RECEIVED_DATA;
+	}
+	if let Ok(queue) = OUTPUT_QUEUES[com.index()].try_get() {
+		if !queue.is_empty() {
+			interrupts |= uart::TRANSMIT_EMPTY;
+		}
+	}
+	interrupts
+}
+
+/// Called by the IRQ3 and IRQ4 handlers, for the ports on that line.
+/// Must not block or allocate
+pub(crate) fn handle_interrupt(irq: u8) {
+	let console = port(Channel::Console);
+
+	for &com in ComPort::ALL.iter().filter(|com| com.irq() == irq) {
+		let uart = match existing(com) {
+			Some(uart) => uart,
+			None => continue,
+		};
+
+		while let Some(byte) = uart.receive() {
+			// only the console has anyone listening
+			if Some(com) == console {
+				crate::task::serial::update_input_queue(byte);
+			}
+		}
+
+		if uart.is_idle() {
+			if let Ok(queue) = OUTPUT_QUEUES[com.index()].try_get() {
+				for byte in core::iter::from_fn(|| queue.pop()).take(uart::FIFO_SIZE) {
+					uart.put(byte);
+				}
+			}
+			// with the queue empty the last bytes go out on their own
+			uart.set_interrupts(wanted_interrupts(com));
+		}
+	}
+}
+
+
+// Output that doesn't wait for the UART
+
+static OUTPUT_QUEUES: [OnceCell<ArrayQueue<u8>>; 4] = [const { OnceCell::uninit() }; 4];
+const OUTPUT_QUEUE_CAP: usize = 0x1000;
+
+/// From now on `send` only queues the bytes and the transmit interrupt sends
+/// them, nobody waits for the UART. Needs the heap.
+pub fn buffer_output() {
+	for &com in ComPort::ALL.iter() {
+		if existing(com).is_some() {
+			OUTPUT_QUEUES[com.index()].try_init_once(|| ArrayQueue::new(OUTPUT_QUEUE_CAP))
+				.expect("Serial output must be buffered only once");
+		}
+	}
+}
+
+/// Sends `bytes` over `com`, through the queue once `buffer_output` was called.
+pub fn send(com: ComPort, bytes: &[u8]) {
+	use x86_64::instructions::interrupts;
+
+	// the interrupt handler takes from the queue too, so it has to wait
+	interrupts::without_interrupts(|| {
+		let uart = match uart(com) {
+			Some(uart) => uart,
+			None => return,
+		};
+		let queue = match OUTPUT_QUEUES[com.index()].try_get() {
+			Ok(queue) => queue,
+			Err(_) => {
+				for &byte in bytes {
+					uart.send(byte);
+				}
+				return;
+			}
+		};
+
+		for &byte in bytes {
+			if let Err(byte) = queue.push(byte) {
+				// the UART can't keep up, make room the slow way
+				drain_output(com);
+				let _ = queue.push(byte);
+			}
+		}
+		uart.set_interrupts(wanted_interrupts(com));
+	});
+}
+
+/// Sends everything still queued, waiting for the UARTs. For when the
+/// interrupts won't come, like in a panic.
+pub fn flush() {
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		for &com in ComPort::ALL.iter() {
+			drain_output(com);
+		}
+	});
+}
+
+// only with the interrupts off, or the handler could send a byte in between
+fn drain_output(com: ComPort) {
+	if let (Ok(queue), Some(uart)) = (OUTPUT_QUEUES[com.index()].try_get(), existing(com)) {
+		while let Some(byte) = queue.pop() {
+			uart.send(byte);
+		}
+	}
+}
+
+/// `send` to a port as a `fmt::Write`.
+pub struct SerialOutput(pub ComPort);
+
+impl core::fmt::Write for SerialOutput {
+	fn write_str(&mut self, s: &str) -> core::fmt::Result {
+		send(self.0, s.as_bytes());
+		Ok(())
+	}
+}
+
+/// The port from before there were channels, it's COM1.
+#[deprecated(note = "use `SerialOutput(ComPort::Com1)`, or `port(Channel::Log)` for the log")]
+pub static SERIAL1: Com1 = Com1;
+
+/// Locks like the old `Mutex<SerialPort>` of COM1, see `SERIAL1`.
+pub struct Com1;
+
+impl Com1 {
+	/// COM1 as a `fmt::Write`. There's nothing to lock, `send` keeps the
+	/// interrupt handler out by itself.
+	pub fn lock(&self) -> SerialOutput {
+		SerialOutput(ComPort::Com1)
+	}
+}
+
+
+#[test_case]
+fn test_channels() {
+	let console = port(Channel::Console);
+
+	assign(Channel::Console, Some(ComPort::Com4));
+	assert_eq!(port(Channel::Console), Some(ComPort::Com4));
+	assign(Channel::Console, None);
+	assert_eq!(port(Channel::Console), None);
+
+	assign(Channel::Console, console);
+	assert_eq!(alloc::format!("{}", ComPort::Com3), "COM3");
+	assert_eq!((ComPort::Com3.irq(), ComPort::Com4.irq()), (4, 3));
+}
+
+#[test_case]
+fn test_configure_data_bits() {
+	// nothing speaks GDB yet, so its port is free to play with
+	let com = ComPort::Com2;
+	let before = config(com);
+
+	assert_eq!(configure(com, Config { data_bits: 7, ..before }), Ok(()));
+	assert_eq!(config(com).data_bits, 7);
+	assert_eq!(configure(com, before), Ok(()));
+}
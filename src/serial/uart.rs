@@ -0,0 +1,266 @@
+// A 16550 UART, talked to through its eight I/O ports.
+
+use core::convert::TryFrom;
+use core::fmt;
+use x86_64::instructions::port::Port;
+
+// registers, from the base port
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+// with DLAB set in the line control, the first two hold the divisor instead
+const DIVISOR_LOW: u16 = 0;
+const DIVISOR_HIGH: u16 = 1;
+
+const DLAB: u8 = 0x80;
+// enable, and clear both FIFOs
+const FIFO_ENABLE: u8 = 0x07;
+// DTR and RTS, and OUT2 which connects the IRQ line to the PIC
+const DTR_RTS_OUT2: u8 = 0x0b;
+// the transmitter goes straight to the receiver, to check there's a UART at all
+const LOOPBACK: u8 = 0x1e;
+// 8N1, so the test byte comes back whole whatever the port is set up for
+const LOOPBACK_LINE: u8 = 0x03;
+// how many times to look for the test byte before giving up on the UART
+const LOOPBACK_POLLS: usize = 1000;
+
+// line status
+const DATA_READY: u8 = 0x01;
+const THR_EMPTY: u8 = 0x20;  // the whole transmit FIFO, with the FIFOs on
+
+// the interrupts, for `set_interrupts`
+pub const RECEIVED_DATA: u8 = 0x01;
+pub const TRANSMIT_EMPTY: u8 = 0x02;
+
+/// What the transmit FIFO holds.
+pub const FIFO_SIZE: usize = 16;
+
+// the divisor divides this
+const MAX_BAUD: u32 = 115_200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+	None,
+	Odd,
+	Even,
+	/// The parity bit is always 1.
+	Mark,
+	/// The parity bit is always 0.
+	Space,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+	One,
+	/// One and a half with 5 data bits.
+	Two,
+}
+
+/// How full the receive FIFO gets before it interrupts. Less waits for
+/// fewer bytes, more interrupts less often.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FifoThreshold {
+	One = 0x00,
+	Four = 0x40,
+	Eight = 0x80,
+	Fourteen = 0xc0,
+}
+
+/// How a port talks: speed, framing and FIFO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+	/// Has to divide 115200, and be at least 2.
+	pub baud: u32,
+	/// 5 to 8.
+	pub data_bits: u8,
+	pub parity: Parity,
+	pub stop_bits: StopBits,
+	pub fifo_threshold: FifoThreshold,
+}
+
+impl Config {
+	/// 115200 baud, 8N1, interrupting at 14 bytes.
+	pub const DEFAULT: Config = Config {
+		baud: MAX_BAUD,
+		data_bits: 8,
+		parity: Parity::None,
+		stop_bits: StopBits::One,
+		fifo_threshold: FifoThreshold::Fourteen,
+	};
+
+	fn divisor(&self) -> Result<u16, Error> {
+		if self.baud == 0 || !MAX_BAUD.is_multiple_of(self.baud) {
+			return Err(Error::BadBaudRate(self.baud));
+		}
+		// the divisor register is only 16 bits, too slow a rate doesn't fit
+		u16::try_from(MAX_BAUD / self.baud).map_err(|_| Error::BadBaudRate(self.baud))
+	}
+
+	fn line_control(&self) -> Result<u8, Error> {
+		let data_bits = match self.data_bits {
+			5..=8 => self.data_bits - 5,
+			_ => return Err(Error::BadDataBits(self.data_bits)),
+		};
+		let stop_bits = match self.stop_bits {
+			StopBits::One => 0x00,
+			StopBits::Two => 0x04,
+		};
+		let parity = match self.parity {
+			Parity::None => 0x00,
+			Parity::Odd => 0x08,
+			Parity::Even => 0x18,
+			Parity::Mark => 0x28,
+			Parity::Space => 0x38,
+		};
+		Ok(data_bits | stop_bits | parity)
+	}
+}
+
+impl fmt::Display for Config {
+	/// The usual shorthand, like `115200 8N1`.
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let parity = match self.parity {
+			Parity::None => 'N',
+			Parity::Odd => 'O',
+			Parity::Even => 'E',
+			Parity::Mark => 'M',
+			Parity::Space => 'S',
+		};
+		let stop_bits = match self.stop_bits {
+			StopBits::One => 1,
+			StopBits::Two => 2,
+		};
+		write!(f, "{} {}{}{}", self.baud, self.data_bits, parity, stop_bits)
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+	BadBaudRate(u32),
+	BadDataBits(u8),
+	/// Nothing came back in loopback, there's no working UART there.
+	NoUart,
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::BadBaudRate(baud) => write!(f, "{} baud doesn't divide {}", baud, MAX_BAUD),
+			Error::BadDataBits(bits) => write!(f, "{} data bits, it has to be 5 to 8", bits),
+			Error::NoUart => write!(f, "no UART answered"),
+		}
+	}
+}
+
+/// A UART at some base port. It's just the port number, so copies are fine,
+/// whoever writes has to keep the others out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Uart {
+	base: u16,
+}
+
+impl Uart {
+	/// Unsafe because there had better be a UART at `base`, and nothing else.
+	pub const unsafe fn new(base: u16) -> Uart {
+		Uart { base }
+	}
+
+	pub fn base(&self) -> u16 {
+		self.base
+	}
+
+	fn read(&self, register: u16) -> u8 {
+		unsafe { Port::new(self.base + register).read() }
+	}
+
+	fn write(&self, register: u16, value: u8) {
+		unsafe { Port::new(self.base + register).write(value) }
+	}
+
+	/// Sets the port up with the interrupts off.
+	pub fn init(&self, config: &Config) -> Result<(), Error> {
+		let divisor = config.divisor()?;
+		let line_control = config.line_control()?;
+
+		self.write(INTERRUPT_ENABLE, 0);
+		self.write(LINE_CONTROL, DLAB);
+		self.write(DIVISOR_LOW, divisor as u8);
+		self.write(DIVISOR_HIGH, (divisor >> 8) as u8);
+		self.write(LINE_CONTROL, LOOPBACK_LINE);
+		self.write(FIFO_CONTROL, FIFO_ENABLE | config.fifo_threshold as u8);
+
+		self.write(MODEM_CONTROL, LOOPBACK);
+		self.write(DATA, 0xae);
+		let echo = (0..LOOPBACK_POLLS).find_map(|_| self.receive());
+		if echo != Some(0xae) {
+			return Err(Error::NoUart);
+		}
+		self.write(LINE_CONTROL, line_control);
+		self.write(MODEM_CONTROL, DTR_RTS_OUT2);
+		// whatever came in before is stale
+		while self.receive().is_some() {}
+		Ok(())
+	}
+
+	/// Which interrupts it raises, `RECEIVED_DATA` and `TRANSMIT_EMPTY`.
+	/// Turning `TRANSMIT_EMPTY` on while it's idle interrupts right away.
+	pub fn set_interrupts(&self, interrupts: u8) {
+		self.write(INTERRUPT_ENABLE, interrupts);
+	}
+
+	pub fn receive(&self) -> Option<u8> {
+		match self.read(LINE_STATUS) & DATA_READY {
+			0 => None,
+			_ => Some(self.read(DATA)),
+		}
+	}
+
+	/// True once everything sent so far is out, then `FIFO_SIZE` bytes fit.
+	pub fn is_idle(&self) -> bool {
+		self.read(LINE_STATUS) & THR_EMPTY != 0
+	}
+
+	/// Sends without checking there's room, see `is_idle`.
+	pub fn put(&self, byte: u8) {
+		self.write(DATA, byte);
+	}
+
+	/// Waits until there's room and sends.
+	pub fn send(&self, byte: u8) {
+		while !self.is_idle() {
+			core::hint::spin_loop();
+		}
+		self.put(byte);
+	}
+}
+
+impl fmt::Write for Uart {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		for byte in s.bytes() {
+			self.send(byte);
+		}
+		Ok(())
+	}
+}
+
+
+#[test_case]
+fn test_uart_config() {
+	let config = Config { baud: 9600, parity: Parity::Even, stop_bits: StopBits::Two, ..Config::DEFAULT };
+	assert_eq!(config.divisor(), Ok(12));
+	assert_eq!(config.line_control(), Ok(0x03 | 0x04 | 0x18));
+	assert_eq!(alloc::format!("{}", config), "9600 8E2");
+
+	let config = Config { baud: 7000, ..Config::DEFAULT };
+	assert_eq!(config.divisor(), Err(Error::BadBaudRate(7000)));
+	let config = Config { baud: 1, ..Config::DEFAULT };
+	assert_eq!(config.divisor(), Err(Error::BadBaudRate(1)));
+	let config = Config { baud: 2, ..Config::DEFAULT };
+	assert_eq!(config.divisor(), Ok(57600));
+	let config = Config { data_bits: 9, ..Config::DEFAULT };
+	assert_eq!(config.line_control(), Err(Error::BadDataBits(9)));
+}
@@ -0,0 +1,268 @@
+//! The kernel shell.
+//!
+//! Reads a line on the kernel console, splits it into words and runs the
+//! command the first word names. Commands are async functions in a registry,
+//! anything can `register` its own, like the allocator does with `mem`.
+//!
+//! Words are split at blanks. Quotes keep blanks in a word, `"` lets `\`
+//! through to escape the next character and `'` doesn't.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use spin::Mutex;
+
+use crate::task::keyboard::KeyStream;
+
+mod builtins;
+
+pub use builtins::register_builtins;
+
+const PROMPT: &str = "> ";
+// how long to wait for the keyboard while something else has it
+const BUSY_RETRY_TICKS: u64 = 9;
+
+/// What a command comes back with.
+pub type CommandResult = Result<(), CommandError>;
+pub type CommandFuture = Pin<Box<dyn Future<Output = CommandResult>>>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+	/// The arguments were wrong, the shell shows the usage.
+	Usage,
+	/// Shown after the command's name.
+	Failed(String),
+}
+
+/// A command for the shell to run, see `register`.
+#[derive(Clone, Copy)]
+pub struct Command {
+	pub name: &'static str,
+	/// The arguments it takes, like `[name]`.
+	pub usage: &'static str,
+	/// One line for `help`.
+	pub help: &'static str,
+	/// Gets the arguments, without the command's name. An `async fn` fits
+	/// in with `|args| Box::pin(command(args))`.
+	pub run: fn(Vec<String>) -> CommandFuture,
+}
+
+/// `register` was given a name another command already has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NameTaken;
+
+// only touched by tasks, never by an interrupt handler
+static COMMANDS: Mutex<BTreeMap<&'static str, Command>> = Mutex::new(BTreeMap::new());
+
+pub fn register(command: Command) -> Result<(), NameTaken> {
+	let mut commands = COMMANDS.lock();
+	if commands.contains_key(command.name) {
+		return Err(NameTaken);
+	}
+	commands.insert(command.name, command);
+	Ok(())
+}
+
+pub fn find(name: &str) -> Option<Command> {
+	COMMANDS.lock().get(name).copied()
+}
+
+/// Every command, by name.
+pub fn commands() -> Vec<Command> {
+	COMMANDS.lock().values().copied().collect()
+}
+
+
+// Parsing
+
+/// A quote was never closed, or the line ended on a `\`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnclosedQuote;
+
+/// Splits a line into words, see the top of this file.
+pub fn parse(line: &str) -> Result<Vec<String>, UnclosedQuote> {
+	let mut words = Vec::new();
+	let mut word: Option<String> = None;  // `Some` once a word started, even an empty ""
+	let mut quote = None;
+	let mut chars = line.chars();
+
+	while let Some(c) = chars.next() {
+		match (quote, c) {
+			(Some('\''), '\'') | (Some('"'), '"') => quote = None,
+			(Some('\''), c) => word.get_or_insert_with(String::new).push(c),
+			(_, '\\') => word.get_or_insert_with(String::new).push(chars.next().ok_or(UnclosedQuote)?),
+			(Some(_), c) => word.get_or_insert_with(String::new).push(c),
+			(None, '\'') | (None, '"') => {
+				quote = Some(c);
+				word.get_or_insert_with(String::new);
+			}
+			(None, c) if c.is_whitespace() => words.extend(word.take()),
+			(None, c) => word.get_or_insert_with(String::new).push(c),
+		}
+	}
+	if quote.is_some() {
+		return Err(UnclosedQuote);
+	}
+	words.extend(word);
+	Ok(words)
+}
+
+
+// Running
+
+/// Runs one line, errors and all go to the console.
+pub async fn execute(line: &str) {
+	use crate::errorln;
+
+	let mut words = match parse(line) {
+		Ok(words) => words,
+		Err(UnclosedQuote) => {
+			errorln!("there's a quote that isn't closed");
+			return;
+		}
+	};
+	if words.is_empty() {
+		return;
+	}
+
+	let name = words.remove(0);
+	let command = match find(&name) {
+		Some(command) => command,
+		None => {
+			errorln!("{}: no such command, `help` lists them", name);
+			return;
+		}
+	};
+	match (command.run)(words).await {
+		Ok(()) => {}
+		Err(CommandError::Usage) => errorln!("usage: {} {}", command.name, command.usage),
+		Err(CommandError::Failed(message)) => errorln!("{}: {}", command.name, message),
+	}
+}
+
+// command names for the first word, nothing after it
+fn complete(before: &str) -> Vec<String> {
+	if before.contains(char::is_whitespace) {
+		return Vec::new();
+	}
+	COMMANDS.lock().keys()
+		.filter(|name| name.starts_with(before))
+		.map(|&name| String::from(name))
+		.collect()
+}
+
+// the shell's keys while a command runs, see `keys`
+static COMMAND_KEYS: Mutex<Option<KeyStream>> = Mutex::new(None);
+
+/// The keyboard, for a command that reads keys itself, like for a `tui`
+/// dialog. The shell keeps it grabbed, so `KeyStream::grab` comes back empty
+/// while a command runs. `None` outside a command, or while it's lent out.
+pub fn keys() -> Option<Keys> {
+	COMMAND_KEYS.lock().take().map(|stream| Keys { stream: Some(stream) })
+}
+
+/// The shell's `KeyStream`, lent to a command. It goes back when dropped.
+pub struct Keys {
+	stream: Option<KeyStream>,  // only `None` once dropped
+}
+
+impl core::ops::Deref for Keys {
+	type Target = KeyStream;
+
+	fn deref(&self) -> &KeyStream {
+		self.stream.as_ref().expect("lent keys are there until dropped")
+	}
+}
+
+impl core::ops::DerefMut for Keys {
+	fn deref_mut(&mut self) -> &mut KeyStream {
+		self.stream.as_mut().expect("lent keys are there until dropped")
+	}
+}
+
+impl Drop for Keys {
+	fn drop(&mut self) {
+		*COMMAND_KEYS.lock() = self.stream.take();
+	}
+}
+
+/// Reads commands on the kernel console and runs them, one at a time.
+pub async fn run() {
+	use crate::line_editor::{Error, LineEditor};
+	use crate::vga_buffer::KERNEL_CONSOLE;
+
+	// keeps the keyboard from the first line on, see `LineEditor`
+	let mut editor = LineEditor::new(KERNEL_CONSOLE);
+	editor.set_completer(Box::new(complete));
+	editor.set_terminal(true);
+
+	loop {
+		// a command can hang on to its keys after it's done
+		if let Some(keys) = COMMAND_KEYS.lock().take() {
+			editor.give_keys(keys);
+		}
+		match editor.read_line(PROMPT).await {
+			Ok(line) => {
+				*COMMAND_KEYS.lock() = editor.take_keys();
+				execute(&line).await;
+			}
+			Err(Error::Interrupted) => {}
+			Err(Error::KeyboardBusy) => crate::task::timer::sleep(BUSY_RETRY_TICKS).await,
+		}
+	}
+}
+
+
+#[test_case]
+fn test_parse_words() {
+	let words = |line| parse(line).unwrap();
+
+	assert_eq!(words("  echo  one two "), ["echo", "one", "two"]);
+	assert_eq!(words(r#"echo "one two" 'a\b' c\ d "" x"\"y""#), ["echo", "one two", r"a\b", "c d", "", "x\"y"]);
+	assert!(words("").is_empty());
+	assert_eq!(parse("echo \"one"), Err(UnclosedQuote));
+	assert_eq!(parse("echo one\\"), Err(UnclosedQuote));
+}
+
+#[test_case]
+fn test_command_registry() {
+	use core::sync::atomic::{AtomicUsize, Ordering};
+	use futures_util::FutureExt;
+
+	static ARGS: AtomicUsize = AtomicUsize::new(0);
+
+	async fn count(args: Vec<String>) -> CommandResult {
+		ARGS.store(args.len(), Ordering::Relaxed);
+		Ok(())
+	}
+
+	let command = Command {
+		name: "test-count",
+		usage: "[args...]",
+		help: "counts its arguments",
+		run: |args| Box::pin(count(args)),
+	};
+	assert_eq!(register(command), Ok(()));
+	assert_eq!(register(command), Err(NameTaken));
+
+	assert!(execute("test-count one 'two three'").now_or_never().is_some());
+	assert_eq!(ARGS.load(Ordering::Relaxed), 2);
+	assert_eq!(complete("test-c"), ["test-count"]);
+	assert!(complete("test-count ").is_empty());
+}
+
+#[test_case]
+fn test_lent_keys() {
+	assert!(keys().is_none());
+	*COMMAND_KEYS.lock() = KeyStream::grab();
+
+	let lent = keys().expect("the shell's keys are there");
+	assert!(keys().is_none());
+	drop(lent);
+	assert!(keys().is_some());
+
+	COMMAND_KEYS.lock().take();
+}
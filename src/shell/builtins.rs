@@ -0,0 +1,140 @@
+// The commands every shell has. Other subsystems register their own.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::{register, Command, CommandError, CommandResult};
+use crate::println;
+
+const BUILTINS: [Command; 7] = [
+	Command {
+		name: "help",
+		usage: "[command]",
+		help: "lists the commands, or tells how to use one",
+		run: |args| Box::pin(help(args)),
+	},
+	Command {
+		name: "clear",
+		usage: "",
+		help: "clears the console",
+		run: |args| Box::pin(clear(args)),
+	},
+	Command {
+		name: "echo",
+		usage: "[words...]",
+		help: "prints its arguments",
+		run: |args| Box::pin(echo(args)),
+	},
+	Command {
+		name: "tasks",
+		usage: "",
+		help: "how many tasks are running",
+		run: |args| Box::pin(tasks(args)),
+	},
+	Command {
+		name: "uptime",
+		usage: "",
+		help: "how long since the timer started",
+		run: |args| Box::pin(uptime(args)),
+	},
+	Command {
+		name: "reboot",
+		usage: "",
+		help: "resets the machine",
+		run: |args| Box::pin(reboot(args)),
+	},
+	Command {
+		name: "layout",
+		usage: "[name]",
+		help: "shows or switches the keyboard layout",
+		run: |args| Box::pin(layout(args)),
+	},
+];
+
+/// Puts the builtin commands in the registry.
+pub fn register_builtins() {
+	for &command in BUILTINS.iter() {
+		register(command).expect("a builtin's name was taken");
+	}
+}
+
+async fn help(args: Vec<String>) -> CommandResult {
+	match args.as_slice() {
+		[] => {
+			let commands = super::commands();
+			let width = commands.iter().map(|command| command.name.len()).max().unwrap_or(0);
+			for command in commands {
+				println!("  {:width$}  {}", command.name, command.help, width = width);
+			}
+			Ok(())
+		}
+		[name] => {
+			let command = super::find(name)
+				.ok_or_else(|| CommandError::Failed(alloc::format!("there's no {} command", name)))?;
+			println!("{}", command.help);
+			println!("usage: {} {}", command.name, command.usage);
+			Ok(())
+		}
+		_ => Err(CommandError::Usage),
+	}
+}
+
+async fn clear(args: Vec<String>) -> CommandResult {
+	if !args.is_empty() {
+		return Err(CommandError::Usage);
+	}
+	crate::print!("\x1b[2J\x1b[1;1H");
+	Ok(())
+}
+
+async fn echo(args: Vec<String>) -> CommandResult {
+	println!("{}", args.join(" "));
+	Ok(())
+}
+
+async fn tasks(args: Vec<String>) -> CommandResult {
+	if !args.is_empty() {
+		return Err(CommandError::Usage);
+	}
+	println!("{} tasks", crate::task::better_executor::live_tasks());
+	Ok(())
+}
+
+async fn uptime(args: Vec<String>) -> CommandResult {
+	if !args.is_empty() {
+		return Err(CommandError::Usage);
+	}
+	let seconds = crate::interrupts::uptime_millis() / 1000;
+	println!(
+		"up {}:{:02}:{:02}, {} ticks",
+		seconds / 3600, seconds / 60 % 60, seconds % 60, crate::interrupts::ticks()
+	);
+	Ok(())
+}
+
+async fn reboot(args: Vec<String>) -> CommandResult {
+	if !args.is_empty() {
+		return Err(CommandError::Usage);
+	}
+	println!("rebooting");
+	// the transmit interrupt won't get to it
+	crate::serial::flush();
+	crate::ps2::reboot();
+}
+
+async fn layout(args: Vec<String>) -> CommandResult {
+	use crate::task::keyboard::{layout, set_layout, LAYOUTS};
+
+	match args.as_slice() {
+		[] => {
+			let names: Vec<&str> = LAYOUTS.iter().map(|layout| layout.name).collect();
+			println!("{} (there's {})", layout().name, names.join(", "));
+			Ok(())
+		}
+		[name] => set_layout(name).map_err(|_| {
+			CommandError::Failed(alloc::format!("there's no layout called {}", name))
+		}),
+		_ => Err(CommandError::Usage),
+	}
+}
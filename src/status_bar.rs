@@ -0,0 +1,97 @@
+//! The row at the bottom of every console: uptime, heap use, how many tasks
+//! are running, the keyboard layout and the time of day.
+//!
+//! `run` reserves the row and keeps it up to date, the text scrolls above it.
+
+use core::fmt::{self, Write};
+
+use crate::vga_buffer::{cp437, CONSOLES};
+
+// about twice a second
+const REFRESH_TICKS: u64 = 9;
+
+// wider than any text mode
+const LINE_WIDTH: usize = 128;
+
+/// Enough CP437 glyphs for a line, whatever doesn't fit is cut off.
+struct Line {
+	glyphs: [u8; LINE_WIDTH],
+	len: usize,
+}
+
+impl Line {
+	fn new() -> Line {
+		Line {
+			glyphs: [b' '; LINE_WIDTH],
+			len: 0,
+		}
+	}
+}
+
+impl fmt::Write for Line {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		for c in s.chars() {
+			if self.len == LINE_WIDTH {
+				break;
+			}
+			self.glyphs[self.len] = cp437::from_char(c).unwrap_or(cp437::REPLACEMENT);
+			self.len += 1;
+		}
+		Ok(())
+	}
+}
+
+/// The status row of `width` glyphs, with the clock on the right.
+fn status_line(width: usize) -> Line {
+	use crate::allocator::{heap_used, HEAP_SIZE};
+	use crate::task::{better_executor, keyboard};
+
+	let seconds = crate::interrupts::uptime_millis() / 1000;
+	let mut line = Line::new();
+	let _ = write!(
+		line,
+		" up {}:{:02}:{:02} │ heap {}/{} B │ {} tasks │ kbd {}",
+		seconds / 3600, seconds / 60 % 60, seconds % 60,
+		heap_used(), HEAP_SIZE,
+		better_executor::live_tasks(),
+		keyboard::layout_name(),
+	);
+
+	let mut clock = Line::new();
+	let _ = write!(clock, "{} ", crate::rtc::time());
+	// the clock gives way to the rest on a narrow screen
+	let width = width.min(LINE_WIDTH);
+	if line.len + clock.len <= width {
+		let start = width - clock.len;
+		line.glyphs[start..width].copy_from_slice(&clock.glyphs[..clock.len]);
+		line.len = width;
+	}
+	line
+}
+
+/// Redraws the status row of every console.
+pub fn refresh() {
+	use x86_64::instructions::interrupts;
+
+	// every console is as wide as the mode
+	let line = status_line(crate::vga_buffer::text_mode().width());
+	for console in CONSOLES.iter() {
+		interrupts::without_interrupts(|| {
+			console.lock().set_status(&line.glyphs[..line.len]);
+		});
+	}
+}
+
+/// Reserves the status row on every console and keeps it refreshed.
+pub async fn run() {
+	use x86_64::instructions::interrupts;
+
+	for console in CONSOLES.iter() {
+		interrupts::without_interrupts(|| console.lock().enable_status_row());
+	}
+
+	loop {
+		refresh();
+		crate::task::timer::sleep(REFRESH_TICKS).await;
+	}
+}
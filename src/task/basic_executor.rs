@@ -11,14 +11,13 @@ impl BasicExecutor {
 	}
 
 	pub fn spawn(&mut self, task: Task) {
-		use crate::println;
-		println!("Added task to executor, task: {:#?}", task);
+		crate::debug!("Added task to executor, task: {:#?}", task);
 		self.task_queue.push_back(task);
 	}
 
 	pub fn run(&mut self) {
-		use crate::println;
-		println!("Started running tasks.");
+		use crate::debug;
+		debug!("Started running tasks.");
 
 		while let Some(mut task) = self.task_queue.pop_front() {
 			use core::task::{Poll, Context};
@@ -31,8 +30,7 @@ impl BasicExecutor {
 			};
 		}
 
-		// use crate::println;
-		println!("Executor completed")
+		debug!("Executor completed")
 	}
 }
 
@@ -50,7 +48,7 @@ fn dummy_raw_waker() -> RawWaker {
 	let vtable = &RawWakerVTable::new(clone, no_op, no_op, no_op);
 
 	// Null ptr to be passed as waker data
-	let null_ptr = 0 as *const ();
+	let null_ptr = core::ptr::null::<()>();
 
 	RawWaker::new(null_ptr, vtable)
 }
@@ -4,6 +4,15 @@ use crate::task::{TaskId, Task};
 use alloc::sync::Arc;
 use crossbeam_queue::ArrayQueue;
 use futures_util::task::Waker;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+// spawned and not finished yet, in every executor
+static LIVE_TASKS: AtomicUsize = AtomicUsize::new(0);
+
+/// How many tasks the executors are running, for the status bar.
+pub fn live_tasks() -> usize {
+	LIVE_TASKS.load(Ordering::Relaxed)
+}
 
 pub struct Executor {
 	tasks: BTreeMap<TaskId, Task>,
@@ -26,7 +35,8 @@ impl Executor {
 		if self.tasks.insert(task_id, task).is_some() {
 			panic!("Inserting a task with existing task id")
 		}
-		self.task_queue.push(task_id).expect("Too many tasks!")
+		self.task_queue.push(task_id).expect("Too many tasks!");
+		LIVE_TASKS.fetch_add(1, Ordering::Relaxed);
 	}
 
 	fn run_next_tasks(&mut self) {
@@ -44,7 +54,7 @@ impl Executor {
 
 			let waker = waker_cache
 				.entry(task_id)
-				.or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
+				.or_insert_with(|| TaskWaker::waker(task_id, task_queue.clone()));
 
 			use core::task::{Poll, Context};
 
@@ -54,6 +64,7 @@ impl Executor {
 				Poll::Ready(()) => {
 					tasks.remove(&task_id);
 					waker_cache.remove(&task_id);
+					LIVE_TASKS.fetch_sub(1, Ordering::Relaxed);
 				}
 			}
 		}
@@ -89,7 +100,7 @@ impl TaskWaker {
 		self.task_queue.push(self.task_id).expect("Too many tasks!");
 	}
 
-	fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
+	fn waker(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
 		Waker::from(Arc::new(TaskWaker {
 			task_id, task_queue
 		}))
@@ -1,6 +1,10 @@
 use crossbeam_queue::ArrayQueue;
 use conquer_once::spin::OnceCell;
 
+mod layout;
+
+pub use layout::{layout, set_layout, Key, Layout, UnknownLayout, LAYOUTS};
+
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 const SCANCODE_QUEUE_CAP: usize = 0x80;
 
@@ -8,19 +12,21 @@ const SCANCODE_QUEUE_CAP: usize = 0x80;
 /// Must not block or allocate
 /// pub(crate) visibility means it is public only within the crate
 pub(crate) fn update_scancode_queue(scancode: u8) {
-	use crate::println;
+	// we're in the interrupt handler, so printing could deadlock
+	use crate::irq_log;
+	use crate::log::Level;
 
 	let scancode_queue_res = SCANCODE_QUEUE.try_get();
 	if let Ok(queue) = scancode_queue_res {
 		let push_res = queue.push(scancode);
-		if let Err(_) = push_res {
-			println!("WARNING: SCANCODE QUEUE IS FULL")
+		if push_res.is_err() {
+			irq_log!(Level::Warn, "scancode queue is full, dropping {:#04x}", scancode)
 		} else {
 			WAKER.wake();
 		}
 	}
 	else {
-		println!("WARNING: SCANCODE QUEUE IS NOT INITIALISED")
+		irq_log!(Level::Warn, "scancode queue is not initialised, dropping {:#04x}", scancode)
 	}
 }
 
@@ -50,7 +56,7 @@ impl Stream for ScancodeStream {
 			return Poll::Ready(Some(scancode))
 		}
 
-		WAKER.register(&ctx.waker());
+		WAKER.register(ctx.waker());
 
 		match queue.pop() {
 			Some(scancode) => {
@@ -65,28 +71,446 @@ impl Stream for ScancodeStream {
 use futures_util::task::AtomicWaker;
 static WAKER: AtomicWaker = AtomicWaker::new();
 
-pub async fn print_keypresses() {
-	let mut stream = ScancodeStream::new();
-	use pc_keyboard::{Keyboard, ScancodeSet1, layouts, HandleControl, DecodedKey};
-	let mut keyboard = Keyboard::new(
-		layouts::Us104Key, ScancodeSet1, HandleControl::Ignore
-	);
 
-	{
-		use crate::println;
-		println!("Async key printer polled for the first time!")
+// Key events, for everyone who wants to hear about the keyboard
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use pc_keyboard::{DecodedKey, KeyCode, KeyState};
+use spin::Mutex;
+
+/// Which modifier keys were held, and which locks were on, when a key
+/// went down or up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers {
+	pub shift: bool,
+	pub ctrl: bool,
+	/// The left Alt, the right one is `alt_gr`.
+	pub alt: bool,
+	/// The right Alt, which types characters on some layouts.
+	pub alt_gr: bool,
+	/// The Windows keys.
+	pub meta: bool,
+	pub caps_lock: bool,
+	pub num_lock: bool,
+	pub scroll_lock: bool,
+}
+
+// pc_keyboard keeps its modifiers to itself, so we track them again,
+// left and right separately so letting go of one doesn't undo the other
+#[derive(Debug, Clone, Copy)]
+struct ModifierKeys {
+	shift: (bool, bool),  // (left, right)
+	ctrl: (bool, bool),
+	alt: (bool, bool),
+	meta: (bool, bool),
+	caps_lock: bool,
+	num_lock: bool,
+	scroll_lock: bool,
+}
+
+impl ModifierKeys {
+	fn new() -> ModifierKeys {
+		ModifierKeys {
+			shift: (false, false),
+			ctrl: (false, false),
+			alt: (false, false),
+			meta: (false, false),
+			caps_lock: false,
+			num_lock: true,  // like pc_keyboard
+			scroll_lock: false,
+		}
+	}
+
+	fn update(&mut self, code: KeyCode, pressed: bool) {
+		match code {
+			KeyCode::ShiftLeft => self.shift.0 = pressed,
+			KeyCode::ShiftRight => self.shift.1 = pressed,
+			KeyCode::ControlLeft => self.ctrl.0 = pressed,
+			KeyCode::ControlRight => self.ctrl.1 = pressed,
+			KeyCode::AltLeft => self.alt.0 = pressed,
+			KeyCode::AltRight => self.alt.1 = pressed,
+			KeyCode::WindowsLeft => self.meta.0 = pressed,
+			KeyCode::WindowsRight => self.meta.1 = pressed,
+			KeyCode::CapsLock if pressed => self.caps_lock = !self.caps_lock,
+			KeyCode::NumpadLock if pressed => self.num_lock = !self.num_lock,
+			KeyCode::ScrollLock if pressed => self.scroll_lock = !self.scroll_lock,
+			_ => {}
+		}
+	}
+
+	// what the layouts want to know
+	fn pc_keyboard(&self) -> pc_keyboard::Modifiers {
+		pc_keyboard::Modifiers {
+			lshift: self.shift.0,
+			rshift: self.shift.1,
+			lctrl: self.ctrl.0,
+			rctrl: self.ctrl.1,
+			numlock: self.num_lock,
+			capslock: self.caps_lock,
+			alt_gr: self.alt.1,
+		}
+	}
+
+	// puts the locks on the keyboard LEDs
+	fn show_locks(&self) {
+		let leds = crate::ps2::Leds {
+			scroll_lock: self.scroll_lock,
+			num_lock: self.num_lock,
+			caps_lock: self.caps_lock,
+		};
+		if let Err(err) = crate::ps2::set_leds(leds) {
+			crate::debug!("can't set the keyboard LEDs: {}", err);
+		}
+	}
+
+	fn state(&self) -> Modifiers {
+		Modifiers {
+			shift: self.shift.0 || self.shift.1,
+			ctrl: self.ctrl.0 || self.ctrl.1,
+			alt: self.alt.0,
+			alt_gr: self.alt.1,
+			meta: self.meta.0 || self.meta.1,
+			caps_lock: self.caps_lock,
+			num_lock: self.num_lock,
+			scroll_lock: self.scroll_lock,
+		}
+	}
+}
+
+/// A key going down or coming back up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+	/// Keys typed over serial come from a terminal, which only sends
+	/// characters. They get the US key that types the character, `Menus` when
+	/// there's none.
+	pub code: KeyCode,
+	pub state: KeyState,
+	/// The modifiers after this key, so pressing Shift comes with `shift` set.
+	pub modifiers: Modifiers,
+	/// What the layout makes of it. Only key presses decode to anything.
+	pub decoded: Option<DecodedKey>,
+}
+
+impl KeyEvent {
+	pub fn is_press(&self) -> bool {
+		self.state == KeyState::Down
+	}
+
+	/// The character the key typed, if it typed one.
+	pub fn character(&self) -> Option<char> {
+		match self.decoded {
+			Some(DecodedKey::Unicode(c)) => Some(c),
+			_ => None,
+		}
+	}
+}
+
+// Events a subscriber hasn't taken yet. Any more than that and it misses some.
+const EVENT_QUEUE_CAP: usize = 0x40;
+
+struct Subscriber {
+	queue: ArrayQueue<KeyEvent>,
+	waker: AtomicWaker,
+	// a `KeyStream`, which only gets what the console would have echoed
+	grabbed: bool,
+}
+
+impl Subscriber {
+	fn new(grabbed: bool) -> Arc<Subscriber> {
+		Arc::new(Subscriber {
+			queue: ArrayQueue::new(EVENT_QUEUE_CAP),
+			waker: AtomicWaker::new(),
+			grabbed,
+		})
+	}
+
+	fn wants(&self, event: &KeyEvent) -> bool {
+		!self.grabbed || (event.is_press() && event.decoded.is_some() && !is_hotkey(event))
+	}
+}
+
+// only touched by tasks, never by the interrupt handler
+static SUBSCRIBERS: Mutex<Vec<Arc<Subscriber>>> = Mutex::new(Vec::new());
+
+/// Every key event from now on, for as long as the stream is around.
+/// Any number of these can be listening at once, they all get every event.
+pub fn subscribe() -> KeyEvents {
+	let subscriber = Subscriber::new(false);
+	SUBSCRIBERS.lock().push(subscriber.clone());
+	KeyEvents { subscriber }
+}
+
+/// A stream of `KeyEvent`s, see `subscribe`.
+pub struct KeyEvents {
+	subscriber: Arc<Subscriber>,
+}
+
+impl Drop for KeyEvents {
+	fn drop(&mut self) {
+		SUBSCRIBERS.lock().retain(|subscriber| !Arc::ptr_eq(subscriber, &self.subscriber));
+	}
+}
+
+impl Stream for KeyEvents {
+	type Item = KeyEvent;
+
+	fn poll_next(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Option<KeyEvent>> {
+		let subscriber = &self.subscriber;
+
+		if let Some(event) = subscriber.queue.pop() {
+			return Poll::Ready(Some(event))
+		}
+
+		subscriber.waker.register(ctx.waker());
+
+		match subscriber.queue.pop() {
+			Some(event) => {
+				subscriber.waker.take();
+				Poll::Ready(Some(event))
+			}
+			None => Poll::Pending,
+		}
+	}
+}
+
+/// The key presses the console would have echoed, for programs that want to
+/// react to them instead, like the widgets in `tui`. Every event has a
+/// decoded key.
+pub struct KeyStream {
+	events: KeyEvents,
+}
+
+impl KeyStream {
+	/// Takes the keys away from the console until the stream is dropped.
+	/// Only one program can have them at a time, `None` while another does.
+	pub fn grab() -> Option<KeyStream> {
+		let mut subscribers = SUBSCRIBERS.lock();
+		if subscribers.iter().any(|subscriber| subscriber.grabbed) {
+			return None;
+		}
+		let subscriber = Subscriber::new(true);
+		subscribers.push(subscriber.clone());
+		Some(KeyStream { events: KeyEvents { subscriber } })
+	}
+}
+
+impl Stream for KeyStream {
+	type Item = KeyEvent;
+
+	fn poll_next(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Option<KeyEvent>> {
+		Pin::new(&mut self.events).poll_next(ctx)
+	}
+}
+
+/// Whether a `KeyStream` has the keys.
+pub fn is_grabbed() -> bool {
+	SUBSCRIBERS.lock().iter().any(|subscriber| subscriber.grabbed)
+}
+
+pub(super) fn publish(event: KeyEvent) {
+	for subscriber in SUBSCRIBERS.lock().iter().filter(|subscriber| subscriber.wants(&event)) {
+		// a subscriber that stopped listening shouldn't hold up the rest
+		if subscriber.queue.push(event).is_err() {
+			crate::debug!("a key event subscriber is full, dropping {:?}", event.code);
+		}
+		subscriber.waker.wake();
+	}
+}
+
+// pc_keyboard only turns scancodes into key codes for us, the layout it
+// gets here is never asked
+enum Scancodes {
+	Set1(pc_keyboard::Keyboard<pc_keyboard::layouts::Us104Key, pc_keyboard::ScancodeSet1>),
+	Set2(pc_keyboard::Keyboard<pc_keyboard::layouts::Us104Key, pc_keyboard::ScancodeSet2>),
+}
+
+impl Scancodes {
+	fn new(set: u8) -> Scancodes {
+		use pc_keyboard::{Keyboard, ScancodeSet1, ScancodeSet2, layouts, HandleControl};
+
+		match set {
+			2 => Scancodes::Set2(Keyboard::new(layouts::Us104Key, ScancodeSet2, HandleControl::Ignore)),
+			_ => Scancodes::Set1(Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore)),
+		}
+	}
+
+	fn add_byte(&mut self, byte: u8) -> Result<Option<pc_keyboard::KeyEvent>, pc_keyboard::Error> {
+		match self {
+			Scancodes::Set1(keyboard) => keyboard.add_byte(byte),
+			Scancodes::Set2(keyboard) => keyboard.add_byte(byte),
+		}
 	}
+}
 
+/// Turns scancodes into `KeyEvent`s for the subscribers, with whatever
+/// layout `set_layout` picked.
+/// The only task that may read the scancodes.
+pub async fn decode_keys() {
 	use futures_util::StreamExt;
+
+	let mut stream = ScancodeStream::new();
+	let mut scancodes = Scancodes::new(crate::ps2::scancode_set());
+	let mut modifiers = ModifierKeys::new();
+	modifiers.show_locks();
+
 	while let Some(scancode) = stream.next().await {
-		if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-			if let Some(keycode) = keyboard.process_keyevent(key_event) {
-				use crate::print;
-				match keycode {
-					DecodedKey::Unicode(c) => print!("{}", c),
-					DecodedKey::RawKey(c) => print!("{:?}", c),
-				}
+		if let Ok(Some(key_event)) = scancodes.add_byte(scancode) {
+			let code = key_event.code;
+			let state = key_event.state;
+			let pressed = state == KeyState::Down;
+			modifiers.update(code, pressed);
+			if pressed && matches!(code, KeyCode::CapsLock | KeyCode::NumpadLock | KeyCode::ScrollLock) {
+				modifiers.show_locks();
 			}
+			let decoded = match code {
+				// these only change how the other keys decode
+				KeyCode::ShiftLeft | KeyCode::ShiftRight
+				| KeyCode::ControlLeft | KeyCode::ControlRight
+				| KeyCode::AltRight | KeyCode::CapsLock | KeyCode::NumpadLock => None,
+				_ if pressed => Some(layout().map_keycode(code, &modifiers.pc_keyboard())),
+				_ => None,
+			};
+			publish(KeyEvent {
+				code,
+				state,
+				modifiers: modifiers.state(),
+				decoded,
+			});
 		}
 	}
 }
+
+/// Echoes what's typed on the console that's on screen, and handles the
+/// console hotkeys: Alt+F1 to Alt+F6 and Shift+PageUp/PageDown.
+pub async fn print_keypresses() {
+	let mut events = subscribe();
+
+	crate::debug!("Async key printer polled for the first time!");
+
+	use crate::vga_buffer::{CONSOLES, CursorShape, active_console, switch_console};
+	use x86_64::instructions::interrupts;
+
+	// the echo is much easier to follow with a cursor
+	for console in CONSOLES.iter() {
+		interrupts::without_interrupts(|| {
+			console.lock().show_cursor(CursorShape::Underline);
+		});
+	}
+
+	use futures_util::StreamExt;
+	while let Some(event) = events.next().await {
+		if !event.is_press() {
+			continue;
+		}
+
+		if is_hotkey(&event) {
+			match event.code {
+				KeyCode::PageUp | KeyCode::PageDown => interrupts::without_interrupts(|| {
+					let mut writer = CONSOLES[active_console()].lock();
+					match event.code {
+						KeyCode::PageUp => writer.scroll_page_up(),
+						_ => writer.scroll_page_down(),
+					}
+				}),
+				code => switch_console(console_hotkey(code).expect("only console hotkeys are left")),
+			}
+			continue;
+		}
+
+		if let Some(keycode) = event.decoded {
+			// a `KeyStream` has it
+			if is_grabbed() {
+				continue;
+			}
+
+			use crate::console_print;
+			// input goes to whichever console is on screen
+			let console = active_console();
+			match keycode {
+				DecodedKey::Unicode(c) => console_print!(console, "{}", c),
+				DecodedKey::RawKey(c) => console_print!(console, "{:?}", c),
+			}
+		}
+	}
+}
+
+/// The name of the layout keys are decoded with, for the status bar.
+pub fn layout_name() -> &'static str {
+	layout().name
+}
+
+// what `print_keypresses` keeps for itself, even from a `KeyStream`
+fn is_hotkey(event: &KeyEvent) -> bool {
+	match event.code {
+		KeyCode::PageUp | KeyCode::PageDown => event.modifiers.shift,
+		// not AltGr, that types characters
+		code => event.modifiers.alt && console_hotkey(code).is_some(),
+	}
+}
+
+/// Alt+F1 to Alt+F6 pick a virtual console.
+fn console_hotkey(code: KeyCode) -> Option<usize> {
+	match code {
+		KeyCode::F1 => Some(0),
+		KeyCode::F2 => Some(1),
+		KeyCode::F3 => Some(2),
+		KeyCode::F4 => Some(3),
+		KeyCode::F5 => Some(4),
+		KeyCode::F6 => Some(5),
+		_ => None,
+	}
+}
+
+
+#[test_case]
+fn test_key_event_subscribers() {
+	use futures_util::{FutureExt, StreamExt};
+
+	let mut modifiers = ModifierKeys::new();
+	modifiers.update(KeyCode::ShiftLeft, true);
+	modifiers.update(KeyCode::ShiftRight, true);
+	modifiers.update(KeyCode::ShiftLeft, false);
+	modifiers.update(KeyCode::CapsLock, true);
+	modifiers.update(KeyCode::CapsLock, false);
+	let state = modifiers.state();
+	assert!(state.shift && state.caps_lock && state.num_lock && !state.ctrl);
+
+	let mut shell = subscribe();
+	let mut hotkeys = subscribe();
+	let event = KeyEvent {
+		code: KeyCode::A,
+		state: KeyState::Down,
+		modifiers: state,
+		decoded: Some(DecodedKey::Unicode('A')),
+	};
+	publish(event);
+	assert_eq!(shell.next().now_or_never(), Some(Some(event)));
+	assert_eq!(hotkeys.next().now_or_never(), Some(Some(event)));
+	assert_eq!(shell.next().now_or_never(), None);
+
+	drop(hotkeys);
+	publish(event);
+	assert_eq!(shell.next().now_or_never(), Some(Some(event)));
+}
+
+#[test_case]
+fn test_key_stream() {
+	use futures_util::{FutureExt, StreamExt};
+
+	let mut keys = KeyStream::grab().expect("nothing else has the keys in tests");
+	assert!(is_grabbed() && KeyStream::grab().is_none());
+
+	let press = |code, modifiers, decoded| KeyEvent { code, state: KeyState::Down, modifiers, decoded };
+	let alt = Modifiers { alt: true, ..Modifiers::default() };
+	let alt_gr = Modifiers { alt_gr: true, ..Modifiers::default() };
+	let typed = press(KeyCode::F1, alt_gr, Some(DecodedKey::RawKey(KeyCode::F1)));
+	publish(press(KeyCode::F1, alt, Some(DecodedKey::RawKey(KeyCode::F1))));
+	publish(KeyEvent { state: KeyState::Up, decoded: None, ..typed });
+	publish(typed);
+	assert_eq!(keys.next().now_or_never(), Some(Some(typed)));
+	assert_eq!(keys.next().now_or_never(), None);
+
+	drop(keys);
+	assert!(!is_grabbed());
+}
@@ -0,0 +1,166 @@
+// Keyboard layouts that can be switched while running. The ones pc_keyboard
+// ships are used as they are, the others are tables of the keys that differ
+// from a US keyboard.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, KeyboardLayout, Modifiers};
+
+/// What a key types on its own, with Shift and with AltGr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+	pub code: KeyCode,
+	pub plain: char,
+	pub shifted: char,
+	pub alt_gr: Option<char>,
+}
+
+impl Key {
+	pub const fn new(code: KeyCode, plain: char, shifted: char) -> Key {
+		Key { code, plain, shifted, alt_gr: None }
+	}
+
+	pub const fn with_alt_gr(self, alt_gr: char) -> Key {
+		Key { alt_gr: Some(alt_gr), ..self }
+	}
+}
+
+type MapKeycode = fn(KeyCode, &Modifiers, HandleControl) -> DecodedKey;
+
+#[derive(Clone, Copy)]
+enum Keys {
+	PcKeyboard(MapKeycode),
+	// anything not in the table types what it would on a US keyboard
+	Table(&'static [Key]),
+}
+
+#[derive(Clone, Copy)]
+pub struct Layout {
+	pub name: &'static str,
+	keys: Keys,
+}
+
+impl Layout {
+	/// A layout pc_keyboard has.
+	pub const fn pc_keyboard(name: &'static str, map_keycode: MapKeycode) -> Layout {
+		Layout { name, keys: Keys::PcKeyboard(map_keycode) }
+	}
+
+	/// A layout that's only `keys` away from a US keyboard.
+	pub const fn table(name: &'static str, keys: &'static [Key]) -> Layout {
+		Layout { name, keys: Keys::Table(keys) }
+	}
+
+	/// What `code` types with `modifiers` held.
+	pub fn map_keycode(&self, code: KeyCode, modifiers: &Modifiers) -> DecodedKey {
+		let keys = match self.keys {
+			Keys::PcKeyboard(map_keycode) => return map_keycode(code, modifiers, HandleControl::Ignore),
+			Keys::Table(keys) => keys,
+		};
+
+		let key = match keys.iter().find(|key| key.code == code) {
+			Some(key) => key,
+			None => return layouts::Us104Key::map_keycode(code, modifiers, HandleControl::Ignore),
+		};
+		if modifiers.alt_gr {
+			if let Some(c) = key.alt_gr {
+				return DecodedKey::Unicode(c);
+			}
+		}
+		// caps lock is only for letters, the keys whose Shift gives the capital
+		let is_letter = key.plain.to_uppercase().eq(core::iter::once(key.shifted));
+		let shifted = match is_letter {
+			true => modifiers.is_caps(),
+			false => modifiers.is_shifted(),
+		};
+		DecodedKey::Unicode(if shifted { key.shifted } else { key.plain })
+	}
+}
+
+// QWERTZ. The key between left Shift and Y has no key code of its own in
+// pc_keyboard, so there's no < > | key.
+const GERMAN: &[Key] = &[
+	Key::new(KeyCode::BackTick, '^', '°'),
+	Key::new(KeyCode::Key2, '2', '"').with_alt_gr('²'),
+	Key::new(KeyCode::Key3, '3', '§').with_alt_gr('³'),
+	Key::new(KeyCode::Key6, '6', '&'),
+	Key::new(KeyCode::Key7, '7', '/').with_alt_gr('{'),
+	Key::new(KeyCode::Key8, '8', '(').with_alt_gr('['),
+	Key::new(KeyCode::Key9, '9', ')').with_alt_gr(']'),
+	Key::new(KeyCode::Key0, '0', '=').with_alt_gr('}'),
+	Key::new(KeyCode::Minus, 'ß', '?').with_alt_gr('\\'),
+	Key::new(KeyCode::Equals, '´', '`'),
+	Key::new(KeyCode::Q, 'q', 'Q').with_alt_gr('@'),
+	Key::new(KeyCode::E, 'e', 'E').with_alt_gr('€'),
+	Key::new(KeyCode::Z, 'y', 'Y'),
+	Key::new(KeyCode::Y, 'z', 'Z'),
+	Key::new(KeyCode::BracketSquareLeft, 'ü', 'Ü'),
+	Key::new(KeyCode::BracketSquareRight, '+', '*').with_alt_gr('~'),
+	Key::new(KeyCode::SemiColon, 'ö', 'Ö'),
+	Key::new(KeyCode::Quote, 'ä', 'Ä'),
+	Key::new(KeyCode::BackSlash, '#', '\''),
+	Key::new(KeyCode::M, 'm', 'M').with_alt_gr('µ'),
+	Key::new(KeyCode::Comma, ',', ';'),
+	Key::new(KeyCode::Fullstop, '.', ':'),
+	Key::new(KeyCode::Slash, '-', '_'),
+];
+
+pub const LAYOUTS: [Layout; 5] = [
+	Layout::pc_keyboard("us", <layouts::Us104Key as KeyboardLayout>::map_keycode),
+	Layout::pc_keyboard("uk", <layouts::Uk105Key as KeyboardLayout>::map_keycode),
+	Layout::table("de", GERMAN),
+	Layout::pc_keyboard("fr", <layouts::Azerty as KeyboardLayout>::map_keycode),
+	Layout::pc_keyboard("dvorak", <layouts::Dvorak104Key as KeyboardLayout>::map_keycode),
+];
+
+static LAYOUT: AtomicUsize = AtomicUsize::new(0);  // an index into `LAYOUTS`
+
+pub fn layout() -> &'static Layout {
+	&LAYOUTS[LAYOUT.load(Ordering::Relaxed)]
+}
+
+/// `set_layout` was given a name that isn't in `LAYOUTS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownLayout;
+
+/// Decodes keys with another layout from the next key on. Keys that are
+/// held down and the locks stay as they are.
+pub fn set_layout(name: &str) -> Result<(), UnknownLayout> {
+	let index = LAYOUTS.iter().position(|layout| layout.name == name).ok_or(UnknownLayout)?;
+	LAYOUT.store(index, Ordering::Relaxed);
+	Ok(())
+}
+
+
+#[test_case]
+fn test_layout_tables() {
+	let mut modifiers = Modifiers {
+		lshift: false,
+		rshift: false,
+		lctrl: false,
+		rctrl: false,
+		numlock: true,
+		capslock: false,
+		alt_gr: false,
+	};
+
+	assert_eq!(set_layout("klingon"), Err(UnknownLayout));
+	set_layout("de").expect("there is a German layout");
+	assert_eq!(layout().name, "de");
+
+	let german = layout();
+	assert_eq!(german.map_keycode(KeyCode::Y, &modifiers), DecodedKey::Unicode('z'));
+	assert_eq!(german.map_keycode(KeyCode::Enter, &modifiers), DecodedKey::Unicode('\n'));
+
+	modifiers.capslock = true;
+	assert_eq!(german.map_keycode(KeyCode::Quote, &modifiers), DecodedKey::Unicode('Ä'));
+	assert_eq!(german.map_keycode(KeyCode::Key7, &modifiers), DecodedKey::Unicode('7'));
+	assert_eq!(german.map_keycode(KeyCode::Minus, &modifiers), DecodedKey::Unicode('ß'));
+
+	modifiers.lshift = true;
+	assert_eq!(german.map_keycode(KeyCode::Key7, &modifiers), DecodedKey::Unicode('/'));
+
+	modifiers.alt_gr = true;
+	assert_eq!(german.map_keycode(KeyCode::Q, &modifiers), DecodedKey::Unicode('@'));
+
+	set_layout("us").expect("there is a US layout");
+}
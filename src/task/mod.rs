@@ -34,6 +34,9 @@ impl fmt::Debug for Task {
 
 pub mod basic_executor;
 pub mod keyboard;
+pub mod mouse;
+pub mod serial;
+pub mod timer;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 struct TaskId(u64);
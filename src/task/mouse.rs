@@ -0,0 +1,200 @@
+use crossbeam_queue::ArrayQueue;
+use conquer_once::spin::OnceCell;
+
+static PACKET_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+const PACKET_QUEUE_CAP: usize = 0x100;
+
+/// Call with every byte the mouse sends
+/// Must not block or allocate
+pub(crate) fn update_packet_queue(byte: u8) {
+	// we're in the interrupt handler, so printing could deadlock
+	use crate::irq_log;
+	use crate::log::Level;
+
+	// nobody's reading the mouse before there's a queue, nothing lost
+	if let Ok(queue) = PACKET_QUEUE.try_get() {
+		if queue.push(byte).is_err() {
+			irq_log!(Level::Warn, "mouse queue is full, dropping {:#04x}", byte)
+		} else {
+			WAKER.wake();
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Buttons {
+	pub left: bool,
+	pub right: bool,
+	pub middle: bool,
+}
+
+/// One packet from the mouse: how far it moved and which buttons are down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+	pub dx: i16,
+	/// Down is positive, like rows on the screen.
+	pub dy: i16,
+	/// Negative when the wheel turns away from you, to scroll up.
+	pub wheel: i8,
+	pub buttons: Buttons,
+}
+
+// the first byte of a packet
+const LEFT: u8 = 0x01;
+const RIGHT: u8 = 0x02;
+const MIDDLE: u8 = 0x04;
+const ALWAYS_SET: u8 = 0x08;
+const X_SIGN: u8 = 0x10;
+const Y_SIGN: u8 = 0x20;
+const X_OVERFLOW: u8 = 0x40;
+const Y_OVERFLOW: u8 = 0x80;
+
+fn decode(packet: &[u8]) -> MouseEvent {
+	let flags = packet[0];
+	let movement = |byte: u8, sign: u8, overflow: u8| match flags & overflow {
+		// the movement is garbage then
+		0 => i16::from(byte) - if flags & sign != 0 { 0x100 } else { 0 },
+		_ => 0,
+	};
+	// the wheel is the low 4 bits, five button mice use the rest for buttons
+	let wheel = match packet.get(3) {
+		Some(&z) => ((z << 4) as i8) >> 4,
+		None => 0,
+	};
+
+	MouseEvent {
+		dx: movement(packet[1], X_SIGN, X_OVERFLOW),
+		dy: -movement(packet[2], Y_SIGN, Y_OVERFLOW),
+		wheel,
+		buttons: Buttons {
+			left: flags & LEFT != 0,
+			right: flags & RIGHT != 0,
+			middle: flags & MIDDLE != 0,
+		},
+	}
+}
+
+pub struct MouseStream {
+	packet: [u8; 4],
+	len: usize,
+	size: usize,  // 4 with a wheel, 3 without
+}
+
+impl MouseStream {
+	pub fn new() -> Self {
+		use crate::ps2::{device, Device, Ps2Port};
+
+		PACKET_QUEUE.try_init_once(|| ArrayQueue::new(PACKET_QUEUE_CAP))
+			.expect("Mouse Queue must be iniitialised only once");
+		let size = match device(Ps2Port::Second) {
+			Some(Device::ScrollMouse) | Some(Device::FiveButtonMouse) => 4,
+			_ => 3,
+		};
+		MouseStream {
+			packet: [0; 4],
+			len: 0,
+			size,
+		}
+	}
+
+	// takes bytes from the queue until a packet is complete
+	fn next_packet(&mut self, queue: &ArrayQueue<u8>) -> Option<MouseEvent> {
+		while let Some(byte) = queue.pop() {
+			// a packet always starts with this bit set, skip ahead till one does
+			if self.len == 0 && byte & ALWAYS_SET == 0 {
+				continue;
+			}
+			self.packet[self.len] = byte;
+			self.len += 1;
+			if self.len == self.size {
+				self.len = 0;
+				return Some(decode(&self.packet[..self.size]));
+			}
+		}
+		None
+	}
+}
+
+use core::pin::Pin;
+use core::task::Poll;
+use core::task::Context;
+use futures_util::Stream;
+impl Stream for MouseStream {
+	type Item = MouseEvent;
+
+	fn poll_next(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Option<MouseEvent>> {
+		let queue = PACKET_QUEUE.try_get().expect("Queue not initialised");
+
+		if let Some(event) = self.next_packet(queue) {
+			return Poll::Ready(Some(event))
+		}
+
+		WAKER.register(ctx.waker());
+
+		match self.next_packet(queue) {
+			Some(event) => {
+				WAKER.take();
+				Poll::Ready(Some(event))
+			}
+			None => Poll::Pending,
+		}
+	}
+}
+
+use futures_util::task::AtomicWaker;
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+
+// how far the mouse has to move to get to the next cell
+const COUNTS_PER_COLUMN: i32 = 8;
+const COUNTS_PER_ROW: i32 = 16;
+// lines scrolled for every notch of the wheel
+const WHEEL_LINES: usize = 3;
+
+/// Moves the pointer on the console that's on screen, and scrolls it back
+/// and forth with the wheel.
+pub async fn track_pointer() {
+	use crate::vga_buffer::{active_console, set_pointer, text_mode, CONSOLES};
+	use futures_util::StreamExt;
+	use x86_64::instructions::interrupts;
+
+	let mut stream = MouseStream::new();
+	// in mouse counts, the pointer shows up once the mouse moves
+	let (mut x, mut y) = (0, 0);
+
+	while let Some(event) = stream.next().await {
+		if event.wheel != 0 {
+			let lines = WHEEL_LINES * usize::from(event.wheel.unsigned_abs());
+			interrupts::without_interrupts(|| {
+				let mut writer = CONSOLES[active_console()].lock();
+				match event.wheel < 0 {
+					true => writer.scroll_up(lines),
+					false => writer.scroll_down(lines),
+				}
+			});
+		}
+
+		if event.dx != 0 || event.dy != 0 {
+			let mode = text_mode();
+			x = (x + i32::from(event.dx)).clamp(0, mode.width() as i32 * COUNTS_PER_COLUMN - 1);
+			y = (y + i32::from(event.dy)).clamp(0, mode.height() as i32 * COUNTS_PER_ROW - 1);
+			set_pointer(Some(((y / COUNTS_PER_ROW) as usize, (x / COUNTS_PER_COLUMN) as usize)));
+		}
+	}
+}
+
+
+#[test_case]
+fn test_mouse_packets() {
+	// right and down, with the left button
+	let event = decode(&[ALWAYS_SET | LEFT | Y_SIGN, 5, 0xfd]);
+	assert_eq!((event.dx, event.dy, event.wheel), (5, 3, 0));
+	assert!(event.buttons.left && !event.buttons.right);
+
+	// left, with the wheel turned away
+	let event = decode(&[ALWAYS_SET | X_SIGN, 0xff, 0, 0x0f]);
+	assert_eq!((event.dx, event.dy, event.wheel), (-1, 0, -1));
+
+	let event = decode(&[ALWAYS_SET | X_OVERFLOW, 0x80, 2, 1]);
+	assert_eq!((event.dx, event.dy, event.wheel), (0, -2, 1));
+}
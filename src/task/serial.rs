@@ -0,0 +1,467 @@
+// What a terminal on the serial console types, turned into key events so it drives the
+// same programs the keyboard does.
+
+use crossbeam_queue::ArrayQueue;
+use conquer_once::spin::OnceCell;
+use pc_keyboard::{DecodedKey, KeyCode, KeyState};
+
+use super::keyboard::{KeyEvent, Modifiers};
+
+static INPUT_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+const INPUT_QUEUE_CAP: usize = 0x100;
+
+/// Call with every byte the serial console receives
+/// Must not block or allocate
+pub(crate) fn update_input_queue(byte: u8) {
+	// we're in the interrupt handler, so printing could deadlock
+	use crate::irq_log;
+	use crate::log::Level;
+
+	// without a queue nobody's reading the console yet, so nobody's typing there either
+	if let Ok(queue) = INPUT_QUEUE.try_get() {
+		if queue.push(byte).is_err() {
+			irq_log!(Level::Warn, "serial input queue is full, dropping {:#04x}", byte)
+		} else {
+			WAKER.wake();
+		}
+	}
+}
+
+/// The bytes the serial console receives.
+pub struct SerialStream {
+	_private: ()
+}
+
+impl SerialStream {
+	pub fn new() -> Self {
+		INPUT_QUEUE.try_init_once(|| ArrayQueue::new(INPUT_QUEUE_CAP))
+			.expect("Serial Input Queue must be iniitialised only once");
+		SerialStream{_private: ()}
+	}
+}
+
+use core::pin::Pin;
+use core::task::Poll;
+use core::task::Context;
+use futures_util::Stream;
+impl Stream for SerialStream {
+	type Item = u8;
+
+	fn poll_next(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Option<u8>> {
+		let queue = INPUT_QUEUE.try_get().expect("Queue not initialised");
+
+		if let Some(byte) = queue.pop() {
+			return Poll::Ready(Some(byte))
+		}
+
+		WAKER.register(ctx.waker());
+
+		match queue.pop() {
+			Some(byte) => {
+				WAKER.take();
+				Poll::Ready(Some(byte))
+			}
+			None => Poll::Pending,
+		}
+	}
+}
+
+use futures_util::task::AtomicWaker;
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+
+// Terminal input: characters as UTF-8, controls for Ctrl+letter, and
+// escape sequences for everything else, xterm style.
+
+const ESC: u8 = 0x1b;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+	Ground,
+	Escape,
+	// `ESC [`, with up to two parameters
+	Csi,
+	// `ESC O`, F1 to F4 and the cursor keys on some terminals
+	Ss3,
+}
+
+struct Decoder {
+	state: State,
+	params: [u16; 2],
+	param: usize,  // the one the digits go to
+	utf8: [u8; 4],
+	utf8_len: usize,
+	utf8_needed: usize,
+	after_cr: bool,
+}
+
+impl Decoder {
+	fn new() -> Decoder {
+		Decoder {
+			state: State::Ground,
+			params: [0; 2],
+			param: 0,
+			utf8: [0; 4],
+			utf8_len: 0,
+			utf8_needed: 0,
+			after_cr: false,
+		}
+	}
+
+	// the key a byte finishes, as a key press
+	fn advance(&mut self, byte: u8) -> Option<KeyEvent> {
+		// a character that was cut short is dropped
+		if self.utf8_len > 0 && byte & 0xc0 != 0x80 {
+			self.utf8_len = 0;
+		}
+
+		match self.state {
+			State::Ground => self.ground(byte),
+			State::Escape => {
+				self.state = State::Ground;
+				match byte {
+					b'[' => {
+						self.params = [0; 2];
+						self.param = 0;
+						self.state = State::Csi;
+						None
+					}
+					b'O' => {
+						self.state = State::Ss3;
+						None
+					}
+					// the first one was the Escape key
+					ESC => {
+						self.state = State::Escape;
+						Some(press(KeyCode::Escape, Modifiers::default()))
+					}
+					// that's how terminals send Alt
+					_ => self.ground(byte).map(|mut event| {
+						event.modifiers.alt = true;
+						event
+					}),
+				}
+			}
+			State::Csi => match byte {
+				b'0'..=b'9' => {
+					if let Some(param) = self.params.get_mut(self.param) {
+						*param = param.saturating_mul(10).saturating_add(u16::from(byte - b'0'));
+					}
+					None
+				}
+				b';' => {
+					self.param += 1;
+					None
+				}
+				0x40..=0x7e => {
+					self.state = State::Ground;
+					self.csi(byte)
+				}
+				_ => None,
+			},
+			State::Ss3 => {
+				self.state = State::Ground;
+				cursor_key(byte).map(|code| press(code, Modifiers::default()))
+			}
+		}
+	}
+
+	// halfway through an escape sequence, or an Escape key on its own
+	fn in_sequence(&self) -> bool {
+		self.state != State::Ground
+	}
+
+	// nothing came after an ESC, so it was the Escape key
+	fn timeout(&mut self) -> Option<KeyEvent> {
+		let state = core::mem::replace(&mut self.state, State::Ground);
+		match state {
+			State::Escape => Some(press(KeyCode::Escape, Modifiers::default())),
+			_ => None,
+		}
+	}
+
+	fn ground(&mut self, byte: u8) -> Option<KeyEvent> {
+		let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+		match byte {
+			ESC => {
+				self.state = State::Escape;
+				None
+			}
+			// some terminals send both for Enter
+			b'\n' if after_cr => None,
+			b'\r' | b'\n' => Some(press(KeyCode::Enter, Modifiers::default())),
+			// DEL is what most terminals send for Backspace
+			0x08 | 0x7f => Some(press(KeyCode::Backspace, Modifiers::default())),
+			b'\t' => Some(press(KeyCode::Tab, Modifiers::default())),
+			// Ctrl+A to Ctrl+Z, decoded like pc_keyboard does with the controls ignored
+			0x01..=0x1a => {
+				let letter = usize::from(byte - 1);
+				Some(KeyEvent {
+					code: LETTERS[letter],
+					state: KeyState::Down,
+					modifiers: Modifiers { ctrl: true, ..Modifiers::default() },
+					decoded: Some(DecodedKey::Unicode(char::from(b'a' + byte - 1))),
+				})
+			}
+			0x20..=0x7e => Some(character(char::from(byte))),
+			0x80..=0xff => self.utf8(byte),
+			_ => None,
+		}
+	}
+
+	fn csi(&self, final_byte: u8) -> Option<KeyEvent> {
+		let code = match final_byte {
+			b'~' => match self.params[0] {
+				1 | 7 => KeyCode::Home,
+				2 => KeyCode::Insert,
+				3 => KeyCode::Delete,
+				4 | 8 => KeyCode::End,
+				5 => KeyCode::PageUp,
+				6 => KeyCode::PageDown,
+				11 => KeyCode::F1,
+				12 => KeyCode::F2,
+				13 => KeyCode::F3,
+				14 => KeyCode::F4,
+				15 => KeyCode::F5,
+				17 => KeyCode::F6,
+				18 => KeyCode::F7,
+				19 => KeyCode::F8,
+				20 => KeyCode::F9,
+				21 => KeyCode::F10,
+				23 => KeyCode::F11,
+				24 => KeyCode::F12,
+				_ => return None,
+			},
+			// Shift+Tab
+			b'Z' => return Some(press(KeyCode::Tab, Modifiers { shift: true, ..Modifiers::default() })),
+			_ => cursor_key(final_byte)?,
+		};
+		// xterm puts the modifiers in the second parameter, plus one
+		let bits = self.params[1].saturating_sub(1);
+		let modifiers = Modifiers {
+			shift: bits & 1 != 0,
+			alt: bits & 2 != 0,
+			ctrl: bits & 4 != 0,
+			meta: bits & 8 != 0,
+			..Modifiers::default()
+		};
+		Some(press(code, modifiers))
+	}
+
+	fn utf8(&mut self, byte: u8) -> Option<KeyEvent> {
+		if self.utf8_len == 0 {
+			self.utf8_needed = match byte {
+				0xc0..=0xdf => 2,
+				0xe0..=0xef => 3,
+				0xf0..=0xf7 => 4,
+				// a continuation without a start, or no UTF-8 at all
+				_ => return None,
+			};
+		}
+		self.utf8[self.utf8_len] = byte;
+		self.utf8_len += 1;
+		if self.utf8_len < self.utf8_needed {
+			return None;
+		}
+
+		let len = core::mem::replace(&mut self.utf8_len, 0);
+		let c = core::str::from_utf8(&self.utf8[..len]).ok()?.chars().next()?;
+		Some(character(c))
+	}
+}
+
+// the last byte of `ESC [ A` and `ESC O A` and the like
+fn cursor_key(byte: u8) -> Option<KeyCode> {
+	Some(match byte {
+		b'A' => KeyCode::ArrowUp,
+		b'B' => KeyCode::ArrowDown,
+		b'C' => KeyCode::ArrowRight,
+		b'D' => KeyCode::ArrowLeft,
+		b'H' => KeyCode::Home,
+		b'F' => KeyCode::End,
+		b'P' => KeyCode::F1,
+		b'Q' => KeyCode::F2,
+		b'R' => KeyCode::F3,
+		b'S' => KeyCode::F4,
+		_ => return None,
+	})
+}
+
+// a key that doesn't type a character of its own, decoded the way the US
+// layout does it
+fn press(code: KeyCode, modifiers: Modifiers) -> KeyEvent {
+	let decoded = match code {
+		KeyCode::Enter => DecodedKey::Unicode('\n'),
+		KeyCode::Backspace => DecodedKey::Unicode('\x08'),
+		KeyCode::Tab => DecodedKey::Unicode('\t'),
+		KeyCode::Escape => DecodedKey::Unicode('\x1b'),
+		KeyCode::Delete => DecodedKey::Unicode('\x7f'),
+		_ => DecodedKey::RawKey(code),
+	};
+	KeyEvent {
+		code,
+		state: KeyState::Down,
+		modifiers,
+		decoded: Some(decoded),
+	}
+}
+
+const LETTERS: [KeyCode; 26] = [
+	KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G,
+	KeyCode::H, KeyCode::I, KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::M, KeyCode::N,
+	KeyCode::O, KeyCode::P, KeyCode::Q, KeyCode::R, KeyCode::S, KeyCode::T, KeyCode::U,
+	KeyCode::V, KeyCode::W, KeyCode::X, KeyCode::Y, KeyCode::Z,
+];
+
+const DIGITS: [KeyCode; 10] = [
+	KeyCode::Key0, KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4,
+	KeyCode::Key5, KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9,
+];
+
+// the rest of a US keyboard, plain and shifted
+const PUNCTUATION: [(char, char, KeyCode); 11] = [
+	('`', '~', KeyCode::BackTick),
+	('-', '_', KeyCode::Minus),
+	('=', '+', KeyCode::Equals),
+	('[', '{', KeyCode::BracketSquareLeft),
+	(']', '}', KeyCode::BracketSquareRight),
+	('\\', '|', KeyCode::BackSlash),
+	(';', ':', KeyCode::SemiColon),
+	('\'', '"', KeyCode::Quote),
+	(',', '<', KeyCode::Comma),
+	('.', '>', KeyCode::Fullstop),
+	('/', '?', KeyCode::Slash),
+];
+
+// a typed character, with the key that types it on a US keyboard
+fn character(c: char) -> KeyEvent {
+	let (code, shift) = match c {
+		'a'..='z' => (LETTERS[c as usize - 'a' as usize], false),
+		'A'..='Z' => (LETTERS[c as usize - 'A' as usize], true),
+		'0'..='9' => (DIGITS[c as usize - '0' as usize], false),
+		' ' => (KeyCode::Spacebar, false),
+		_ => match ")!@#$%^&*(".find(c) {
+			Some(digit) => (DIGITS[digit], true),
+			None => PUNCTUATION.iter()
+				.find(|&&(plain, shifted, _)| c == plain || c == shifted)
+				.map(|&(_, shifted, code)| (code, c == shifted))
+				.unwrap_or((KeyCode::Menus, false)),
+		},
+	};
+	KeyEvent {
+		code,
+		state: KeyState::Down,
+		modifiers: Modifiers { shift, ..Modifiers::default() },
+		decoded: Some(DecodedKey::Unicode(c)),
+	}
+}
+
+
+// a terminal sends a whole sequence at once, so an ESC with nothing after
+// it for this many ticks was the Escape key
+const ESCAPE_TICKS: u64 = 2;
+
+/// Turns what's typed on the serial console into `KeyEvent`s for the
+/// subscribers, just like `decode_keys` does for the keyboard.
+/// The only task that may read the serial console.
+pub async fn decode_serial() {
+	use futures_util::future::{select, Either};
+	use futures_util::StreamExt;
+
+	let mut stream = SerialStream::new();
+	let mut decoder = Decoder::new();
+
+	loop {
+		let byte = match decoder.in_sequence() {
+			false => stream.next().await,
+			true => match select(stream.next(), super::timer::sleep(ESCAPE_TICKS)).await {
+				Either::Left((byte, _)) => byte,
+				Either::Right(_) => {
+					if let Some(event) = decoder.timeout() {
+						type_key(event);
+					}
+					continue;
+				}
+			},
+		};
+
+		match byte {
+			Some(byte) => if let Some(event) = decoder.advance(byte) {
+				type_key(event);
+			},
+			None => return,
+		}
+	}
+}
+
+fn type_key(event: KeyEvent) {
+	use super::keyboard::publish;
+
+	echo(&event);
+	publish(event);
+	publish(KeyEvent { state: KeyState::Up, decoded: None, ..event });
+}
+
+// terminals leave echoing to the other end, a program with the keys
+// grabbed draws what it wants itself
+fn echo(event: &KeyEvent) {
+	use crate::serial::{port, send, Channel};
+
+	if super::keyboard::is_grabbed() {
+		return;
+	}
+	let com = match port(Channel::Console) {
+		Some(com) => com,
+		None => return,
+	};
+	if event.modifiers.ctrl || event.modifiers.alt {
+		return;
+	}
+	match event.decoded {
+		Some(DecodedKey::Unicode('\n')) => send(com, b"\r\n"),
+		Some(DecodedKey::Unicode('\x08')) => send(com, b"\x08 \x08"),
+		Some(DecodedKey::Unicode(c)) if !c.is_control() => {
+			let mut utf8 = [0; 4];
+			send(com, c.encode_utf8(&mut utf8).as_bytes());
+		}
+		_ => {}
+	}
+}
+
+
+#[test_case]
+fn test_terminal_keys() {
+	let mut decoder = Decoder::new();
+	let mut feed = |bytes: &[u8]| -> alloc::vec::Vec<KeyEvent> {
+		bytes.iter().filter_map(|&byte| decoder.advance(byte)).collect()
+	};
+
+	let typed = feed(b"hI!");
+	assert_eq!(typed.iter().map(|event| event.code).collect::<alloc::vec::Vec<_>>(), [KeyCode::H, KeyCode::I, KeyCode::Key1]);
+	assert!(!typed[0].modifiers.shift && typed[1].modifiers.shift);
+	assert_eq!(typed[2].character(), Some('!'));
+
+	// one Enter for \r\n, Backspace for DEL
+	let typed = feed(b"\r\n\x7f");
+	assert_eq!(typed.len(), 2);
+	assert_eq!((typed[0].character(), typed[1].code), (Some('\n'), KeyCode::Backspace));
+
+	let typed = feed("\x03é".as_bytes());
+	assert!(typed[0].modifiers.ctrl);
+	assert_eq!((typed[0].code, typed[0].character()), (KeyCode::C, Some('c')));
+	assert_eq!((typed[1].code, typed[1].character()), (KeyCode::Menus, Some('é')));
+
+	let typed = feed(b"\x1b[A\x1bOP\x1b[5;2~\x1bx");
+	assert_eq!(typed[0].decoded, Some(DecodedKey::RawKey(KeyCode::ArrowUp)));
+	assert_eq!(typed[1].code, KeyCode::F1);
+	assert_eq!(typed[2].code, KeyCode::PageUp);
+	assert!(typed[2].modifiers.shift);
+	assert!(typed[3].modifiers.alt);
+	assert_eq!(typed[3].character(), Some('x'));
+
+	// Escape on its own only shows up once nothing follows
+	assert!(feed(b"\x1b").is_empty());
+	assert!(decoder.in_sequence());
+	assert_eq!(decoder.timeout().map(|event| event.code), Some(KeyCode::Escape));
+	assert!(!decoder.in_sequence());
+}
@@ -0,0 +1,85 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+use futures_util::task::AtomicWaker;
+
+// How many tasks can sleep at once. Any more just keep getting polled.
+const SLEEPERS: usize = 16;
+const FREE: u64 = u64::MAX;
+
+// a slot is taken while its deadline isn't FREE
+static DEADLINES: [AtomicU64; SLEEPERS] = [const { AtomicU64::new(FREE) }; SLEEPERS];
+static WAKERS: [AtomicWaker; SLEEPERS] = [const { AtomicWaker::new() }; SLEEPERS];
+
+/// Called by the timer interrupt on every tick.
+/// Must not block or allocate
+pub(crate) fn tick(now: u64) {
+	for (deadline, waker) in DEADLINES.iter().zip(WAKERS.iter()) {
+		if deadline.load(Ordering::Relaxed) <= now {
+			waker.wake();
+		}
+	}
+}
+
+/// Waits until `ticks` more timer ticks have gone by.
+pub fn sleep(ticks: u64) -> Sleep {
+	Sleep {
+		deadline: crate::interrupts::ticks() + ticks,
+		slot: None,
+	}
+}
+
+pub struct Sleep {
+	deadline: u64,
+	slot: Option<usize>,
+}
+
+impl Future for Sleep {
+	type Output = ();
+
+	fn poll(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<()> {
+		if crate::interrupts::ticks() >= self.deadline {
+			self.release();
+			return Poll::Ready(());
+		}
+
+		if self.slot.is_none() {
+			self.slot = DEADLINES.iter().position(|slot| {
+				slot.compare_exchange(FREE, u64::MAX - 1, Ordering::AcqRel, Ordering::Relaxed).is_ok()
+			});
+		}
+
+		match self.slot {
+			Some(slot) => {
+				// the waker goes in before the deadline, so the tick can't miss it
+				WAKERS[slot].register(ctx.waker());
+				DEADLINES[slot].store(self.deadline, Ordering::Release);
+			}
+			// every slot is taken, try again straight away
+			None => ctx.waker().wake_by_ref(),
+		}
+
+		// the deadline may have passed while we registered
+		if crate::interrupts::ticks() >= self.deadline {
+			self.release();
+			return Poll::Ready(());
+		}
+		Poll::Pending
+	}
+}
+
+impl Sleep {
+	fn release(&mut self) {
+		if let Some(slot) = self.slot.take() {
+			DEADLINES[slot].store(FREE, Ordering::Release);
+			WAKERS[slot].take();
+		}
+	}
+}
+
+impl Drop for Sleep {
+	fn drop(&mut self) {
+		self.release();
+	}
+}
@@ -0,0 +1,374 @@
+//! Text UI building blocks for in-kernel tools: framed windows, list views,
+//! menus, input fields and dialogs, drawn with CP437 box characters on one of
+//! the virtual consoles and driven by a `KeyStream`.
+//!
+//! A `Widget` draws itself into a `Canvas`, which keeps it inside its area,
+//! and reacts to keys. `run_modal` puts one in a window, feeds it keys until
+//! it's done and then puts back whatever the window covered.
+//!
+//! The cells are drawn into the console like text is, so draw on a console
+//! nothing else is printing to.
+
+use alloc::vec::Vec;
+use pc_keyboard::DecodedKey;
+
+use crate::task::keyboard::KeyStream;
+use crate::vga_buffer::{cp437, theme, ColorCode, Writer, CONSOLES};
+
+mod list;
+mod input;
+mod dialog;
+
+pub use list::{ListView, Menu};
+pub use input::InputField;
+pub use dialog::Dialog;
+
+// the control characters pc_keyboard decodes these keys to
+pub const ENTER: char = '\n';
+pub const ESCAPE: char = '\x1b';
+pub const BACKSPACE: char = '\x08';
+pub const DELETE: char = '\x7f';
+pub const TAB: char = '\t';
+
+/// Some cells of a console, relative to whatever contains them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+	pub row: usize,
+	pub col: usize,
+	pub width: usize,
+	pub height: usize,
+}
+
+impl Rect {
+	pub const fn new(row: usize, col: usize, width: usize, height: usize) -> Rect {
+		Rect { row, col, width, height }
+	}
+
+	/// `width` by `height` in the middle of `outer`, shrunk to fit.
+	pub fn centered(outer: Rect, width: usize, height: usize) -> Rect {
+		let width = width.min(outer.width);
+		let height = height.min(outer.height);
+		Rect {
+			row: outer.row + (outer.height - height) / 2,
+			col: outer.col + (outer.width - width) / 2,
+			width,
+			height,
+		}
+	}
+
+	/// Everything but a border one cell thick.
+	pub fn inner(self) -> Rect {
+		Rect {
+			row: self.row + 1,
+			col: self.col + 1,
+			width: self.width.saturating_sub(2),
+			height: self.height.saturating_sub(2),
+		}
+	}
+}
+
+/// The colours widgets draw with, from the console theme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+	pub normal: ColorCode,
+	/// The selected item, the focused button.
+	pub highlight: ColorCode,
+}
+
+impl Style {
+	pub fn from_theme() -> Style {
+		let theme = theme();
+		Style {
+			normal: theme.text,
+			highlight: theme.status,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Border {
+	Single,
+	Double,
+}
+
+impl Border {
+	// corners top left, top right, bottom left, bottom right, then the
+	// horizontal and vertical lines
+	fn glyphs(self) -> [u8; 6] {
+		match self {
+			Border::Single => [0xda, 0xbf, 0xc0, 0xd9, 0xc4, 0xb3],
+			Border::Double => [0xc9, 0xbb, 0xc8, 0xbc, 0xcd, 0xba],
+		}
+	}
+}
+
+/// Part of a console that a widget can draw into. Everything outside is
+/// clipped, so a widget can't scribble over its neighbours.
+pub struct Canvas<'a> {
+	writer: &'a mut Writer,
+	area: Rect,  // in console cells
+}
+
+impl<'a> Canvas<'a> {
+	/// `area` is cut down to the console's text rows.
+	pub fn new(writer: &'a mut Writer, area: Rect) -> Canvas<'a> {
+		let width = area.width.min(writer.width().saturating_sub(area.col));
+		let height = area.height.min(writer.height().saturating_sub(area.row));
+		Canvas {
+			writer,
+			area: Rect { width, height, ..area },
+		}
+	}
+
+	pub fn width(&self) -> usize {
+		self.area.width
+	}
+
+	pub fn height(&self) -> usize {
+		self.area.height
+	}
+
+	/// A canvas for part of this one, `area` is relative to it.
+	pub fn sub(&mut self, area: Rect) -> Canvas<'_> {
+		let row = area.row.min(self.area.height);
+		let col = area.col.min(self.area.width);
+		Canvas {
+			writer: self.writer,
+			area: Rect {
+				row: self.area.row + row,
+				col: self.area.col + col,
+				width: area.width.min(self.area.width - col),
+				height: area.height.min(self.area.height - row),
+			},
+		}
+	}
+
+	pub fn put(&mut self, row: usize, col: usize, glyph: u8, color: ColorCode) {
+		if row < self.area.height && col < self.area.width {
+			self.writer.put_cell(self.area.row + row, self.area.col + col, glyph, color);
+		}
+	}
+
+	/// Writes `text` on one row, cut off at the edge. Returns how many cells it took.
+	pub fn text(&mut self, row: usize, col: usize, text: &str, color: ColorCode) -> usize {
+		let mut written = 0;
+		for (i, c) in text.chars().enumerate() {
+			if col + i >= self.area.width {
+				break;
+			}
+			let glyph = cp437::from_char(c).unwrap_or(cp437::REPLACEMENT);
+			self.put(row, col + i, glyph, color);
+			written += 1;
+		}
+		written
+	}
+
+	pub fn fill(&mut self, glyph: u8, color: ColorCode) {
+		for row in 0..self.area.height {
+			for col in 0..self.area.width {
+				self.put(row, col, glyph, color);
+			}
+		}
+	}
+
+	/// A border around the edge, with `title` in the top of it.
+	pub fn frame(&mut self, border: Border, title: &str, color: ColorCode) {
+		let (width, height) = (self.area.width, self.area.height);
+		if width < 2 || height < 2 {
+			return;
+		}
+
+		let [top_left, top_right, bottom_left, bottom_right, horizontal, vertical] = border.glyphs();
+		for col in 1..width - 1 {
+			self.put(0, col, horizontal, color);
+			self.put(height - 1, col, horizontal, color);
+		}
+		for row in 1..height - 1 {
+			self.put(row, 0, vertical, color);
+			self.put(row, width - 1, vertical, color);
+		}
+		self.put(0, 0, top_left, color);
+		self.put(0, width - 1, top_right, color);
+		self.put(height - 1, 0, bottom_left, color);
+		self.put(height - 1, width - 1, bottom_right, color);
+
+		if !title.is_empty() && width > 4 {
+			let mut title_area = self.sub(Rect::new(0, 2, width - 4, 1));
+			let len = title_area.text(0, 1, title, color);
+			title_area.put(0, 0, b' ', color);
+			title_area.put(0, len + 1, b' ', color);
+		}
+	}
+}
+
+/// Something that draws itself and reacts to keys.
+pub trait Widget {
+	/// Draws the whole widget, `canvas` is exactly its area.
+	fn draw(&self, canvas: &mut Canvas, style: &Style);
+
+	fn handle_key(&mut self, key: DecodedKey) -> Response;
+
+	/// Where the hardware cursor should be in the widget's area, if anywhere.
+	fn cursor(&self) -> Option<(usize, usize)> {
+		None
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Response {
+	/// The key meant nothing to the widget.
+	Ignored,
+	/// The widget changed and has to be drawn again.
+	Changed,
+	/// Enter, the user is done.
+	Submit,
+	/// Escape, the user gave up.
+	Cancel,
+}
+
+/// A framed area with a title, the widget goes inside the frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window<'a> {
+	pub title: &'a str,
+	pub area: Rect,
+	pub border: Border,
+}
+
+impl<'a> Window<'a> {
+	/// A window for `width` by `height` cells of content in the middle of the
+	/// console's text.
+	pub fn centered(title: &'a str, width: usize, height: usize) -> Window<'a> {
+		let mode = crate::vga_buffer::text_mode();
+		// the status row might be there, so one row less to be safe
+		let screen = Rect::new(0, 0, mode.width(), mode.height() - 1);
+		Window {
+			title,
+			area: Rect::centered(screen, width + 2, height + 2),
+			border: Border::Double,
+		}
+	}
+
+	/// Blanks the window and draws the frame, returns the canvas inside it.
+	pub fn draw<'w>(&self, writer: &'w mut Writer, style: &Style) -> Canvas<'w> {
+		let mut canvas = Canvas::new(writer, self.area);
+		canvas.fill(b' ', style.normal);
+		canvas.frame(self.border, self.title, style.normal);
+		let inner = canvas.area.inner();
+		Canvas::new(canvas.writer, inner)
+	}
+}
+
+// what a window covered, to be put back when it closes
+struct Saved {
+	area: Rect,
+	cells: Vec<(u8, ColorCode)>,
+}
+
+impl Saved {
+	fn take(writer: &Writer, area: Rect) -> Saved {
+		let mut cells = Vec::with_capacity(area.width * area.height);
+		for row in area.row..area.row + area.height {
+			for col in area.col..area.col + area.width {
+				cells.push(writer.cell(row, col).unwrap_or((b' ', theme().text)));
+			}
+		}
+		Saved { area, cells }
+	}
+
+	fn restore(&self, writer: &mut Writer) {
+		let mut cells = self.cells.iter();
+		for row in self.area.row..self.area.row + self.area.height {
+			for col in self.area.col..self.area.col + self.area.width {
+				if let Some(&(glyph, color)) = cells.next() {
+					writer.put_cell(row, col, glyph, color);
+				}
+			}
+		}
+	}
+}
+
+/// Shows `widget` in `window` on `console` and feeds it keys until it's
+/// submitted or cancelled, then puts back what the window covered.
+/// Returns `Response::Submit` or `Response::Cancel`.
+pub async fn run_modal(
+	console: usize, window: &Window<'_>, widget: &mut dyn Widget, keys: &mut KeyStream
+) -> Response {
+	use futures_util::StreamExt;
+	use x86_64::instructions::interrupts;
+
+	let style = Style::from_theme();
+	let (saved, position, cursor) = interrupts::without_interrupts(|| {
+		let writer = CONSOLES[console].lock();
+		(Saved::take(&writer, window.area), writer.position(), writer.cursor_shape())
+	});
+
+	let draw = |widget: &dyn Widget| interrupts::without_interrupts(|| {
+		let mut writer = CONSOLES[console].lock();
+		let mut canvas = window.draw(&mut writer, &style);
+		widget.draw(&mut canvas, &style);
+		let area = canvas.area;
+		match widget.cursor() {
+			Some((row, col)) => {
+				writer.set_position(area.row + row, area.col + col);
+				writer.show_cursor(cursor.unwrap_or(crate::vga_buffer::CursorShape::Underline));
+			}
+			None => {
+				writer.hide_cursor();
+				writer.flush();
+			}
+		}
+	});
+
+	draw(widget);
+	let response = loop {
+		let key = match keys.next().await {
+			Some(event) => event.decoded.expect("grabbed keys are decoded"),
+			None => break Response::Cancel,
+		};
+		match widget.handle_key(key) {
+			Response::Ignored => {}
+			Response::Changed => draw(widget),
+			done => break done,
+		}
+	};
+
+	interrupts::without_interrupts(|| {
+		let mut writer = CONSOLES[console].lock();
+		saved.restore(&mut writer);
+		// `restore` only marks the rows, the dialog stays on screen till a flush
+		writer.flush();
+		writer.set_position(position.0, position.1);
+		match cursor {
+			Some(shape) => writer.show_cursor(shape),
+			None => writer.hide_cursor(),
+		}
+	});
+	response
+}
+
+
+#[test_case]
+fn test_window_frame() {
+	use x86_64::instructions::interrupts;
+
+	let style = Style::from_theme();
+	let window = Window {
+		title: "Hi",
+		area: Rect::new(1, 1, 8, 4),
+		border: Border::Single,
+	};
+	interrupts::without_interrupts(|| {
+		let mut writer = CONSOLES[3].lock();
+		let mut canvas = window.draw(&mut writer, &style);
+		assert_eq!((canvas.width(), canvas.height()), (6, 2));
+		// cut off at the frame
+		assert_eq!(canvas.text(0, 0, "overflowing", style.normal), 6);
+
+		let glyph = |row, col| writer.cell(row, col).unwrap().0;
+		assert_eq!(glyph(1, 1), 0xda);
+		assert_eq!(glyph(4, 8), 0xd9);
+		assert_eq!((glyph(1, 3), glyph(1, 4), glyph(1, 5), glyph(1, 6)), (b' ', b'H', b'i', b' '));
+		assert_eq!(glyph(2, 2), b'o');
+		assert_eq!(glyph(2, 8), 0xb3);
+	});
+}
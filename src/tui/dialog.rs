@@ -0,0 +1,83 @@
+use pc_keyboard::{DecodedKey, KeyCode};
+
+use super::{Canvas, Rect, Response, Style, Widget, ENTER, ESCAPE, TAB};
+
+/// A message with a row of buttons under it, like "OK" and "Cancel".
+pub struct Dialog<'a> {
+	message: &'a str,
+	buttons: &'a [&'a str],
+	focused: usize,
+}
+
+impl<'a> Dialog<'a> {
+	pub fn new(message: &'a str, buttons: &'a [&'a str]) -> Dialog<'a> {
+		Dialog {
+			message,
+			buttons,
+			focused: 0,
+		}
+	}
+
+	/// The index of the button that was pressed, or is about to be.
+	pub fn focused(&self) -> usize {
+		self.focused
+	}
+
+	/// How big the inside of a window has to be for all of it.
+	pub fn size(&self) -> (usize, usize) {
+		let message = self.message.lines().map(|line| line.chars().count()).max().unwrap_or(0);
+		let buttons: usize = self.buttons.iter().map(|button| button.chars().count() + 5).sum();
+		// a blank row before the buttons
+		let height = self.message.lines().count() + 2;
+		(message.max(buttons) + 2, height)
+	}
+
+	fn focus(&mut self, button: usize) -> Response {
+		if button == self.focused || button >= self.buttons.len() {
+			return Response::Ignored;
+		}
+		self.focused = button;
+		Response::Changed
+	}
+}
+
+impl Widget for Dialog<'_> {
+	fn draw(&self, canvas: &mut Canvas, style: &Style) {
+		for (row, line) in self.message.lines().enumerate() {
+			canvas.text(row, 1, line, style.normal);
+		}
+
+		// the buttons go on the bottom row, on the right
+		let width: usize = self.buttons.iter().map(|button| button.chars().count() + 5).sum();
+		let mut col = canvas.width().saturating_sub(width);
+		let row = canvas.height().saturating_sub(1);
+		for (i, button) in self.buttons.iter().enumerate() {
+			let color = match i == self.focused {
+				true => style.highlight,
+				false => style.normal,
+			};
+			let len = button.chars().count() + 4;
+			let mut cell = canvas.sub(Rect::new(row, col, len, 1));
+			cell.fill(b' ', color);
+			cell.text(0, 0, "<", color);
+			cell.text(0, 2, button, color);
+			cell.text(0, len - 1, ">", color);
+			col += len + 1;
+		}
+	}
+
+	fn handle_key(&mut self, key: DecodedKey) -> Response {
+		let last = self.buttons.len().saturating_sub(1);
+		match key {
+			DecodedKey::Unicode(ENTER) => Response::Submit,
+			DecodedKey::Unicode(ESCAPE) => Response::Cancel,
+			DecodedKey::Unicode(TAB) => self.focus(match self.focused == last {
+				true => 0,
+				false => self.focused + 1,
+			}),
+			DecodedKey::RawKey(KeyCode::ArrowLeft) => self.focus(self.focused.saturating_sub(1)),
+			DecodedKey::RawKey(KeyCode::ArrowRight) => self.focus(self.focused + 1),
+			_ => Response::Ignored,
+		}
+	}
+}
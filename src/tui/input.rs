@@ -0,0 +1,134 @@
+use alloc::string::String;
+use core::cell::Cell;
+use pc_keyboard::{DecodedKey, KeyCode};
+
+use super::{Canvas, Response, Style, Widget, BACKSPACE, DELETE, ENTER, ESCAPE};
+
+/// One line of text to type into, scrolled sideways if it gets too long.
+pub struct InputField {
+	text: String,
+	cursor: usize,  // in chars, not bytes
+	// the first char on screen and how many fit, from the last draw
+	scroll: Cell<usize>,
+	width: Cell<usize>,
+}
+
+impl InputField {
+	pub fn new() -> InputField {
+		InputField::with_text(String::new())
+	}
+
+	/// Starts with `text` in it and the cursor at the end.
+	pub fn with_text(text: String) -> InputField {
+		InputField {
+			cursor: text.chars().count(),
+			text,
+			scroll: Cell::new(0),
+			width: Cell::new(1),
+		}
+	}
+
+	pub fn text(&self) -> &str {
+		&self.text
+	}
+
+	pub fn into_text(self) -> String {
+		self.text
+	}
+
+	fn byte_index(&self, char_index: usize) -> usize {
+		self.text.char_indices().nth(char_index).map_or(self.text.len(), |(i, _)| i)
+	}
+
+	fn move_cursor(&mut self, to: usize) -> Response {
+		let to = to.min(self.text.chars().count());
+		if to == self.cursor {
+			return Response::Ignored;
+		}
+		self.cursor = to;
+		Response::Changed
+	}
+}
+
+impl Default for InputField {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Widget for InputField {
+	fn draw(&self, canvas: &mut Canvas, style: &Style) {
+		// one cell more for the cursor after the last char
+		let width = canvas.width().max(1);
+		let mut scroll = self.scroll.get();
+		if self.cursor < scroll {
+			scroll = self.cursor;
+		} else if self.cursor >= scroll + width {
+			scroll = self.cursor + 1 - width;
+		}
+		self.scroll.set(scroll);
+		self.width.set(width);
+
+		let mut line = canvas.sub(super::Rect::new(0, 0, width, 1));
+		line.fill(b' ', style.highlight);
+		let start = self.byte_index(scroll);
+		line.text(0, 0, &self.text[start..], style.highlight);
+	}
+
+	fn handle_key(&mut self, key: DecodedKey) -> Response {
+		match key {
+			DecodedKey::Unicode(ENTER) => Response::Submit,
+			DecodedKey::Unicode(ESCAPE) => Response::Cancel,
+			DecodedKey::Unicode(BACKSPACE) => {
+				if self.cursor == 0 {
+					return Response::Ignored;
+				}
+				self.cursor -= 1;
+				let at = self.byte_index(self.cursor);
+				self.text.remove(at);
+				Response::Changed
+			}
+			DecodedKey::Unicode(DELETE) => {
+				if self.cursor == self.text.chars().count() {
+					return Response::Ignored;
+				}
+				let at = self.byte_index(self.cursor);
+				self.text.remove(at);
+				Response::Changed
+			}
+			DecodedKey::Unicode(c) if !c.is_control() => {
+				let at = self.byte_index(self.cursor);
+				self.text.insert(at, c);
+				self.cursor += 1;
+				Response::Changed
+			}
+			DecodedKey::RawKey(KeyCode::ArrowLeft) => self.move_cursor(self.cursor.saturating_sub(1)),
+			DecodedKey::RawKey(KeyCode::ArrowRight) => self.move_cursor(self.cursor + 1),
+			DecodedKey::RawKey(KeyCode::Home) => self.move_cursor(0),
+			DecodedKey::RawKey(KeyCode::End) => self.move_cursor(usize::MAX),
+			_ => Response::Ignored,
+		}
+	}
+
+	fn cursor(&self) -> Option<(usize, usize)> {
+		Some((0, self.cursor - self.scroll.get()))
+	}
+}
+
+
+#[test_case]
+fn test_input_field_editing() {
+	let mut field = InputField::new();
+	for c in "helo".chars() {
+		field.handle_key(DecodedKey::Unicode(c));
+	}
+	field.handle_key(DecodedKey::RawKey(KeyCode::ArrowLeft));
+	field.handle_key(DecodedKey::Unicode('l'));
+	assert_eq!(field.text(), "hello");
+
+	field.handle_key(DecodedKey::RawKey(KeyCode::Home));
+	assert_eq!(field.handle_key(DecodedKey::Unicode(BACKSPACE)), Response::Ignored);
+	field.handle_key(DecodedKey::Unicode(DELETE));
+	assert_eq!(field.text(), "ello");
+	assert_eq!(field.handle_key(DecodedKey::Unicode(ENTER)), Response::Submit);
+}
@@ -0,0 +1,152 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use pc_keyboard::{DecodedKey, KeyCode};
+
+use super::{Canvas, Response, Style, Widget, ENTER, ESCAPE};
+
+/// Lines to pick from, one of them selected. Scrolls to keep the selection
+/// in sight.
+pub struct ListView {
+	items: Vec<String>,
+	selected: usize,
+	// the first item on screen and how many fit, from the last draw
+	top: Cell<usize>,
+	visible: Cell<usize>,
+}
+
+impl ListView {
+	pub fn new(items: Vec<String>) -> ListView {
+		ListView {
+			items,
+			selected: 0,
+			top: Cell::new(0),
+			visible: Cell::new(1),
+		}
+	}
+
+	pub fn items(&self) -> &[String] {
+		&self.items
+	}
+
+	/// The index of the selected item, `None` if there are no items.
+	pub fn selected(&self) -> Option<usize> {
+		match self.items.is_empty() {
+			true => None,
+			false => Some(self.selected),
+		}
+	}
+
+	pub fn select(&mut self, index: usize) {
+		self.selected = index.min(self.items.len().saturating_sub(1));
+	}
+
+	// moves the selection by `by` items, stopping at either end
+	fn move_selection(&mut self, by: isize) -> Response {
+		let last = self.items.len().saturating_sub(1) as isize;
+		let selected = (self.selected as isize + by).clamp(0, last) as usize;
+		if selected == self.selected {
+			return Response::Ignored;
+		}
+		self.selected = selected;
+		Response::Changed
+	}
+}
+
+impl Widget for ListView {
+	fn draw(&self, canvas: &mut Canvas, style: &Style) {
+		let visible = canvas.height().max(1);
+		let mut top = self.top.get();
+		if self.selected < top {
+			top = self.selected;
+		} else if self.selected >= top + visible {
+			top = self.selected + 1 - visible;
+		}
+		self.top.set(top);
+		self.visible.set(visible);
+
+		for (row, (index, item)) in self.items.iter().enumerate().skip(top).take(visible).enumerate() {
+			let color = match index == self.selected {
+				true => style.highlight,
+				false => style.normal,
+			};
+			let mut line = canvas.sub(super::Rect::new(row, 0, canvas.width(), 1));
+			line.fill(b' ', color);
+			line.text(0, 1, item, color);
+		}
+	}
+
+	fn handle_key(&mut self, key: DecodedKey) -> Response {
+		let page = self.visible.get() as isize;
+		match key {
+			DecodedKey::Unicode(ENTER) if !self.items.is_empty() => Response::Submit,
+			DecodedKey::Unicode(ESCAPE) => Response::Cancel,
+			DecodedKey::RawKey(KeyCode::ArrowUp) => self.move_selection(-1),
+			DecodedKey::RawKey(KeyCode::ArrowDown) => self.move_selection(1),
+			DecodedKey::RawKey(KeyCode::PageUp) => self.move_selection(-page),
+			DecodedKey::RawKey(KeyCode::PageDown) => self.move_selection(page),
+			DecodedKey::RawKey(KeyCode::Home) => self.move_selection(isize::MIN / 2),
+			DecodedKey::RawKey(KeyCode::End) => self.move_selection(isize::MAX / 2),
+			_ => Response::Ignored,
+		}
+	}
+}
+
+/// A list of commands. Besides the arrows, typing the first letter of an
+/// entry picks it straight away.
+pub struct Menu {
+	list: ListView,
+}
+
+impl Menu {
+	pub fn new(entries: &[&str]) -> Menu {
+		Menu {
+			list: ListView::new(entries.iter().map(|&entry| String::from(entry)).collect()),
+		}
+	}
+
+	/// The index of the chosen entry.
+	pub fn selected(&self) -> Option<usize> {
+		self.list.selected()
+	}
+}
+
+impl Widget for Menu {
+	fn draw(&self, canvas: &mut Canvas, style: &Style) {
+		self.list.draw(canvas, style);
+	}
+
+	fn handle_key(&mut self, key: DecodedKey) -> Response {
+		if let DecodedKey::Unicode(c) = key {
+			if c.is_alphanumeric() {
+				let hotkey = self.list.items.iter().position(|entry| {
+					entry.chars().next().is_some_and(|first| first.eq_ignore_ascii_case(&c))
+				});
+				if let Some(index) = hotkey {
+					self.list.select(index);
+					return Response::Submit;
+				}
+			}
+		}
+		self.list.handle_key(key)
+	}
+}
+
+
+#[test_case]
+fn test_list_keys() {
+	use alloc::string::ToString;
+
+	let mut list = ListView::new((0..10).map(|i| i.to_string()).collect());
+	assert_eq!(list.handle_key(DecodedKey::RawKey(KeyCode::ArrowUp)), Response::Ignored);
+	assert_eq!(list.handle_key(DecodedKey::RawKey(KeyCode::ArrowDown)), Response::Changed);
+	assert_eq!(list.selected(), Some(1));
+	list.handle_key(DecodedKey::RawKey(KeyCode::End));
+	assert_eq!(list.selected(), Some(9));
+	assert_eq!(list.handle_key(DecodedKey::Unicode(ENTER)), Response::Submit);
+
+	let mut menu = Menu::new(&["Open", "Save", "Quit"]);
+	assert_eq!(menu.handle_key(DecodedKey::Unicode('s')), Response::Submit);
+	assert_eq!(menu.selected(), Some(1));
+	assert_eq!(menu.handle_key(DecodedKey::Unicode(ESCAPE)), Response::Cancel);
+}
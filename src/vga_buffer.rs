@@ -1,6 +1,23 @@
-use volatile::Volatile;
 use core::fmt;
 
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::VirtAddr;
+
+pub mod registers;
+pub mod cp437;
+mod scrollback;
+mod modes;
+pub mod font;
+pub mod graphics;
+mod theme;
+mod snapshot;
+
+use scrollback::Scrollback;
+pub use font::Font;
+pub use modes::TextMode;
+pub use theme::{set_theme, theme, Theme, UnknownTheme, THEMES};
+pub use snapshot::{Diff, Snapshot};
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -23,14 +40,77 @@ pub enum Color {
 	White = 15,
 }
 
+impl Color {
+	const ALL: [Color; 16] = [
+		Color::Black, Color::Blue, Color::Green, Color::Cyan,
+		Color::Red, Color::Magenta, Color::Brown, Color::LightGrey,
+		Color::DarkGrey, Color::LightBlue, Color::LightGreen, Color::LightCyan,
+		Color::LightRed, Color::Pink, Color::Yellow, Color::White,
+	];
+
+	fn from_index(index: u8) -> Color {
+		Color::ALL[usize::from(index & 0x0f)]
+	}
+}
+
+/// A foreground and a background colour, the way a cell stores them.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
-struct ColorCode(u8);
+pub struct ColorCode(u8);
 
 impl ColorCode {
-	fn new(foreground: Color, background: Color) -> ColorCode {
+	pub const fn new(foreground: Color, background: Color) -> ColorCode {
 		ColorCode(((background as u8) << 4)| (foreground as u8))
 	}
+
+	pub fn foreground(self) -> Color {
+		Color::from_index(self.0)
+	}
+
+	pub fn background(self) -> Color {
+		Color::from_index(self.0 >> 4)
+	}
+
+	/// The escape sequence that switches to these colours, for text that
+	/// goes to the serial port as well.
+	pub fn ansi(self) -> AnsiColor {
+		AnsiColor(self)
+	}
+
+	fn with_foreground(self, foreground: u8) -> ColorCode {
+		ColorCode((self.0 & 0xf0) | (foreground & 0x0f))
+	}
+
+	fn with_background(self, background: u8) -> ColorCode {
+		ColorCode((self.0 & 0x0f) | ((background & 0x0f) << 4))
+	}
+
+	fn reversed(self) -> ColorCode {
+		ColorCode(self.0.rotate_left(4))
+	}
+}
+
+// ANSI orders its colours differently from the VGA palette.
+// black, red, green, yellow, blue, magenta, cyan, white
+// Only blue and red, and cyan and yellow swap, so it works both ways.
+const ANSI_TO_VGA: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+const BRIGHT: u8 = 0x08;
+
+/// Prints as `ESC [ ... m` with the foreground and background of a `ColorCode`.
+#[derive(Debug, Clone, Copy)]
+pub struct AnsiColor(ColorCode);
+
+impl fmt::Display for AnsiColor {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		// the bright colours have codes of their own, 60 higher
+		fn code(color: u8, base: u8) -> u8 {
+			let bright = if color & BRIGHT != 0 { 60 } else { 0 };
+			base + bright + ANSI_TO_VGA[usize::from(color & 0x07)]
+		}
+
+		let color = (self.0).0;
+		write!(f, "\x1b[{};{}m", code(color & 0x0f, 30), code(color >> 4, 40))
+	}
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -40,60 +120,613 @@ struct ScreenChar {
 	color_code: ColorCode,
 }
 
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH:  usize = 80;
+// big enough for any of the text modes
+const MAX_HEIGHT: usize = 60;
+const MAX_WIDTH:  usize = 90;
 
-#[repr(transparent)]
-struct Buffer {
-	chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
-}
+const DEFAULT_COLOR: ColorCode = ColorCode::new(Color::LightRed, Color::Black);
+const BLANK: ScreenChar = ScreenChar {
+	ascii_character: b' ',
+	color_code: DEFAULT_COLOR,
+};
+
+// only the top left `width` by `height` cells are on screen
+type Line = [ScreenChar; MAX_WIDTH];
+type Cells = [Line; MAX_HEIGHT];
 
 pub struct Writer {
+	width: usize,
+	height: usize,
 	column_position: usize,
+	row_position: usize,
+	saved_position: (usize, usize),
 	color_code: ColorCode,
-	buffer: &'static mut Buffer,
+	reversed: bool,  // `color_code` is swapped by `ESC [ 7 m`
+	default_color: ColorCode,
+	parser: Parser,
+	// the colours `push_color` saved, the ones past the end weren't kept
+	color_stack: [(ColorCode, bool); COLOR_SCOPES],
+	color_depth: usize,
+	cursor: Option<CursorShape>,  // None when the cursor is hidden
+	scrollback: Option<Scrollback>,  // needs the heap, so it starts out disabled
+	// the live screen, drawn in RAM and copied to the VGA memory by `flush`
+	shadow: &'static mut Cells,
+	dirty: u64,  // a bit for every row that changed since the last flush
+	scrolled: usize,  // lines scrolled since the last flush
+	visible: bool,
+	// the row below the text is kept for `set_status`
+	status_row: bool,
 }
 
+/// How deep `push_color` can nest and still restore the colour.
+pub const COLOR_SCOPES: usize = 8;
+
+/// How the hardware cursor is drawn in its cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+	Underline,
+	Block,
+}
+
+use crate::ansi::{Action, Csi, Parser};
+
 impl Writer {
 	pub fn write_byte(&mut self, byte: u8) {
+		self.snap_to_live();
+		self.put_byte(byte);
+		self.flush();
+	}
+
+	// a byte into the shadow, it's on screen after the next flush
+	fn put_byte(&mut self, byte: u8) {
 		match byte {
 			b'\n' => self.new_line(),
 			byte => {
-				if self.column_position >= BUFFER_WIDTH {
+				if self.column_position >= self.width {
 					self.new_line();
 				}
 
-				let row = BUFFER_HEIGHT - 1;
+				let row = self.row_position;
 				let col = self.column_position;
 
 				let color_code = self.color_code;
-				self.buffer.chars[row][col].write(ScreenChar {
+				self.shadow[row][col] = ScreenChar {
 					ascii_character: byte,
-					color_code: color_code,
-				});
+					color_code,
+				};
+				self.dirty |= 1 << row;
 				self.column_position += 1;
 			}
 		}
 	}
 
 	fn new_line(&mut self) {
-		for row in 1..BUFFER_HEIGHT {
-			for col in 0..BUFFER_WIDTH {
-				let character = self.buffer.chars[row][col].read();
-				self.buffer.chars[row-1][col].write(character);
-			}
-		}
-		self.clear_row(BUFFER_HEIGHT - 1);
 		self.column_position = 0;
+		if self.row_position < self.height - 1 {
+			self.row_position += 1;
+			return;
+		}
+
+		if let Some(scrollback) = &mut self.scrollback {
+			scrollback.push_line(&self.shadow[0]);
+		}
+
+		self.shadow.copy_within(1..self.height, 0);
+		// the dirty rows move up with the text, `flush` moves the display start
+		// along so the clean ones don't have to be copied again
+		self.dirty >>= 1;
+		self.scrolled += 1;
+		self.clear_row(self.height - 1);
+		// the status row stays put, so it's drawn again below the moved text
+		if self.status_row {
+			self.dirty |= 1 << self.height;
+		}
 	}
 
+	/// Escape sequences are interpreted, the rest is drawn with the closest CP437 glyph.
 	pub fn write_string(&mut self, string: &str) {
+		self.snap_to_live();
+
 		for byte in string.bytes() {
-			match byte {
-				0x20..=0x7e | b'\n' => self.write_byte(byte),
-				_ => self.write_byte(0xfe),
+			if let Some(action) = self.parser.advance(byte) {
+				self.perform(action);
+			}
+		}
+		self.flush();
+	}
+
+	/// Copies the rows that changed since the last flush to the VGA memory.
+	/// Everything that writes flushes once when it's done, so a long string
+	/// only touches the (slow) VGA memory once per row.
+	pub fn flush(&mut self) {
+		if !self.visible {
+			// the whole screen is drawn when it comes back anyway
+			self.dirty = 0;
+			self.scrolled = 0;
+			return;
+		}
+
+		let vga = VGA_MEMORY.load(Ordering::Relaxed) as *mut ScreenChar;
+		let mut origin = ORIGIN.load(Ordering::Relaxed);
+
+		let height = self.screen_height();
+		let pointer = pointer();
+		if self.scrolled > 0 {
+			let start = origin + self.scrolled * self.width;
+			// start the display further down while there's memory left,
+			// then go back to the top and draw everything there
+			origin = match self.scrolled < self.height
+				&& start + self.width * height <= VGA_CELLS.load(Ordering::Relaxed)
+			{
+				true => start,
+				false => {
+					self.dirty = self.all_rows();
+					0
+				}
+			};
+			set_start_address(origin);
+			// the pointer's highlight is in the VGA memory and went up with the
+			// text, it's drawn again where the pointer really is
+			if let Some((row, _)) = pointer.filter(|&(row, _)| row < height) {
+				self.dirty |= 1 << row;
+				if let Some(moved) = row.checked_sub(self.scrolled) {
+					self.dirty |= 1 << moved;
+				}
+			}
+			self.scrolled = 0;
+		}
+
+		for row in 0..height {
+			if self.dirty & (1 << row) == 0 {
+				continue;
+			}
+			let line = &self.displayed_row(row)[..self.width];
+			for (col, &cell) in line.iter().enumerate() {
+				let cell = match pointer == Some((row, col)) {
+					true => ScreenChar { color_code: cell.color_code.reversed(), ..cell },
+					false => cell,
+				};
+				unsafe { vga.add(origin + row * self.width + col).write_volatile(cell) };
+			}
+		}
+		self.dirty = 0;
+		self.update_cursor();
+	}
+
+	fn all_rows(&self) -> u64 {
+		(1 << self.screen_height()) - 1
+	}
+
+	// the text and the status row
+	fn screen_height(&self) -> usize {
+		self.height + self.status_row as usize
+	}
+
+	pub fn width(&self) -> usize {
+		self.width
+	}
+
+	/// The rows the text goes in, the status row isn't one of them.
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	/// Keeps the bottom row of the screen for `set_status`, the text scrolls
+	/// above it and escape sequences can't reach it.
+	pub fn enable_status_row(&mut self) {
+		if self.status_row {
+			return;
+		}
+		let (width, height) = (self.width, self.height);
+		self.status_row = true;
+		self.resize(width, height);
+		self.set_status(b"");
+	}
+
+	/// Fills the status row with CP437 `glyphs`, cut off or padded to the
+	/// width. Does nothing without `enable_status_row`.
+	pub fn set_status(&mut self, glyphs: &[u8]) {
+		if !self.status_row {
+			return;
+		}
+
+		let color_code = theme().status;
+		let row = self.height;
+		for (col, cell) in self.shadow[row][..self.width].iter_mut().enumerate() {
+			*cell = ScreenChar {
+				ascii_character: glyphs.get(col).copied().unwrap_or(b' '),
+				color_code,
+			};
+		}
+		self.dirty |= 1 << row;
+		self.flush();
+	}
+
+	/// Follows a mode change. Lines that don't fit any more go to the
+	/// scrollback, so the write position stays on screen.
+	fn resize(&mut self, width: usize, height: usize) {
+		self.snap_to_live();
+
+		let status = self.shadow.get(self.height).copied().filter(|_| self.status_row);
+		let height = height - self.status_row as usize;
+
+		let overflow = (self.row_position + 1).saturating_sub(height);
+		if let Some(scrollback) = &mut self.scrollback {
+			for line in &self.shadow[..overflow] {
+				scrollback.push_line(line);
+			}
+		}
+		self.shadow.copy_within(overflow..self.height, 0);
+
+		// whatever was outside the old screen shouldn't show up in the new one
+		let blank = ScreenChar {
+			ascii_character: b' ',
+			color_code: self.color_code,
+		};
+		for (row, line) in self.shadow.iter_mut().enumerate() {
+			let start = if row < self.height - overflow { self.width } else { 0 };
+			for cell in &mut line[start.min(MAX_WIDTH)..] {
+				*cell = blank;
+			}
+		}
+
+		self.width = width;
+		self.height = height;
+		if let Some(status) = status {
+			self.shadow[height] = status;
+		}
+		self.row_position -= overflow;
+		self.column_position = self.column_position.min(width);
+		let (saved_row, saved_col) = self.saved_position;
+		self.saved_position = (saved_row.min(height - 1), saved_col.min(width - 1));
+		self.scrolled = 0;
+		self.dirty = self.all_rows();
+	}
+
+	// the live screen, or the history if we're looking back
+	fn displayed_row(&self, row: usize) -> &Line {
+		if row >= self.height {
+			return &self.shadow[row];  // the status row never scrolls back
+		}
+		match &self.scrollback {
+			Some(scrollback) if row < scrollback.offset() => {
+				scrollback.line(scrollback.offset() - row)
+			}
+			Some(scrollback) => &self.shadow[row - scrollback.offset()],
+			None => &self.shadow[row],
+		}
+	}
+
+	/// Keeps the last `depth` lines that scroll off the top.
+	/// Replaces any history collected so far.
+	pub fn enable_scrollback(&mut self, depth: usize) {
+		self.snap_to_live();
+		self.scrollback = Some(Scrollback::new(depth));
+		self.flush();
+	}
+
+	/// Looks `lines` further back into the history.
+	pub fn scroll_up(&mut self, lines: usize) {
+		if let Some(scrollback) = &mut self.scrollback {
+			let offset = scrollback.offset().saturating_add(lines);
+			if scrollback.scroll_to(offset) {
+				self.dirty = self.all_rows();
+			}
+		}
+		self.flush();
+	}
+
+	pub fn scroll_down(&mut self, lines: usize) {
+		if let Some(scrollback) = &mut self.scrollback {
+			let offset = scrollback.offset().saturating_sub(lines);
+			if scrollback.scroll_to(offset) {
+				self.dirty = self.all_rows();
+			}
+		}
+		self.flush();
+	}
+
+	// half a screen at a time, so there's some context left over
+	pub fn scroll_page_up(&mut self) {
+		self.scroll_up(self.height / 2);
+	}
+
+	pub fn scroll_page_down(&mut self) {
+		self.scroll_down(self.height / 2);
+	}
+
+	fn is_scrolled_back(&self) -> bool {
+		match &self.scrollback {
+			Some(scrollback) => scrollback.offset() != 0,
+			None => false,
+		}
+	}
+
+	// any output shows up on the live screen, so we go back there first
+	fn snap_to_live(&mut self) {
+		if let Some(scrollback) = &mut self.scrollback {
+			if scrollback.scroll_to(0) {
+				self.dirty = self.all_rows();
+			}
+		}
+	}
+
+	fn perform(&mut self, action: Action) {
+		match action {
+			Action::Print(c) => {
+				let glyph = cp437::from_char(c).unwrap_or(cp437::REPLACEMENT);
+				self.put_byte(glyph);
+			}
+			Action::Execute(byte) => self.execute(byte),
+			Action::Csi(csi) => self.csi_dispatch(&csi),
+			Action::Reset => {
+				self.color_code = self.default_color;
+				self.reversed = false;
+				self.clear_region(0, 0, self.height - 1, self.width - 1);
+				self.set_position(0, 0);
+			}
+		}
+	}
+
+	fn execute(&mut self, byte: u8) {
+		match byte {
+			b'\n' => self.new_line(),
+			b'\r' => self.column_position = 0,
+			b'\t' => {
+				let next_stop = (self.column_position / 8 + 1) * 8;
+				self.column_position = next_stop.min(self.width);
+			}
+			// backspace only moves the cursor, like a real terminal
+			0x08 => self.column_position = self.column_position.saturating_sub(1),
+			_ => {}
+		}
+	}
+
+	fn csi_dispatch(&mut self, csi: &Csi) {
+		if csi.private {
+			return;  // no private modes yet
+		}
+
+		let row = self.row_position;
+		// the column can be one past the edge right before wrapping
+		let col = self.column_position.min(self.width - 1);
+		let n = usize::from(csi.param(0, 1));
+
+		match csi.final_byte {
+			b'm' => {
+				let (color, reversed) = select_graphic_rendition(self.color_code, self.reversed, self.default_color, csi);
+				self.color_code = color;
+				self.reversed = reversed;
+			}
+			b'A' => self.set_position(row.saturating_sub(n), col),
+			b'B' => self.set_position(row + n, col),
+			b'C' => self.set_position(row, col + n),
+			b'D' => self.set_position(row, col.saturating_sub(n)),
+			b'G' => self.set_position(row, n - 1),
+			b'H' | b'f' => {
+				let col = usize::from(csi.param(1, 1));
+				self.set_position(n - 1, col - 1);
+			}
+			b'J' => match csi.param(0, 0) {
+				0 => self.clear_region(row, col, self.height - 1, self.width - 1),
+				1 => self.clear_region(0, 0, row, col),
+				_ => self.clear_region(0, 0, self.height - 1, self.width - 1),
+			},
+			b'K' => match csi.param(0, 0) {
+				0 => self.clear_region(row, col, row, self.width - 1),
+				1 => self.clear_region(row, 0, row, col),
+				_ => self.clear_region(row, 0, row, self.width - 1),
+			},
+			b's' => self.saved_position = (row, self.column_position),
+			b'u' => {
+				let (row, col) = self.saved_position;
+				self.set_position(row, col);
+			}
+			_ => {}
+		}
+	}
+
+	/// The colour text is written in.
+	pub fn color(&self) -> ColorCode {
+		self.color_code
+	}
+
+	/// Stays until an escape sequence or `pop_color` changes it.
+	pub fn set_color(&mut self, color: ColorCode) {
+		self.color_code = color;
+		self.reversed = false;
+	}
+
+	/// What `ESC [ 0 m` goes back to, the theme's text colour.
+	pub fn default_color(&self) -> ColorCode {
+		self.default_color
+	}
+
+	/// Switches to `color` until the matching `pop_color`. Scopes nest up
+	/// to `COLOR_SCOPES` deep, popping a deeper one leaves the colour as it is.
+	pub fn push_color(&mut self, color: ColorCode) {
+		if let Some(saved) = self.color_stack.get_mut(self.color_depth) {
+			*saved = (self.color_code, self.reversed);
+		}
+		self.color_depth += 1;
+		self.color_code = color;
+		self.reversed = false;
+	}
+
+	/// Goes back to the colour from before the last `push_color`.
+	pub fn pop_color(&mut self) {
+		if self.color_depth == 0 {
+			return;
+		}
+		self.color_depth -= 1;
+		if let Some(&(color, reversed)) = self.color_stack.get(self.color_depth) {
+			self.color_code = color;
+			self.reversed = reversed;
+		}
+	}
+
+	// Text in the old default colour changes along with it.
+	fn set_default_color(&mut self, color: ColorCode) {
+		let old = self.default_color;
+		for line in self.shadow[..self.height].iter_mut() {
+			for cell in line[..self.width].iter_mut().filter(|cell| cell.color_code == old) {
+				cell.color_code = color;
 			}
 		}
+		if self.color_code == old {
+			self.color_code = color;
+		}
+		self.default_color = color;
+		self.dirty = self.all_rows();
+	}
+
+	/// The glyph and colour of a text cell, `None` outside the text.
+	pub fn cell(&self, row: usize, col: usize) -> Option<(u8, ColorCode)> {
+		if row >= self.height || col >= self.width {
+			return None;
+		}
+		let cell = self.shadow[row][col];
+		Some((cell.ascii_character, cell.color_code))
+	}
+
+	/// Puts a glyph straight into a text cell, for boxes and such. It's on
+	/// screen after the next `flush`, cells outside the text are ignored.
+	pub fn put_cell(&mut self, row: usize, col: usize, glyph: u8, color: ColorCode) {
+		if row >= self.height || col >= self.width {
+			return;
+		}
+		self.snap_to_live();
+		self.shadow[row][col] = ScreenChar {
+			ascii_character: glyph,
+			color_code: color,
+		};
+		self.dirty |= 1 << row;
+	}
+
+	/// The write position as (row, column).
+	pub fn position(&self) -> (usize, usize) {
+		(self.row_position, self.column_position)
+	}
+
+	/// Moves the cursor, clamped to the screen. Rows and columns start at 0.
+	pub fn set_position(&mut self, row: usize, col: usize) {
+		self.snap_to_live();
+		self.row_position = row.min(self.height - 1);
+		self.column_position = col.min(self.width - 1);
+		self.flush();
+	}
+
+	/// Turns on the blinking hardware cursor, it follows the write position from now on.
+	pub fn show_cursor(&mut self, shape: CursorShape) {
+		use registers::{Crtc, CRTC_MAX_SCAN_LINE, CRTC_CURSOR_START, CRTC_CURSOR_END};
+
+		// the hardware belongs to the console on screen, the others just remember
+		self.cursor = Some(shape);
+		if !self.is_visible() {
+			return;
+		}
+
+		let mut crtc = Crtc::new();
+		unsafe {
+			// the cell height depends on the font, so ask the hardware
+			let last_line = crtc.read(CRTC_MAX_SCAN_LINE) & 0x1f;
+			let first_line = match shape {
+				CursorShape::Underline => last_line.saturating_sub(1),
+				CursorShape::Block => 0,
+			};
+
+			// bit 5 of the start register hides the cursor, keep it clear
+			let start = crtc.read(CRTC_CURSOR_START) & 0xc0;
+			crtc.write(CRTC_CURSOR_START, start | first_line);
+			let end = crtc.read(CRTC_CURSOR_END) & 0xe0;
+			crtc.write(CRTC_CURSOR_END, end | last_line);
+		}
+
+		self.update_cursor();
+	}
+
+	pub fn hide_cursor(&mut self) {
+		use registers::{Crtc, CRTC_CURSOR_START};
+
+		self.cursor = None;
+		if !self.is_visible() {
+			return;
+		}
+
+		let mut crtc = Crtc::new();
+		unsafe {
+			let start = crtc.read(CRTC_CURSOR_START);
+			crtc.write(CRTC_CURSOR_START, start | 0x20);
+		}
+	}
+
+	pub fn cursor_shape(&self) -> Option<CursorShape> {
+		self.cursor
+	}
+
+	/// Moves the hardware cursor to the write position.
+	/// Nothing to do while it's hidden, `show_cursor` catches up.
+	fn update_cursor(&mut self) {
+		use registers::{Crtc, CRTC_CURSOR_HIGH, CRTC_CURSOR_LOW};
+
+		if self.cursor.is_none() || !self.is_visible() {
+			return;
+		}
+
+		// right before wrapping the column is one past the edge
+		let col = self.column_position.min(self.width - 1);
+		let offset = ORIGIN.load(Ordering::Relaxed) + match self.is_scrolled_back() {
+			// a position past the end of the screen isn't drawn
+			true => self.width * self.screen_height(),
+			false => self.row_position * self.width + col,
+		};
+
+		let mut crtc = Crtc::new();
+		unsafe {
+			crtc.write(CRTC_CURSOR_HIGH, (offset >> 8) as u8);
+			crtc.write(CRTC_CURSOR_LOW, offset as u8);
+		}
+	}
+
+	/// Whether this console is the one on screen.
+	pub fn is_visible(&self) -> bool {
+		self.visible
+	}
+
+	/// Puts `to` on screen instead of this writer.
+	fn hand_over_screen(&mut self, to: &mut Writer) {
+		assert!(self.visible, "only the visible console can hand over the screen");
+
+		// whatever is on screen now should be the live view
+		self.snap_to_live();
+		to.snap_to_live();
+
+		self.visible = false;
+		to.visible = true;
+		to.dirty = to.all_rows();
+		to.flush();
+
+		// the hardware cursor still looks like the old console's
+		match to.cursor {
+			Some(shape) => to.show_cursor(shape),
+			None => to.hide_cursor(),
+		}
+	}
+
+	/// Blanks every cell from (`from_row`, `from_col`) to (`to_row`, `to_col`)
+	/// inclusive, in reading order.
+	fn clear_region(&mut self, from_row: usize, from_col: usize, to_row: usize, to_col: usize) {
+		let blank = ScreenChar {
+			ascii_character: b' ',
+			color_code: self.color_code,
+		};
+
+		for row in from_row..=to_row {
+			let start = if row == from_row { from_col } else { 0 };
+			let end = if row == to_row { to_col } else { self.width - 1 };
+			self.shadow[row][start..=end].fill(blank);
+			self.dirty |= 1 << row;
+		}
 	}
 
 	fn clear_row(&mut self, row: usize) {
@@ -103,9 +736,43 @@ impl Writer {
 			// color_code: ColorCode(Color::Black as u8),
 		};
 
-		for col in 0..BUFFER_WIDTH {
-			self.buffer.chars[row][col].write(blank);
-		}
+		self.shadow[row] = [blank; MAX_WIDTH];
+		self.dirty |= 1 << row;
+	}
+}
+
+// The colours after an `ESC [ ... m`, which `default` goes back to, and
+// whether they're reversed. `color` is drawn as it is, already swapped when
+// `reversed` is set.
+fn select_graphic_rendition(color: ColorCode, reversed: bool, default: ColorCode, csi: &Csi) -> (ColorCode, bool) {
+	// `ESC [ m` is the same as `ESC [ 0 m`
+	let params = match csi.params() {
+		[] => &[0][..],
+		params => params,
+	};
+
+	// the colour codes are about the unswapped colours
+	let unswapped = if reversed { color.reversed() } else { color };
+	let (color, reversed) = params.iter().fold((unswapped, reversed), |(color, reversed), &param| match param {
+		0 => (default, false),
+		7 => (color, true),
+		27 => (color, false),
+		param => (color_rendition(color, default, param), reversed),
+	});
+	(if reversed { color.reversed() } else { color }, reversed)
+}
+
+fn color_rendition(color: ColorCode, default: ColorCode, param: u16) -> ColorCode {
+	match param {
+		1 => ColorCode(color.0 | BRIGHT),
+		22 => ColorCode(color.0 & !BRIGHT),
+		30..=37 => color.with_foreground(ANSI_TO_VGA[usize::from(param - 30)]),
+		39 => color.with_foreground(default.0),
+		40..=47 => color.with_background(ANSI_TO_VGA[usize::from(param - 40)]),
+		49 => color.with_background(default.0 >> 4),
+		90..=97 => color.with_foreground(ANSI_TO_VGA[usize::from(param - 90)] | BRIGHT),
+		100..=107 => color.with_background(ANSI_TO_VGA[usize::from(param - 100)] | BRIGHT),
+		_ => color,
 	}
 }
 
@@ -116,15 +783,340 @@ impl fmt::Write for Writer {
 	}
 }
 
+// Virtual consoles
+
+pub const CONSOLE_COUNT: usize = 6;
+/// Where `print!` goes.
+pub const KERNEL_CONSOLE: usize = 0;
+
+// every console draws into its own shadow, only the visible one gets flushed
+static mut SHADOWS: [Cells; CONSOLE_COUNT] = [[[BLANK; MAX_WIDTH]; MAX_HEIGHT]; CONSOLE_COUNT];
+
+/// Must be called once per console, or we end up with two `&mut`s to the same shadow.
+unsafe fn shadow(console: usize) -> &'static mut Cells {
+	&mut SHADOWS[console]
+}
+
+fn new_console(console: usize) -> Mutex<Writer> {
+	let visible = console == KERNEL_CONSOLE;
+
+	let mode = text_mode();
+	let color = theme().text;
+	Mutex::new(Writer {
+		width: mode.width(),
+		height: mode.height(),
+		column_position: 0,
+		row_position: mode.height() - 1,  // start at the bottom like before
+		saved_position: (0, 0),
+		color_code: color,
+		default_color: color,
+		parser: Parser::new(),
+		reversed: false,
+		color_stack: [(color, false); COLOR_SCOPES],
+		color_depth: 0,
+		cursor: None,
+		scrollback: None,
+		shadow: unsafe { shadow(console) },
+		// the first flush clears whatever the bootloader left behind
+		dirty: if visible { (1 << mode.height()) - 1 } else { 0 },
+		scrolled: 0,
+		visible,
+		status_row: false,
+	})
+}
+
 use spin::Mutex;
 lazy_static::lazy_static! {
-	pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-		column_position: 0,
-		color_code: ColorCode::new(Color::LightRed, Color::Black),
-		buffer: unsafe { &mut *(0xb8000 as *mut Buffer)},
+	pub static ref CONSOLES: [Mutex<Writer>; CONSOLE_COUNT] = [
+		new_console(0), new_console(1), new_console(2),
+		new_console(3), new_console(4), new_console(5),
+	];
+}
+
+/// The writer from before there were consoles, it's the kernel console.
+#[deprecated(note = "use `CONSOLES[KERNEL_CONSOLE]`")]
+pub static WRITER: KernelConsole = KernelConsole;
+
+/// Locks like the `Mutex<Writer>` of the kernel console, see `WRITER`.
+pub struct KernelConsole;
+
+impl core::ops::Deref for KernelConsole {
+	type Target = Mutex<Writer>;
+
+	fn deref(&self) -> &Mutex<Writer> {
+		&CONSOLES[KERNEL_CONSOLE]
+	}
+}
+
+static ACTIVE_CONSOLE: AtomicUsize = AtomicUsize::new(KERNEL_CONSOLE);
+
+/// The console on screen, it's also the one keyboard input goes to.
+pub fn active_console() -> usize {
+	ACTIVE_CONSOLE.load(Ordering::Relaxed)
+}
+
+/// Puts another console on screen. Out of range consoles are ignored, and so
+/// is everything in graphics mode, where no console is on screen.
+pub fn switch_console(console: usize) {
+	use x86_64::instructions::interrupts;
+
+	if console >= CONSOLE_COUNT || graphics::is_active() {
+		return;
+	}
+
+	interrupts::without_interrupts(|| {
+		let active = active_console();
+		if active == console {
+			return;
+		}
+
+		// always lock in the same order, so two switches can't deadlock
+		let (mut from, mut to) = if active < console {
+			let from = CONSOLES[active].lock();
+			(from, CONSOLES[console].lock())
+		} else {
+			let to = CONSOLES[console].lock();
+			(CONSOLES[active].lock(), to)
+		};
+
+		from.hand_over_screen(&mut to);
+		ACTIVE_CONSOLE.store(console, Ordering::Relaxed);
+	});
+}
+
+const NO_POINTER: usize = usize::MAX;
+static POINTER: AtomicUsize = AtomicUsize::new(NO_POINTER);  // row * MAX_WIDTH + column
+
+/// The cell the mouse pointer is on, if it's shown.
+pub fn pointer() -> Option<(usize, usize)> {
+	match POINTER.load(Ordering::Relaxed) {
+		NO_POINTER => None,
+		cell => Some((cell / MAX_WIDTH, cell % MAX_WIDTH)),
+	}
+}
+
+/// Highlights a cell of the console on screen as the mouse pointer, `None`
+/// hides it. The pointer is only drawn, the text under it doesn't change.
+pub fn set_pointer(position: Option<(usize, usize)>) {
+	use x86_64::instructions::interrupts;
+
+	interrupts::without_interrupts(|| {
+		let mut writer = CONSOLES[active_console()].lock();
+		let old = pointer();
+		POINTER.store(position.map_or(NO_POINTER, |(row, col)| row * MAX_WIDTH + col), Ordering::Relaxed);
+		for (row, _) in old.into_iter().chain(position) {
+			if row < writer.screen_height() {
+				writer.dirty |= 1 << row;
+			}
+		}
+		writer.flush();
+	});
+}
+
+// The VGA memory
+
+// The text memory is a 32 KiB window, but the bootloader only maps the page
+// at 0xb8000, which is just enough for an 80x25 screen.
+const VGA_MEMORY_ADDRESS: u64 = 0xb8000;
+const VGA_MEMORY_SIZE: usize = 0x8000;
+const FONT_MEMORY_ADDRESS: u64 = 0xa0000;
+
+static VGA_MEMORY: AtomicUsize = AtomicUsize::new(VGA_MEMORY_ADDRESS as usize);
+static VGA_CELLS: AtomicUsize = AtomicUsize::new(80 * 25);
+// where plane 2 shows up while we load a font, 0 until it's mapped
+static FONT_MEMORY: AtomicUsize = AtomicUsize::new(0);
+// the cell in the top left corner, the display start address
+static ORIGIN: AtomicUsize = AtomicUsize::new(0);
+
+fn set_start_address(origin: usize) {
+	use registers::{Crtc, CRTC_START_HIGH, CRTC_START_LOW};
+
+	ORIGIN.store(origin, Ordering::Relaxed);
+	let mut crtc = Crtc::new();
+	unsafe {
+		crtc.write(CRTC_START_HIGH, (origin >> 8) as u8);
+		crtc.write(CRTC_START_LOW, origin as u8);
+	}
+}
+
+/// Reaches the whole VGA memory through the physical memory mapping. The
+/// screen can then scroll by moving the display start instead of copying
+/// every row, and there's room for the bigger text modes.
+pub fn map_vga_memory(physical_memory_offset: VirtAddr) {
+	use x86_64::instructions::interrupts;
+
+	let address = physical_memory_offset + VGA_MEMORY_ADDRESS;
+	let font = physical_memory_offset + FONT_MEMORY_ADDRESS;
+	// the same memory as before, so the screen and the origin stay as they are
+	interrupts::without_interrupts(|| {
+		VGA_MEMORY.store(address.as_u64() as usize, Ordering::Relaxed);
+		VGA_CELLS.store(VGA_MEMORY_SIZE / 2, Ordering::Relaxed);
+		FONT_MEMORY.store(font.as_u64() as usize, Ordering::Relaxed);
 	});
 }
 
+/// The top left cell while the display starts at the beginning of the
+/// memory, for the panic screen, which can't wait for a lock.
+pub fn vga_memory() -> *mut u16 {
+	VGA_MEMORY.load(Ordering::Relaxed) as *mut u16
+}
+
+
+// Text modes
+
+static MODE: AtomicUsize = AtomicUsize::new(0);  // an index into `TextMode::ALL`
+// the font the modes draw with at its own height, the BIOS one till `set_font`
+static FONT: Mutex<Option<Font>> = Mutex::new(None);
+
+pub fn text_mode() -> TextMode {
+	TextMode::ALL[MODE.load(Ordering::Relaxed)]
+}
+
+/// `set_mode`, `set_graphics_mode` and `set_font` need `map_vga_memory` first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VgaMemoryNotMapped;
+
+/// Reprograms the VGA for another text mode, or back to text from graphics.
+/// Every console is resized, whatever doesn't fit any more goes to its scrollback.
+pub fn set_mode(mode: TextMode) -> Result<(), VgaMemoryNotMapped> {
+	use x86_64::instructions::interrupts;
+
+	let font_memory = FONT_MEMORY.load(Ordering::Relaxed);
+	if font_memory == 0 {
+		return Err(VgaMemoryNotMapped);
+	}
+	let index = TextMode::ALL.iter().position(|&m| m == mode).unwrap();
+
+	interrupts::without_interrupts(|| {
+		let active = active_console();
+		{
+			// nothing may be drawn till the new mode is in place
+			let mut writer = CONSOLES[active].lock();
+			unsafe {
+				load_font(font_memory as *mut u8, mode.font_height());
+				modes::program(mode);
+			}
+			MODE.store(index, Ordering::Relaxed);
+			set_start_address(0);
+			if graphics::is_active() {
+				graphics::leave();
+				writer.visible = true;
+			}
+			writer.resize(mode.width(), mode.height());
+			writer.flush();
+
+			// the cursor is drawn in scan lines, and the cells have a new height
+			match writer.cursor {
+				Some(shape) => writer.show_cursor(shape),
+				None => writer.hide_cursor(),
+			}
+		}
+
+		for (i, console) in CONSOLES.iter().enumerate() {
+			if i != active {
+				console.lock().resize(mode.width(), mode.height());
+			}
+		}
+	});
+	Ok(())
+}
+
+/// Replaces the glyphs on screen, in this mode and the ones after it.
+/// An 8x16 font is squashed for the 8 line modes, other heights are loaded
+/// as they are and leave blank lines at the bottom of taller cells.
+pub fn set_font(font: Font) -> Result<(), VgaMemoryNotMapped> {
+	use x86_64::instructions::interrupts;
+
+	let font_memory = FONT_MEMORY.load(Ordering::Relaxed);
+	if font_memory == 0 {
+		return Err(VgaMemoryNotMapped);
+	}
+
+	interrupts::without_interrupts(|| {
+		// plane 2 takes over the VGA memory for a while, nobody may draw meanwhile
+		let _writer = CONSOLES[active_console()].lock();
+		*FONT.lock() = Some(font);
+		// graphics mode has no font memory, it's loaded on the way back
+		if !graphics::is_active() {
+			unsafe { load_font(font_memory as *mut u8, text_mode().font_height()) };
+		}
+	});
+	Ok(())
+}
+
+/// Switches to 320x200 pixels in 256 colours, see `graphics` for drawing.
+/// The consoles keep collecting text in the background and `print!` shows
+/// up on top of the pixels. `set_mode` goes back to text.
+pub fn set_graphics_mode() -> Result<(), VgaMemoryNotMapped> {
+	use x86_64::instructions::interrupts;
+
+	let memory = FONT_MEMORY.load(Ordering::Relaxed);
+	if memory == 0 {
+		return Err(VgaMemoryNotMapped);
+	}
+
+	interrupts::without_interrupts(|| {
+		let mut writer = CONSOLES[active_console()].lock();
+		if graphics::is_active() {
+			return;
+		}
+
+		// the pixels go where the font lives, so it's saved first
+		let font = unsafe { saved_font(memory as *mut u8) };
+		let font = font.as_ref().unwrap().clone();
+		writer.visible = false;
+		unsafe { modes::program_graphics() };
+		graphics::enter(memory, font);
+	});
+	Ok(())
+}
+
+/// Back to 80x25 text from graphics mode for the panic screen, without
+/// waiting for a lock. The font only comes back if nobody holds it, the
+/// serial dump has the message either way.
+pub fn emergency_text_mode() {
+	if !graphics::is_active() {
+		return;
+	}
+
+	graphics::abandon();
+	unsafe { modes::program(TextMode::Text80x25) };
+	MODE.store(0, Ordering::Relaxed);
+	ORIGIN.store(0, Ordering::Relaxed);
+
+	let memory = FONT_MEMORY.load(Ordering::Relaxed) as *mut u8;
+	if let Some(font) = FONT.try_lock() {
+		if let Some(font) = &*font {
+			unsafe { font.load(memory) };
+		}
+	}
+}
+
+// The font the text modes draw with. The first time round it's still the
+// BIOS font, which has to be saved before anything overwrites it.
+unsafe fn saved_font(memory: *mut u8) -> spin::MutexGuard<'static, Option<Font>> {
+	use registers::{Crtc, CRTC_MAX_SCAN_LINE};
+
+	let mut font = FONT.lock();
+	if font.is_none() {
+		// nothing has changed the mode yet, so the cells are the BIOS font's height
+		let height = usize::from(Crtc::new().read(CRTC_MAX_SCAN_LINE) & 0x1f) + 1;
+		*font = Some(Font::read(memory, height));
+	}
+	font
+}
+
+// Loads the current font for cells `height` lines tall.
+unsafe fn load_font(memory: *mut u8, height: usize) {
+	let font = saved_font(memory);
+	let font = font.as_ref().unwrap();
+
+	match font.height() == 2 * height {
+		true => font.halved().load(memory),
+		false => font.load(memory),
+	}
+}
+
 // pub fn yet_another_printer() {
 	
 
@@ -139,38 +1131,65 @@ lazy_static::lazy_static! {
 
 // }
 
+/// Like `print!`, but to one of the virtual consoles.
 #[macro_export]
-macro_rules! println {
-	// no need to import print!() to use println!()
-	() => ($crate::print!("\n"));
-	($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+macro_rules! console_print {
+	($console:expr, $($arg:tt)*) => (
+		$crate::vga_buffer::_print_to($console, format_args!($($arg)*))
+	);
 }
 
 #[macro_export]
-macro_rules! print {
-	($($arg:tt)*) => ($crate::vga_buffer::_print(format_args!($($arg)*)));
+macro_rules! console_println {
+	($console:expr) => ($crate::console_print!($console, "\n"));
+	($console:expr, $($arg:tt)*) => (
+		$crate::console_print!($console, "{}\n", format_args!($($arg)*))
+	);
+}
+
+/// Runs `f` with everything `print!` writes in `color`, on every sink that
+/// understands colours. Afterwards the kernel console's colour from before
+/// comes back.
+pub fn with_color<R>(color: ColorCode, f: impl FnOnce() -> R) -> R {
+	use x86_64::instructions::interrupts;
+
+	let previous = interrupts::without_interrupts(|| CONSOLES[KERNEL_CONSOLE].lock().color());
+	crate::print!("{}", color.ansi());
+	let result = f();
+	crate::print!("{}", previous.ansi());
+	result
 }
 
 #[doc(hidden)]
-pub fn _print(args: fmt::Arguments) {
+pub fn _print_to(console: usize, args: fmt::Arguments) {
 	use core::fmt::Write;
 	// if an interrupt occurs while the mutex is locked
 	// a deadlock occurs.
 	// Thus, we'll prevent the handling of interrupts while the mutex is locked.
 	use x86_64::instructions::interrupts;
 	interrupts::without_interrupts(|| {
-		WRITER.lock().write_fmt(args).unwrap();
+		CONSOLES[console].lock().write_fmt(args).unwrap();
 	});
 }
 
 
+// what's actually on screen right now
+#[cfg(test)]
+fn vga_cell(row: usize, col: usize) -> ScreenChar {
+	let vga = VGA_MEMORY.load(Ordering::Relaxed) as *const ScreenChar;
+	let origin = ORIGIN.load(Ordering::Relaxed);
+	unsafe { vga.add(origin + row * text_mode().width() + col).read_volatile() }
+}
+
 #[test_case]  // test cases pass if there is no panic
 fn test_println_simple() {
+	use crate::println;
 	println!("Hello, World!");
 }
 
 #[test_case]
 fn test_println_many() {
+	use crate::println;
 	for i in 1..200 {
 		println!("REEEEEEEE {}", i);
 	}
@@ -183,15 +1202,332 @@ fn test_println_output() {  // if the output shows up in the vga buffer
 	use core::fmt::Write;
 	use x86_64::instructions::interrupts;
 	interrupts::without_interrupts ( || {
-		let mut writer = WRITER.lock();  // global static buffer
+		let mut writer = CONSOLES[KERNEL_CONSOLE].lock();  // global static buffer
 		writeln!(writer, "\n{}", s).expect("could not write to vga buffer");
-		for (i, c) in s.chars().enumerate() {
-			let screen_char = writer
-				.buffer
-				// BUFFER_HEIGHT-1 is the last line. ln shifts it up one
-				.chars[BUFFER_HEIGHT - 2][i]
-				.read();
-			assert_eq!(char::from(screen_char.ascii_character), c);
+		let screen = writer.snapshot();
+		assert_eq!(screen.row_text(writer.height - 2), s);
+		// and it made it out of the shadow
+		for (i, c) in s.bytes().enumerate() {
+			assert_eq!(vga_cell(writer.height - 2, i).ascii_character, c);
+		}
+	});
+}
+
+#[test_case]
+fn test_ansi_colours() {
+	use core::fmt::Write;
+	use x86_64::instructions::interrupts;
+	interrupts::without_interrupts(|| {
+		let mut writer = CONSOLES[KERNEL_CONSOLE].lock();
+		write!(writer, "\n\x1b[92;44mX\x1b[0mY").expect("could not write to vga buffer");
+		let row = writer.row_position;
+
+		let coloured = writer.shadow[row][0];
+		assert_eq!(coloured.ascii_character, b'X');
+		assert_eq!(coloured.color_code, ColorCode::new(Color::LightGreen, Color::Blue));
+
+		let reset = writer.shadow[row][1];
+		assert_eq!(reset.ascii_character, b'Y');
+		assert_eq!(reset.color_code, writer.default_color);
+
+		// a second 7 keeps it reversed, 27 turns it off
+		write!(writer, "\x1b[7mR\x1b[7mR\x1b[27mN").expect("could not write to vga buffer");
+		let reversed = writer.default_color.reversed();
+		assert_eq!(writer.shadow[row][2].color_code, reversed);
+		assert_eq!(writer.shadow[row][3].color_code, reversed);
+		assert_eq!(writer.shadow[row][4].color_code, writer.default_color);
+	});
+}
+
+#[test_case]
+fn test_color_scopes() {
+	use core::fmt::Write;
+	use x86_64::instructions::interrupts;
+
+	let warning = ColorCode::new(Color::Yellow, Color::Black);
+	let error = ColorCode::new(Color::White, Color::Red);
+
+	interrupts::without_interrupts(|| {
+		let mut writer = CONSOLES[KERNEL_CONSOLE].lock();
+		let before = writer.color();
+		writer.push_color(warning);
+		writer.push_color(error);
+		write!(writer, "\nE").expect("could not write to vga buffer");
+		writer.pop_color();
+		write!(writer, "W").expect("could not write to vga buffer");
+		writer.pop_color();
+		writer.pop_color();  // one too many is ignored
+		assert_eq!(writer.color(), before);
+
+		let row = writer.row_position;
+		assert_eq!(writer.shadow[row][0].color_code, error);
+		assert_eq!(writer.shadow[row][1].color_code, warning);
+	});
+
+	// the escape sequence gives back the same colours
+	let mut color = DEFAULT_COLOR;
+	let mut parser = Parser::new();
+	let mut ansi = alloc::string::String::new();
+	write!(ansi, "{}", error.ansi()).unwrap();
+	for byte in ansi.bytes() {
+		if let Some(Action::Csi(csi)) = parser.advance(byte) {
+			color = select_graphic_rendition(color, false, DEFAULT_COLOR, &csi).0;
+		}
+	}
+	assert_eq!(color, error);
+
+	let inside = with_color(warning, || {
+		interrupts::without_interrupts(|| CONSOLES[KERNEL_CONSOLE].lock().color())
+	});
+	assert_eq!(inside, warning);
+}
+
+#[test_case]
+fn test_pointer_while_scrolling() {
+	use core::fmt::Write;
+	use x86_64::instructions::interrupts;
+
+	set_pointer(Some((5, 0)));
+	interrupts::without_interrupts(|| {
+		let mut writer = CONSOLES[KERNEL_CONSOLE].lock();
+		let bottom = writer.height() - 1;
+		writer.set_position(bottom, 0);
+		write!(writer, "\n\n").expect("could not write to vga buffer");
+
+		// the highlight stays on the pointer, not on the text that was under it
+		assert_eq!(vga_cell(5, 0).color_code, writer.shadow[5][0].color_code.reversed());
+		assert_eq!(vga_cell(3, 0).color_code, writer.shadow[3][0].color_code);
+	});
+	set_pointer(None);
+}
+
+#[test_case]
+fn test_status_row() {
+	use core::fmt::Write;
+	use x86_64::instructions::interrupts;
+
+	// a console in the background, the others expect the whole screen
+	interrupts::without_interrupts(|| {
+		let mut writer = CONSOLES[KERNEL_CONSOLE + 2].lock();
+		let screen_height = writer.height();
+		writer.enable_status_row();
+		assert_eq!(writer.height(), screen_height - 1);
+
+		writer.set_status(b"status");
+		for i in 0..screen_height {
+			write!(writer, "\nline {}", i).expect("could not write to vga buffer");
 		}
+		write!(writer, "\x1b[99;1Hlast").expect("could not write to vga buffer");
+
+		let status = writer.height;
+		assert_eq!(writer.row_position, status - 1);
+		assert_eq!(writer.shadow[status][0].ascii_character, b's');
+		assert_eq!(writer.shadow[status][0].color_code, theme().status);
+		assert_eq!(writer.shadow[status - 1][0].ascii_character, b'l');
+	});
+}
+
+#[test_case]
+fn test_ansi_cursor_and_erase() {
+	use core::fmt::Write;
+	use x86_64::instructions::interrupts;
+	interrupts::without_interrupts(|| {
+		let mut writer = CONSOLES[KERNEL_CONSOLE].lock();
+		write!(writer, "\x1b[2J\x1b[3;5HAB\rC\tD\x08E").expect("could not write to vga buffer");
+
+		let expected = [(0, b'C'), (4, b'A'), (5, b'B'), (8, b'E')];
+		for &(col, c) in &expected {
+			assert_eq!(writer.shadow[2][col].ascii_character, c);
+		}
+		assert_eq!(writer.shadow[0][0].ascii_character, b' ');
+
+		// back to the bottom so later output scrolls like before
+		write!(writer, "\x1b[25;1H").expect("could not write to vga buffer");
+	});
+}
+
+#[test_case]
+fn test_hardware_cursor_follows_writer() {
+	use core::fmt::Write;
+	use registers::{Crtc, CRTC_CURSOR_HIGH, CRTC_CURSOR_LOW};
+	use x86_64::instructions::interrupts;
+	interrupts::without_interrupts(|| {
+		let mut writer = CONSOLES[KERNEL_CONSOLE].lock();
+		let shape = writer.cursor_shape();
+		writer.show_cursor(CursorShape::Block);
+		write!(writer, "\nabc").expect("could not write to vga buffer");
+
+		let mut crtc = Crtc::new();
+		let offset = unsafe {
+			usize::from(crtc.read(CRTC_CURSOR_HIGH)) << 8
+				| usize::from(crtc.read(CRTC_CURSOR_LOW))
+		};
+		let origin = ORIGIN.load(Ordering::Relaxed);
+		assert_eq!(offset, origin + writer.row_position * writer.width + 3);
+
+		match shape {
+			Some(shape) => writer.show_cursor(shape),
+			None => writer.hide_cursor(),
+		}
+	});
+}
+
+#[test_case]
+fn test_scrollback() {
+	use core::fmt::Write;
+	use x86_64::instructions::interrupts;
+	interrupts::without_interrupts(|| {
+		let mut writer = CONSOLES[KERNEL_CONSOLE].lock();
+		let height = writer.height;
+		writer.enable_scrollback(height);
+		for i in 0..=height {
+			write!(writer, "\nline {}", i).expect("could not write to vga buffer");
+		}
+
+		// "line 0" just went off the top
+		writer.scroll_up(1);
+		assert_eq!(writer.displayed_row(0)[5].ascii_character, b'0');
+		assert_eq!(writer.displayed_row(1)[5].ascii_character, b'1');
+
+		// scrolling can't go past the oldest line
+		writer.scroll_up(usize::MAX);
+		writer.scroll_down(height - 1);
+		assert_eq!(writer.displayed_row(0)[5].ascii_character, b'0');
+
+		// new output jumps back to the live screen
+		write!(writer, "!").expect("could not write to vga buffer");
+		assert_eq!(writer.displayed_row(0)[5].ascii_character, b'1');
+
+		writer.scrollback = None;
+	});
+}
+
+#[test_case]
+fn test_console_switching() {
+	use core::fmt::Write;
+	use x86_64::instructions::interrupts;
+
+	let other = KERNEL_CONSOLE + 1;
+
+	interrupts::without_interrupts(|| {
+		let mut kernel = CONSOLES[KERNEL_CONSOLE].lock();
+		write!(kernel, "\x1b[1;1HK").expect("could not write to vga buffer");
+		let mut writer = CONSOLES[other].lock();
+		write!(writer, "\x1b[1;1HO").expect("could not write to vga buffer");
+		assert!(!writer.is_visible());
+	});
+	// writing to a console in the background leaves the screen alone
+	assert_eq!(vga_cell(0, 0).ascii_character, b'K');
+
+	switch_console(other);
+	assert_eq!(active_console(), other);
+	assert_eq!(vga_cell(0, 0).ascii_character, b'O');
+
+	switch_console(KERNEL_CONSOLE);
+	assert_eq!(vga_cell(0, 0).ascii_character, b'K');
+
+	interrupts::without_interrupts(|| {
+		write!(CONSOLES[KERNEL_CONSOLE].lock(), "\x1b[25;1H")
+			.expect("could not write to vga buffer");
+	});
+}
+
+#[test_case]
+fn test_cp437_glyphs() {
+	use core::fmt::Write;
+	use x86_64::instructions::interrupts;
+	interrupts::without_interrupts(|| {
+		let mut writer = CONSOLES[KERNEL_CONSOLE].lock();
+		write!(writer, "\né£─█→€").expect("could not write to vga buffer");
+
+		let row = writer.row_position;
+		let expected = [0x82, 0x9c, 0xc4, 0xdb, 0x1a, cp437::REPLACEMENT];
+		for (col, &glyph) in expected.iter().enumerate() {
+			assert_eq!(writer.shadow[row][col].ascii_character, glyph);
+		}
+	});
+}
+
+#[test_case]
+fn test_hardware_scroll() {
+	use core::fmt::Write;
+	use x86_64::instructions::interrupts;
+
+	interrupts::without_interrupts(|| {
+		let mut writer = CONSOLES[KERNEL_CONSOLE].lock();
+		let (width, height) = (writer.width, writer.height);
+		// from the top of the VGA memory, so there's room to scroll into
+		set_start_address(0);
+		writer.set_position(height - 1, 0);
+		writer.dirty = writer.all_rows();
+		writer.flush();
+
+		// the second row becomes the first, and isn't copied there
+		let vga = VGA_MEMORY.load(Ordering::Relaxed) as *mut ScreenChar;
+		let marker = ScreenChar { ascii_character: b'#', color_code: writer.color_code.reversed() };
+		unsafe { vga.add(width).write_volatile(marker) };
+
+		write!(writer, "\nscrolled").unwrap();
+		writer.flush();
+		assert_eq!(ORIGIN.load(Ordering::Relaxed), width);
+		assert_eq!(vga_cell(0, 0), marker);
+		assert_eq!(vga_cell(height - 1, 0), writer.shadow[height - 1][0]);
+		assert_eq!(vga_cell(height - 1, 0).ascii_character, b's');
+
+		// the marker went behind the writer's back
+		writer.dirty = writer.all_rows();
+		writer.flush();
+		assert_eq!(vga_cell(0, 0), writer.shadow[0][0]);
+	});
+}
+
+#[test_case]
+fn test_text_modes() {
+	use core::fmt::Write;
+	use registers::{Crtc, CRTC_MAX_SCAN_LINE};
+	use x86_64::instructions::interrupts;
+
+	// back to 80x25 at the end
+	for &mode in TextMode::ALL.iter().rev() {
+		set_mode(mode).expect("the VGA memory should be mapped");
+		assert_eq!(text_mode(), mode);
+		let cell_height = unsafe { Crtc::new().read(CRTC_MAX_SCAN_LINE) & 0x1f } + 1;
+		assert_eq!(usize::from(cell_height), mode.font_height());
+
+		interrupts::without_interrupts(|| {
+			let mut writer = CONSOLES[KERNEL_CONSOLE].lock();
+			assert_eq!((writer.width(), writer.height()), (mode.width(), mode.height()));
+			write!(writer, "\n{}", mode.name()).expect("could not write to vga buffer");
+			let row = writer.row_position;
+			assert_eq!(vga_cell(row, 3).ascii_character, mode.name().as_bytes()[3]);
+
+			let other = CONSOLES[KERNEL_CONSOLE + 1].lock();
+			assert_eq!(other.height(), mode.height());
+			assert!(other.row_position < mode.height());
+		});
+	}
+}
+
+#[test_case]
+fn test_set_font() {
+	use x86_64::instructions::interrupts;
+
+	let memory = FONT_MEMORY.load(Ordering::Relaxed) as *mut u8;
+	// whatever is loaded now, the BIOS font or another test's
+	let mut font = interrupts::without_interrupts(|| {
+		let _writer = CONSOLES[active_console()].lock();
+		unsafe { saved_font(memory) }.clone().unwrap()
+	});
+	let smiley = [0x00, 0x00, 0x7e, 0x81, 0xa5, 0x81, 0x81, 0xbd, 0x99, 0x81, 0x81, 0x7e];
+	font.set_glyph(0x01, &smiley);
+	set_font(font.clone()).expect("the VGA memory should be mapped");
+
+	// nothing may draw while plane 2 is mapped
+	let loaded = interrupts::without_interrupts(|| {
+		let _writer = CONSOLES[active_console()].lock();
+		unsafe { Font::read(memory, font.height()) }
 	});
+	let lines = smiley.len().min(font.height());
+	assert_eq!(loaded.glyph(0x01)[..lines], smiley[..lines]);
+	assert_eq!(loaded.glyph(b'A'), font.glyph(b'A'));
 }
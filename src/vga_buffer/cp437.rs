@@ -0,0 +1,60 @@
+// Code page 437 is the character set burned into the VGA ROM font.
+// The printable ASCII range is the same, the rest are accented letters,
+// box drawing, shades, arrows and a handful of greek and maths symbols.
+
+/// The glyph we draw when a character has no place in the font.
+pub const REPLACEMENT: u8 = 0xfe;  // ■
+
+/// What each of the 256 glyphs looks like in Unicode.
+/// The control range has glyphs too, the VGA doesn't care about controls.
+pub const TO_UNICODE: [char; 256] = [
+	'\0', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',  // 0x00
+	'►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',  // 0x10
+	' ', '!', '"', '#', '$', '%', '&', '\'', '(', ')', '*', '+', ',', '-', '.', '/',  // 0x20
+	'0', '1', '2', '3', '4', '5', '6', '7', '8', '9', ':', ';', '<', '=', '>', '?',  // 0x30
+	'@', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O',  // 0x40
+	'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', '[', '\\', ']', '^', '_',  // 0x50
+	'`', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o',  // 0x60
+	'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '{', '|', '}', '~', '⌂',  // 0x70
+	'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',  // 0x80
+	'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',  // 0x90
+	'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',  // 0xa0
+	'░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',  // 0xb0
+	'└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',  // 0xc0
+	'╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',  // 0xd0
+	'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',  // 0xe0
+	'≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',  // 0xf0
+];
+
+// characters that look close enough to a glyph with a different code point
+const ALIASES: &[(char, u8)] = &[
+	('β', 0xe1),  // ß is drawn like a beta anyway
+	('\u{3bc}', 0xe6),  // greek mu, the table has the micro sign
+	('\u{2126}', 0xea),  // ohm sign, the table has omega
+	('\u{2211}', 0xe4),  // n-ary sum, the table has sigma
+	('∅', 0xed),
+	('ϕ', 0xed),
+	('∈', 0xee),
+];
+
+/// Finds the glyph for `c`, if the font has one.
+pub fn from_char(c: char) -> Option<u8> {
+	match c {
+		// ASCII maps to itself, and it's by far the most common case
+		' '..='~' => Some(c as u8),
+		'\0' => None,
+		_ => TO_UNICODE.iter()
+			.position(|&glyph| glyph == c)
+			.map(|index| index as u8)
+			.or_else(|| {
+				ALIASES.iter()
+					.find(|&&(alias, _)| alias == c)
+					.map(|&(_, glyph)| glyph)
+			}),
+	}
+}
+
+/// The Unicode character a glyph stands for.
+pub fn to_char(glyph: u8) -> char {
+	TO_UNICODE[usize::from(glyph)]
+}
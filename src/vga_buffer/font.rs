@@ -0,0 +1,170 @@
+// The character generator reads its glyphs from plane 2 of the VGA memory,
+// 32 bytes for each of the 256 glyphs, one byte per scan line. Text mode
+// interleaves the planes, so plane 2 has to be mapped by itself to get at it.
+
+use super::registers::{
+	GraphicsController, Sequencer, GC_MISC, GC_MODE, GC_READ_MAP, SEQ_MAP_MASK, SEQ_MEMORY_MODE,
+};
+
+mod psf;
+
+pub use psf::PsfError;
+
+pub const GLYPHS: usize = 256;
+const GLYPH_STRIDE: usize = 32;
+
+/// The font we ship, an 8x16 PSF2 in code page 437 order. `font/LICENSE`
+/// says where its glyphs came from.
+pub static DEFAULT_PSF: &[u8] = include_bytes!("font/default-8x16.psf");
+
+/// Glyphs 8 pixels wide, a byte per line and up to 32 lines tall.
+#[derive(Clone)]
+pub struct Font {
+	height: usize,
+	glyphs: [[u8; GLYPH_STRIDE]; GLYPHS],
+}
+
+impl Font {
+	/// Every glyph empty, to be filled in with `set_glyph`.
+	pub fn blank(height: usize) -> Font {
+		Font {
+			height: height.min(GLYPH_STRIDE),
+			glyphs: [[0; GLYPH_STRIDE]; GLYPHS],
+		}
+	}
+
+	/// Reads a PC Screen Font, version 1 or 2, from wherever it came from,
+	/// like a file compiled in with `include_bytes!`. Glyphs for characters
+	/// code page 437 doesn't have are left out.
+	pub fn from_psf(data: &[u8]) -> Result<Font, PsfError> {
+		psf::parse(data)
+	}
+
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	/// The lines of a glyph, top first, the leftmost pixel in the top bit.
+	pub fn glyph(&self, glyph: u8) -> &[u8] {
+		&self.glyphs[usize::from(glyph)][..self.height]
+	}
+
+	/// Replaces a glyph, lines past the font's height are ignored.
+	pub fn set_glyph(&mut self, glyph: u8, lines: &[u8]) {
+		let height = self.height;
+		let glyph = &mut self.glyphs[usize::from(glyph)];
+		*glyph = [0; GLYPH_STRIDE];
+		for (line, &byte) in glyph.iter_mut().zip(lines).take(height) {
+			*line = byte;
+		}
+	}
+
+	/// Reads back `height` lines of every glyph the hardware has loaded.
+	/// `memory` is where 0xa0000 is mapped.
+	pub unsafe fn read(memory: *const u8, height: usize) -> Font {
+		let mut font = Font {
+			height,
+			glyphs: [[0; GLYPH_STRIDE]; GLYPHS],
+		};
+		with_plane_2(|| {
+			for (i, glyph) in font.glyphs.iter_mut().enumerate() {
+				for (line, byte) in glyph.iter_mut().enumerate().take(height) {
+					*byte = memory.add(i * GLYPH_STRIDE + line).read_volatile();
+				}
+			}
+		});
+		font
+	}
+
+	/// Hands the glyphs to the character generator, the CRTC's cell height
+	/// should match.
+	pub unsafe fn load(&self, memory: *mut u8) {
+		with_plane_2(|| {
+			for (i, glyph) in self.glyphs.iter().enumerate() {
+				for (line, &byte) in glyph.iter().enumerate() {
+					memory.add(i * GLYPH_STRIDE + line).write_volatile(byte);
+				}
+			}
+		});
+	}
+
+	/// A font half as tall, every pair of lines merged into one so thin
+	/// strokes don't disappear.
+	pub fn halved(&self) -> Font {
+		let mut font = Font {
+			height: self.height / 2,
+			glyphs: [[0; GLYPH_STRIDE]; GLYPHS],
+		};
+		for (from, to) in self.glyphs.iter().zip(font.glyphs.iter_mut()) {
+			for (line, byte) in to.iter_mut().enumerate().take(font.height) {
+				*byte = from[2 * line] | from[2 * line + 1];
+			}
+		}
+		font
+	}
+}
+
+// Maps plane 2 alone at 0xa0000 while `f` runs, then goes back to text mode's
+// interleaved planes at 0xb8000. Nothing else may touch the VGA memory meanwhile.
+unsafe fn with_plane_2<R>(f: impl FnOnce() -> R) -> R {
+	let mut sequencer = Sequencer::new();
+	let mut graphics = GraphicsController::new();
+
+	sequencer.write(SEQ_MAP_MASK, 0x04);
+	sequencer.write(SEQ_MEMORY_MODE, 0x07);  // no odd/even
+	graphics.write(GC_READ_MAP, 0x02);
+	graphics.write(GC_MODE, 0x00);
+	graphics.write(GC_MISC, 0x04);  // 64 KiB at 0xa0000
+
+	let result = f();
+
+	sequencer.write(SEQ_MAP_MASK, 0x03);
+	sequencer.write(SEQ_MEMORY_MODE, 0x03);
+	graphics.write(GC_READ_MAP, 0x00);
+	graphics.write(GC_MODE, 0x10);
+	graphics.write(GC_MISC, 0x0e);  // 32 KiB at 0xb8000, odd/even
+	result
+}
+
+
+#[test_case]
+fn test_psf_formats() {
+	use alloc::vec::Vec;
+
+	// the built-in font, in code page 437 order
+	let font = Font::from_psf(DEFAULT_PSF).expect("the built-in font should parse");
+	assert_eq!(font.height(), 16);
+	assert!(font.glyph(b'A').iter().any(|&line| line != 0));
+	assert!(font.glyph(b' ').iter().all(|&line| line == 0));
+	assert_eq!(font.glyph(0xdb), &[0xff; 16][..]);  // █
+
+	// a PSF2 with two glyphs, for 'A' and for '█' plus a sequence we skip
+	let mut psf2 = Vec::from(&[0x72, 0xb5, 0x4a, 0x86][..]);
+	for &field in [0u32, 32, 1, 2, 16, 16, 8].iter() {
+		psf2.extend_from_slice(&field.to_le_bytes());
+	}
+	psf2.extend_from_slice(&[0x18; 16]);
+	psf2.extend_from_slice(&[0xff; 16]);
+	psf2.extend_from_slice(b"A\xff");
+	psf2.extend_from_slice("█".as_bytes());
+	psf2.extend_from_slice(&[0xfe, b'e', 0xcc, 0x81, 0xff]);  // e and a combining acute
+	let font = Font::from_psf(&psf2).expect("the PSF2 should parse");
+	assert_eq!(font.height(), 16);
+	assert_eq!(font.glyph(b'A'), &[0x18; 16][..]);
+	assert_eq!(font.glyph(0xdb), &[0xff; 16][..]);  // █
+	assert!(font.glyph(b'e').iter().all(|&line| line == 0));
+
+	// a PSF1 whose only mapped glyph stands for 'é', plus a sequence we skip
+	let mut psf1 = Vec::from(&[0x36, 0x04, 0x02, 8][..]);
+	psf1.extend_from_slice(&[0x18; 8]);
+	psf1.extend(core::iter::repeat_n(0, 255 * 8));
+	psf1.extend_from_slice(&[0xe9, 0x00, 0xfe, 0xff, 0x65, 0x00, 0x01, 0x03, 0xff, 0xff]);
+	psf1.extend(core::iter::repeat_n(0xff, 255 * 2));
+	let font = Font::from_psf(&psf1).expect("the PSF1 should parse");
+	assert_eq!(font.height(), 8);
+	assert_eq!(font.glyph(0x82), &[0x18; 8][..]);
+	assert_eq!(font.glyph(0), &[0; 8][..]);
+
+	assert_eq!(Font::from_psf(&psf1[..100]).err(), Some(PsfError::Truncated));
+	assert_eq!(Font::from_psf(b"not a font").err(), Some(PsfError::BadMagic));
+}
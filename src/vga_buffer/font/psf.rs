@@ -0,0 +1,179 @@
+// PC Screen Font, the format the Linux console uses. Version 1 has 256 or
+// 512 glyphs 8 pixels wide, version 2 any number of any size. Either can
+// carry a table of the Unicode characters each glyph stands for, which is
+// how the glyphs find their place in code page 437.
+
+use super::{Font, GLYPHS, GLYPH_STRIDE};
+use crate::vga_buffer::cp437;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_UNICODE: u8 = 0x06;  // either of the table bits
+const PSF1_SEPARATOR: u16 = 0xffff;
+const PSF1_SEQUENCE: u16 = 0xfffe;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xff;
+const PSF2_SEQUENCE: u8 = 0xfe;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PsfError {
+	BadMagic,
+	Truncated,
+	/// Only glyphs up to 8 pixels wide and 32 tall fit in a text mode cell.
+	Size { width: u32, height: u32 },
+	BadUnicodeTable,
+}
+
+struct Header {
+	glyph_count: usize,
+	height: usize,
+	bytes_per_glyph: usize,
+	glyphs_start: usize,
+	unicode: Option<Unicode>,
+}
+
+#[derive(Clone, Copy)]
+enum Unicode {
+	Psf1,
+	Psf2,
+}
+
+pub fn parse(data: &[u8]) -> Result<Font, PsfError> {
+	let header = header(data)?;
+	let glyphs_end = header.glyphs_start + header.glyph_count * header.bytes_per_glyph;
+	let bitmaps = data.get(header.glyphs_start..glyphs_end).ok_or(PsfError::Truncated)?;
+	let bitmap = |glyph: usize| {
+		let start = glyph * header.bytes_per_glyph;
+		&bitmaps[start..start + header.height]
+	};
+
+	let mut font = Font::blank(header.height);
+	match header.unicode {
+		// without a table the glyphs had better be in code page 437 order
+		None => {
+			for glyph in 0..header.glyph_count.min(GLYPHS) {
+				font.set_glyph(glyph as u8, bitmap(glyph));
+			}
+		}
+		Some(format) => {
+			let mut table = &data[glyphs_end..];
+			for glyph in 0..header.glyph_count {
+				let (chars, rest) = next_entry(table, format)?;
+				table = rest;
+				chars.for_each(|c| {
+					if let Some(slot) = cp437::from_char(c) {
+						font.set_glyph(slot, bitmap(glyph));
+					}
+				});
+			}
+		}
+	}
+	Ok(font)
+}
+
+fn header(data: &[u8]) -> Result<Header, PsfError> {
+	if data.starts_with(&PSF1_MAGIC) {
+		let mode = *data.get(2).ok_or(PsfError::Truncated)?;
+		let height = usize::from(*data.get(3).ok_or(PsfError::Truncated)?);
+		check_size(8, height)?;
+		return Ok(Header {
+			glyph_count: if mode & PSF1_MODE_512 != 0 { 512 } else { 256 },
+			height,
+			bytes_per_glyph: height,
+			glyphs_start: 4,
+			unicode: if mode & PSF1_MODE_UNICODE != 0 { Some(Unicode::Psf1) } else { None },
+		});
+	}
+
+	if !data.starts_with(&PSF2_MAGIC) {
+		return Err(PsfError::BadMagic);
+	}
+	let field = |i: usize| -> Result<u32, PsfError> {
+		let bytes = data.get(4 + 4 * i..8 + 4 * i).ok_or(PsfError::Truncated)?;
+		Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+	};
+	// version, header size, flags, glyph count, bytes per glyph, height, width
+	let (header_size, flags, count) = (field(1)?, field(2)?, field(3)?);
+	let (bytes_per_glyph, height, width) = (field(4)?, field(5)?, field(6)?);
+	check_size(width, height as usize)?;
+	if bytes_per_glyph < height {
+		return Err(PsfError::Size { width, height });
+	}
+
+	Ok(Header {
+		glyph_count: count as usize,
+		height: height as usize,
+		bytes_per_glyph: bytes_per_glyph as usize,
+		glyphs_start: header_size as usize,
+		unicode: if flags & PSF2_HAS_UNICODE_TABLE != 0 { Some(Unicode::Psf2) } else { None },
+	})
+}
+
+fn check_size(width: u32, height: usize) -> Result<(), PsfError> {
+	match width <= 8 && height > 0 && height <= GLYPH_STRIDE {
+		true => Ok(()),
+		false => Err(PsfError::Size { width, height: height as u32 }),
+	}
+}
+
+// The characters one glyph stands for, and the rest of the table. Sequences
+// of combining characters come after the single characters, we skip them.
+fn next_entry(table: &[u8], format: Unicode) -> Result<(Chars<'_>, &[u8]), PsfError> {
+	match format {
+		Unicode::Psf1 => {
+			let mut end = 0;
+			loop {
+				let unit = table.get(end..end + 2).ok_or(PsfError::Truncated)?;
+				if u16::from_le_bytes([unit[0], unit[1]]) == PSF1_SEPARATOR {
+					break;
+				}
+				end += 2;
+			}
+			let entry = &table[..end];
+			let singles = entry.chunks(2)
+				.map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+				.position(|unit| unit == PSF1_SEQUENCE)
+				.map_or(entry, |sequences| &entry[..2 * sequences]);
+			Ok((Chars::Ucs2(singles), &table[end + 2..]))
+		}
+		Unicode::Psf2 => {
+			let end = table.iter()
+				.position(|&byte| byte == PSF2_SEPARATOR)
+				.ok_or(PsfError::Truncated)?;
+			let entry = &table[..end];
+			let singles = entry.iter()
+				.position(|&byte| byte == PSF2_SEQUENCE)
+				.map_or(entry, |sequences| &entry[..sequences]);
+			let singles = core::str::from_utf8(singles).map_err(|_| PsfError::BadUnicodeTable)?;
+			Ok((Chars::Utf8(singles.chars()), &table[end + 1..]))
+		}
+	}
+}
+
+enum Chars<'a> {
+	Ucs2(&'a [u8]),
+	Utf8(core::str::Chars<'a>),
+}
+
+impl Iterator for Chars<'_> {
+	type Item = char;
+
+	fn next(&mut self) -> Option<char> {
+		match self {
+			Chars::Ucs2(units) => loop {
+				let (unit, rest) = match units {
+					[low, high, rest @ ..] => (u16::from_le_bytes([*low, *high]), rest),
+					_ => return None,
+				};
+				*units = rest;
+				// surrogates can't stand for a glyph on their own
+				if let Some(c) = char::from_u32(u32::from(unit)) {
+					return Some(c);
+				}
+			},
+			Chars::Utf8(chars) => chars.next(),
+		}
+	}
+}
@@ -0,0 +1,503 @@
+//! Mode 13h: 320x200 pixels, a byte each. Chain-4 lays them out one after
+//! another from 0xa0000, so drawing is just writing bytes.
+//!
+//! `print!` keeps working in graphics mode. The kernel console still collects
+//! the text in the background, and `TEXT` draws a copy of it into the
+//! framebuffer with the console font.
+//!
+//! `print!` draws from interrupt handlers too, so lock `FRAMEBUFFER` and
+//! `TEXT` with interrupts off, and never `TEXT` while holding `FRAMEBUFFER`.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+use super::{cp437, registers, select_graphic_rendition, theme, ColorCode, Font, DEFAULT_COLOR};
+use crate::ansi::{Action, Csi, Parser};
+
+pub const WIDTH: usize = 320;
+pub const HEIGHT: usize = 200;
+
+// glyphs are a byte wide
+const GLYPH_WIDTH: usize = 8;
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// The pixels, they can only be drawn while `is_active`.
+pub static FRAMEBUFFER: Mutex<Framebuffer> = Mutex::new(Framebuffer { memory: 0 });
+
+/// Where `print!` goes in graphics mode.
+pub static TEXT: Mutex<TextConsole> = Mutex::new(TextConsole::new());
+
+/// Whether the screen is in graphics mode, see `vga_buffer::set_graphics_mode`.
+pub fn is_active() -> bool {
+	ACTIVE.load(Ordering::Relaxed)
+}
+
+/// The colour closest to an RGB value, 8 bits each. Colours 16 to 231 are a
+/// 6x6x6 cube and 232 to 255 greys, like xterm's 256 colours.
+pub fn rgb(red: u8, green: u8, blue: u8) -> u8 {
+	// the cube's levels are 0 and then 95 to 255 in steps of 40
+	fn level(value: u8) -> u8 {
+		match value {
+			0..=47 => 0,
+			48..=114 => 1,
+			value => (value - 35) / 40,
+		}
+	}
+
+	if red == green && green == blue && (8..=238).contains(&red) {
+		return 232 + (red - 3) / 10;
+	}
+	16 + 36 * level(red) + 6 * level(green) + level(blue)
+}
+
+/// Changes one of the colours, 6 bits each of red, green and blue.
+pub fn set_palette(index: u8, color: [u8; 3]) {
+	unsafe { registers::write_dac(index, color) };
+}
+
+// The first 16 colours are the text mode ones, so `Color` means the same here.
+fn load_palette() {
+	const COLORS: [[u8; 3]; 16] = [
+		[0, 0, 0], [0, 0, 42], [0, 42, 0], [0, 42, 42],
+		[42, 0, 0], [42, 0, 42], [42, 21, 0], [42, 42, 42],
+		[21, 21, 21], [21, 21, 63], [21, 63, 21], [21, 63, 63],
+		[63, 21, 21], [63, 21, 63], [63, 63, 21], [63, 63, 63],
+	];
+	const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+	for (i, &color) in COLORS.iter().enumerate() {
+		set_palette(i as u8, color);
+	}
+	for i in 0..216 {
+		let color = [LEVELS[i / 36] >> 2, LEVELS[i / 6 % 6] >> 2, LEVELS[i % 6] >> 2];
+		set_palette(16 + i as u8, color);
+	}
+	for i in 0..24 {
+		let grey = (8 + 10 * i) >> 2;
+		set_palette(232 + i, [grey; 3]);
+	}
+}
+
+// Called by `set_graphics_mode` once the registers are programmed.
+pub(super) fn enter(memory: usize, font: Font) {
+	load_palette();
+
+	// the 8x16 font is squashed like in the 8 line text modes, so 25 rows fit
+	let font = match font.height() {
+		16 => font.halved(),
+		_ => font,
+	};
+	FRAMEBUFFER.lock().memory = memory;
+
+	let mut text = TEXT.lock();
+	text.font = Some(font);
+	text.color_code = theme().text;
+	text.reversed = false;
+	text.set_area(Rect::SCREEN);
+	ACTIVE.store(true, Ordering::Relaxed);
+}
+
+// Called by `set_mode` before it goes back to text.
+pub(super) fn leave() {
+	ACTIVE.store(false, Ordering::Relaxed);
+	FRAMEBUFFER.lock().memory = 0;
+}
+
+// For the panic screen, which can't wait for the lock. Nothing draws once
+// `ACTIVE` is clear.
+pub(super) fn abandon() {
+	ACTIVE.store(false, Ordering::Relaxed);
+}
+
+
+/// A rectangle in pixels, it may reach past the edges of the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+	pub x: i32,
+	pub y: i32,
+	pub width: i32,
+	pub height: i32,
+}
+
+impl Rect {
+	pub const SCREEN: Rect = Rect::new(0, 0, WIDTH as i32, HEIGHT as i32);
+
+	pub const fn new(x: i32, y: i32, width: i32, height: i32) -> Rect {
+		Rect { x, y, width, height }
+	}
+
+	// the part on screen, as the columns x0..x1 of the rows y0..y1
+	fn clip(&self) -> Option<(usize, usize, usize, usize)> {
+		let x0 = self.x.max(0);
+		let y0 = self.y.max(0);
+		let x1 = self.x.saturating_add(self.width).min(WIDTH as i32);
+		let y1 = self.y.saturating_add(self.height).min(HEIGHT as i32);
+		if x0 >= x1 || y0 >= y1 {
+			return None;
+		}
+		Some((x0 as usize, y0 as usize, x1 as usize, y1 as usize))
+	}
+}
+
+/// The drawing primitives. Anything off screen is clipped, and nothing is
+/// drawn at all outside graphics mode.
+pub struct Framebuffer {
+	memory: usize,  // where 0xa0000 is mapped, 0 in text mode
+}
+
+impl Framebuffer {
+	pub fn put_pixel(&mut self, x: i32, y: i32, color: u8) {
+		if let Some((x0, y0, x1, _)) = Rect::new(x, y, 1, 1).clip() {
+			self.fill_span(y0, x0, x1, color);
+		}
+	}
+
+	/// `None` off screen, or outside graphics mode.
+	pub fn pixel(&self, x: i32, y: i32) -> Option<u8> {
+		let (x, y, _, _) = Rect::new(x, y, 1, 1).clip()?;
+		match self.memory {
+			0 => None,
+			memory => Some(unsafe { (memory as *const u8).add(y * WIDTH + x).read_volatile() }),
+		}
+	}
+
+	pub fn clear(&mut self, color: u8) {
+		self.fill_rect(Rect::SCREEN, color);
+	}
+
+	pub fn fill_rect(&mut self, rect: Rect, color: u8) {
+		if let Some((x0, y0, x1, y1)) = rect.clip() {
+			for y in y0..y1 {
+				self.fill_span(y, x0, x1, color);
+			}
+		}
+	}
+
+	/// The outline of `rect`, a pixel thick and inside it.
+	pub fn draw_rect(&mut self, rect: Rect, color: u8) {
+		let Rect { x, y, width, height } = rect;
+		self.fill_rect(Rect::new(x, y, width, 1), color);
+		self.fill_rect(Rect::new(x, y + height - 1, width, 1), color);
+		self.fill_rect(Rect::new(x, y, 1, height), color);
+		self.fill_rect(Rect::new(x + width - 1, y, 1, height), color);
+	}
+
+	/// Both ends are drawn.
+	pub fn draw_line(&mut self, from: (i32, i32), to: (i32, i32), color: u8) {
+		// Bresenham, with the error term covering both directions at once
+		let (mut x, mut y) = from;
+		let dx = (to.0 - x).abs();
+		let dy = -(to.1 - y).abs();
+		let step_x = if x < to.0 { 1 } else { -1 };
+		let step_y = if y < to.1 { 1 } else { -1 };
+		let mut error = dx + dy;
+
+		loop {
+			self.put_pixel(x, y, color);
+			if (x, y) == to {
+				break;
+			}
+			if 2 * error >= dy {
+				error += dy;
+				x += step_x;
+			}
+			if 2 * error <= dx {
+				error += dx;
+				y += step_y;
+			}
+		}
+	}
+
+	/// Copies an image `width` pixels wide, row after row, with its top left
+	/// corner at (`x`, `y`). Pixels of the `transparent` colour are skipped.
+	pub fn blit(&mut self, x: i32, y: i32, width: usize, pixels: &[u8], transparent: Option<u8>) {
+		if width == 0 {
+			return;
+		}
+		for (row, line) in pixels.chunks(width).enumerate() {
+			for (col, &color) in line.iter().enumerate() {
+				if Some(color) != transparent {
+					self.put_pixel(x + col as i32, y + row as i32, color);
+				}
+			}
+		}
+	}
+
+	/// Draws a glyph of `font` with its top left corner at (`x`, `y`).
+	/// Without a `background` the pixels between the strokes are left alone.
+	pub fn draw_glyph(
+		&mut self, font: &Font, glyph: u8, x: i32, y: i32, foreground: u8, background: Option<u8>
+	) {
+		for (row, &line) in font.glyph(glyph).iter().enumerate() {
+			for col in 0..GLYPH_WIDTH {
+				let color = match line & (0x80 >> col) != 0 {
+					true => Some(foreground),
+					false => background,
+				};
+				if let Some(color) = color {
+					self.put_pixel(x + col as i32, y + row as i32, color);
+				}
+			}
+		}
+	}
+
+	/// Moves what's in `rect` up by `lines` pixels, the lines at the bottom
+	/// are filled with `fill`.
+	pub fn scroll_up(&mut self, rect: Rect, lines: usize, fill: u8) {
+		let (x0, y0, x1, y1) = match rect.clip() {
+			Some(clipped) => clipped,
+			None => return,
+		};
+		let lines = lines.min(y1 - y0);
+
+		if self.memory != 0 {
+			let pixels = self.memory as *mut u8;
+			for y in y0..y1 - lines {
+				for x in x0..x1 {
+					unsafe {
+						let moved = pixels.add((y + lines) * WIDTH + x).read_volatile();
+						pixels.add(y * WIDTH + x).write_volatile(moved);
+					}
+				}
+			}
+		}
+		for y in y1 - lines..y1 {
+			self.fill_span(y, x0, x1, fill);
+		}
+	}
+
+	// the pixels x0..x1 of row y, already clipped
+	fn fill_span(&mut self, y: usize, x0: usize, x1: usize, color: u8) {
+		if self.memory == 0 {
+			return;
+		}
+		let pixels = self.memory as *mut u8;
+		for x in x0..x1 {
+			unsafe { pixels.add(y * WIDTH + x).write_volatile(color) };
+		}
+	}
+}
+
+
+/// Text drawn into the framebuffer in cells the size of the font's glyphs.
+/// It understands the same colours and the basic cursor movements the
+/// `Writer` does.
+pub struct TextConsole {
+	font: Option<Font>,  // there's nothing to draw with before graphics mode
+	area: Rect,
+	column: usize,
+	row: usize,
+	color_code: ColorCode,
+	reversed: bool,
+	parser: Parser,
+}
+
+impl TextConsole {
+	const fn new() -> TextConsole {
+		TextConsole {
+			font: None,
+			area: Rect::SCREEN,
+			column: 0,
+			row: 0,
+			color_code: DEFAULT_COLOR,
+			reversed: false,
+			parser: Parser::new(),
+		}
+	}
+
+	/// Keeps the text inside `area`, leaving the rest of the screen to
+	/// whatever else draws. The area is cleared.
+	pub fn set_area(&mut self, area: Rect) {
+		self.area = area;
+		self.home();
+	}
+
+	pub fn area(&self) -> Rect {
+		self.area
+	}
+
+	pub fn columns(&self) -> usize {
+		self.area.clip().map_or(0, |(x0, _, x1, _)| (x1 - x0) / GLYPH_WIDTH)
+	}
+
+	pub fn rows(&self) -> usize {
+		let height = match &self.font {
+			Some(font) if font.height() > 0 => font.height(),
+			_ => return 0,
+		};
+		self.area.clip().map_or(0, |(_, y0, _, y1)| (y1 - y0) / height)
+	}
+
+	// clears the area and starts again in its top left corner
+	fn home(&mut self) {
+		let background = self.background();
+		FRAMEBUFFER.lock().fill_rect(self.area, background);
+		self.column = 0;
+		self.row = 0;
+	}
+
+	fn foreground(&self) -> u8 {
+		self.color_code.0 & 0x0f
+	}
+
+	fn background(&self) -> u8 {
+		self.color_code.0 >> 4
+	}
+
+	fn put_glyph(&mut self, framebuffer: &mut Framebuffer, glyph: u8) {
+		let (x0, y0) = match self.area.clip() {
+			Some((x0, y0, _, _)) => (x0, y0),
+			None => return,
+		};
+		if self.column >= self.columns() {
+			self.new_line(framebuffer);
+		}
+
+		let (foreground, background) = (self.foreground(), self.background());
+		if let Some(font) = &self.font {
+			let x = x0 + self.column * GLYPH_WIDTH;
+			let y = y0 + self.row * font.height();
+			framebuffer.draw_glyph(font, glyph, x as i32, y as i32, foreground, Some(background));
+		}
+		self.column += 1;
+	}
+
+	fn new_line(&mut self, framebuffer: &mut Framebuffer) {
+		self.column = 0;
+		if self.row + 1 < self.rows() {
+			self.row += 1;
+			return;
+		}
+
+		// only whole rows scroll, a leftover strip at the bottom stays as it is
+		let height = self.font.as_ref().map_or(0, Font::height);
+		let text = Rect {
+			height: (self.rows() * height) as i32,
+			..self.area
+		};
+		framebuffer.scroll_up(text, height, self.background());
+	}
+
+	fn perform(&mut self, framebuffer: &mut Framebuffer, action: Action) {
+		match action {
+			Action::Print(c) => {
+				let glyph = cp437::from_char(c).unwrap_or(cp437::REPLACEMENT);
+				self.put_glyph(framebuffer, glyph);
+			}
+			Action::Execute(b'\n') => self.new_line(framebuffer),
+			Action::Execute(b'\r') => self.column = 0,
+			Action::Execute(b'\t') => {
+				let next_stop = (self.column / 8 + 1) * 8;
+				self.column = next_stop.min(self.columns());
+			}
+			Action::Execute(0x08) => self.column = self.column.saturating_sub(1),
+			Action::Execute(_) => {}
+			Action::Csi(csi) => self.csi_dispatch(framebuffer, &csi),
+			Action::Reset => {
+				self.color_code = theme().text;
+				self.reversed = false;
+				framebuffer.fill_rect(self.area, self.background());
+				self.column = 0;
+				self.row = 0;
+			}
+		}
+	}
+
+	fn csi_dispatch(&mut self, framebuffer: &mut Framebuffer, csi: &Csi) {
+		if csi.private {
+			return;
+		}
+
+		match csi.final_byte {
+			b'm' => {
+				let (color, reversed) = select_graphic_rendition(self.color_code, self.reversed, theme().text, csi);
+				self.color_code = color;
+				self.reversed = reversed;
+			}
+			b'H' | b'f' => {
+				let row = usize::from(csi.param(0, 1)) - 1;
+				let column = usize::from(csi.param(1, 1)) - 1;
+				self.row = row.min(self.rows().saturating_sub(1));
+				self.column = column.min(self.columns().saturating_sub(1));
+			}
+			// no partial erasing here, it's only for showing the log
+			b'J' if csi.param(0, 0) == 2 => {
+				framebuffer.fill_rect(self.area, self.background());
+			}
+			_ => {}
+		}
+	}
+}
+
+impl fmt::Write for TextConsole {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		let mut framebuffer = FRAMEBUFFER.lock();
+		for byte in s.bytes() {
+			if let Some(action) = self.parser.advance(byte) {
+				self.perform(&mut framebuffer, action);
+			}
+		}
+		Ok(())
+	}
+}
+
+
+#[test_case]
+fn test_graphics_mode() {
+	use core::fmt::Write;
+	use super::{set_graphics_mode, set_mode, text_mode, CONSOLES, KERNEL_CONSOLE};
+	use x86_64::instructions::interrupts;
+
+	let mode = text_mode();
+	set_graphics_mode().expect("the VGA memory should be mapped");
+	assert!(is_active());
+
+	interrupts::without_interrupts(|| {
+		let mut framebuffer = FRAMEBUFFER.lock();
+		framebuffer.clear(0);
+		framebuffer.put_pixel(-1, 0, 15);  // off screen
+		assert_eq!(framebuffer.pixel(0, 0), Some(0));
+
+		framebuffer.draw_line((0, 0), (9, 9), 15);
+		assert_eq!(framebuffer.pixel(5, 5), Some(15));
+		assert_eq!(framebuffer.pixel(6, 5), Some(0));
+
+		framebuffer.fill_rect(Rect::new(300, 190, 40, 40), 4);
+		assert_eq!(framebuffer.pixel(319, 199), Some(4));
+		assert_eq!(framebuffer.pixel(299, 199), Some(0));
+
+		framebuffer.draw_rect(Rect::new(20, 20, 10, 10), 2);
+		assert_eq!(framebuffer.pixel(20, 25), Some(2));
+		assert_eq!(framebuffer.pixel(29, 29), Some(2));
+		assert_eq!(framebuffer.pixel(25, 25), Some(0));
+
+		framebuffer.blit(40, 40, 2, &[1, 2, 3, 4], Some(2));
+		assert_eq!(framebuffer.pixel(40, 40), Some(1));
+		assert_eq!(framebuffer.pixel(41, 40), Some(0));
+		assert_eq!(framebuffer.pixel(40, 41), Some(3));
+	});
+
+	// a light blue █ in a corner of its own
+	interrupts::without_interrupts(|| {
+		let mut text = TEXT.lock();
+		text.set_area(Rect::new(100, 100, 64, 16));
+		assert_eq!((text.columns(), text.rows()), (8, 2));
+		write!(text, "\x1b[94m█\x1b[0m").unwrap();
+
+		let framebuffer = FRAMEBUFFER.lock();
+		assert_eq!(framebuffer.pixel(100, 100), Some(9));
+		assert_eq!(framebuffer.pixel(107, 107), Some(9));
+		assert_eq!(framebuffer.pixel(108, 100), Some(0));
+		drop(framebuffer);
+		text.set_area(Rect::SCREEN);
+	});
+	crate::println!("println! still works in graphics mode");
+
+	set_mode(mode).expect("the VGA memory should be mapped");
+	assert!(!is_active());
+	interrupts::without_interrupts(|| {
+		assert!(CONSOLES[KERNEL_CONSOLE].lock().is_visible());
+	});
+}
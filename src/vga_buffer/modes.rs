@@ -0,0 +1,183 @@
+// Register values for the text modes and mode 13h, after Chris Giese's modes.c.
+// Everything but the font and the palette comes from these tables.
+
+use super::registers::{
+	write_misc_output, AttributeController, Crtc, GraphicsController, Sequencer,
+	CRTC_HORIZONTAL_BLANK_END, CRTC_VERTICAL_RETRACE_END,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextMode {
+	/// What the BIOS leaves us in, with the 8x16 font
+	Text80x25,
+	/// The same timings with an 8x8 font
+	Text80x50,
+	/// 480 scan lines and 8 pixel wide cells, with the 8x8 font
+	Text90x60,
+}
+
+impl TextMode {
+	pub const ALL: [TextMode; 3] = [TextMode::Text80x25, TextMode::Text80x50, TextMode::Text90x60];
+
+	pub fn width(self) -> usize {
+		match self {
+			TextMode::Text80x25 | TextMode::Text80x50 => 80,
+			TextMode::Text90x60 => 90,
+		}
+	}
+
+	pub fn height(self) -> usize {
+		match self {
+			TextMode::Text80x25 => 25,
+			TextMode::Text80x50 => 50,
+			TextMode::Text90x60 => 60,
+		}
+	}
+
+	pub fn font_height(self) -> usize {
+		match self {
+			TextMode::Text80x25 => 16,
+			TextMode::Text80x50 | TextMode::Text90x60 => 8,
+		}
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			TextMode::Text80x25 => "80x25",
+			TextMode::Text80x50 => "80x50",
+			TextMode::Text90x60 => "90x60",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<TextMode> {
+		TextMode::ALL.iter().copied().find(|mode| mode.name() == name)
+	}
+
+	fn registers(self) -> &'static Registers {
+		match self {
+			TextMode::Text80x25 => &TEXT_80X25,
+			TextMode::Text80x50 => &TEXT_80X50,
+			TextMode::Text90x60 => &TEXT_90X60,
+		}
+	}
+}
+
+struct Registers {
+	misc: u8,
+	sequencer: [u8; 5],
+	crtc: [u8; 25],
+	graphics: [u8; 9],
+	attribute: [u8; 21],
+}
+
+const GRAPHICS: [u8; 9] = [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0e, 0x00, 0xff];
+const ATTRIBUTE: [u8; 21] = [
+	0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07,
+	0x38, 0x39, 0x3a, 0x3b, 0x3c, 0x3d, 0x3e, 0x3f,
+	0x0c, 0x00, 0x0f, 0x08, 0x00,
+];
+
+static TEXT_80X25: Registers = Registers {
+	misc: 0x67,
+	sequencer: [0x03, 0x00, 0x03, 0x00, 0x02],
+	crtc: [
+		0x5f, 0x4f, 0x50, 0x82, 0x55, 0x81, 0xbf, 0x1f,
+		0x00, 0x4f, 0x0d, 0x0e, 0x00, 0x00, 0x00, 0x50,
+		0x9c, 0x0e, 0x8f, 0x28, 0x1f, 0x96, 0xb9, 0xa3,
+		0xff,
+	],
+	graphics: GRAPHICS,
+	attribute: ATTRIBUTE,
+};
+
+static TEXT_80X50: Registers = Registers {
+	misc: 0x67,
+	sequencer: [0x03, 0x00, 0x03, 0x00, 0x02],
+	crtc: [
+		0x5f, 0x4f, 0x50, 0x82, 0x55, 0x81, 0xbf, 0x1f,
+		0x00, 0x47, 0x06, 0x07, 0x00, 0x00, 0x01, 0x40,
+		0x9c, 0x8e, 0x8f, 0x28, 0x1f, 0x96, 0xb9, 0xa3,
+		0xff,
+	],
+	graphics: GRAPHICS,
+	attribute: ATTRIBUTE,
+};
+
+static TEXT_90X60: Registers = Registers {
+	misc: 0xe7,
+	sequencer: [0x03, 0x01, 0x03, 0x00, 0x02],
+	crtc: [
+		0x6b, 0x59, 0x5a, 0x82, 0x60, 0x8d, 0x0b, 0x3e,
+		0x00, 0x47, 0x06, 0x07, 0x00, 0x00, 0x00, 0x00,
+		0xea, 0x0c, 0xdf, 0x2d, 0x08, 0xe8, 0x05, 0xa3,
+		0xff,
+	],
+	graphics: GRAPHICS,
+	attribute: ATTRIBUTE,
+};
+
+// 320x200 in 256 colours, chain-4 puts the pixels one after another at 0xa0000
+static GRAPHICS_320X200: Registers = Registers {
+	misc: 0x63,
+	sequencer: [0x03, 0x01, 0x0f, 0x00, 0x0e],
+	crtc: [
+		0x5f, 0x4f, 0x50, 0x82, 0x54, 0x80, 0xbf, 0x1f,
+		0x00, 0x41, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+		0x9c, 0x0e, 0x8f, 0x28, 0x40, 0x96, 0xb9, 0xa3,
+		0xff,
+	],
+	graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x05, 0x0f, 0xff],
+	attribute: [
+		0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
+		0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+		0x41, 0x00, 0x0f, 0x00, 0x00,
+	],
+};
+
+/// Reprograms the timings and the cell size. The font and the text memory
+/// are left alone, so the caller has to redraw and load a matching font.
+pub unsafe fn program(mode: TextMode) {
+	write_registers(mode.registers());
+}
+
+/// Mode 13h. The pixels overwrite the text and the font in planes 0 to 2,
+/// and the palette is whatever the DAC holds.
+pub unsafe fn program_graphics() {
+	write_registers(&GRAPHICS_320X200);
+}
+
+unsafe fn write_registers(registers: &Registers) {
+	write_misc_output(registers.misc);
+
+	let mut sequencer = Sequencer::new();
+	for (i, &value) in registers.sequencer.iter().enumerate() {
+		sequencer.write(i as u8, value);
+	}
+
+	// the timing registers are write protected until we say otherwise,
+	// and the tables have to keep them unprotected
+	let mut crtc = Crtc::new();
+	let blank_end = crtc.read(CRTC_HORIZONTAL_BLANK_END);
+	crtc.write(CRTC_HORIZONTAL_BLANK_END, blank_end | 0x80);
+	let retrace_end = crtc.read(CRTC_VERTICAL_RETRACE_END);
+	crtc.write(CRTC_VERTICAL_RETRACE_END, retrace_end & !0x80);
+	for (i, &value) in registers.crtc.iter().enumerate() {
+		let value = match i as u8 {
+			CRTC_HORIZONTAL_BLANK_END => value | 0x80,
+			CRTC_VERTICAL_RETRACE_END => value & !0x80,
+			_ => value,
+		};
+		crtc.write(i as u8, value);
+	}
+
+	let mut graphics = GraphicsController::new();
+	for (i, &value) in registers.graphics.iter().enumerate() {
+		graphics.write(i as u8, value);
+	}
+
+	let mut attribute = AttributeController::new();
+	for (i, &value) in registers.attribute.iter().enumerate() {
+		attribute.write(i as u8, value);
+	}
+	attribute.enable_display();
+}
@@ -0,0 +1,112 @@
+// The VGA hardware is programmed through a handful of indexed registers:
+// write the register number to the index port, then read or write the data port.
+
+use x86_64::instructions::port::{Port, PortReadOnly, PortWriteOnly};
+
+pub const CRTC_MAX_SCAN_LINE: u8 = 0x09;
+pub const CRTC_CURSOR_START:  u8 = 0x0a;
+pub const CRTC_CURSOR_END:    u8 = 0x0b;
+pub const CRTC_START_HIGH:    u8 = 0x0c;
+pub const CRTC_START_LOW:     u8 = 0x0d;
+pub const CRTC_CURSOR_HIGH:   u8 = 0x0e;
+pub const CRTC_CURSOR_LOW:    u8 = 0x0f;
+pub const CRTC_HORIZONTAL_BLANK_END: u8 = 0x03;
+pub const CRTC_VERTICAL_RETRACE_END: u8 = 0x11;
+
+pub const SEQ_MAP_MASK:    u8 = 0x02;
+pub const SEQ_MEMORY_MODE: u8 = 0x04;
+
+pub const GC_READ_MAP: u8 = 0x04;
+pub const GC_MODE:     u8 = 0x05;
+pub const GC_MISC:     u8 = 0x06;
+
+macro_rules! indexed_registers {
+	($(#[$doc:meta])* $name:ident, $index:expr) => {
+		$(#[$doc])*
+		pub struct $name {
+			index: Port<u8>,
+			data: Port<u8>,
+		}
+
+		impl $name {
+			pub const fn new() -> $name {
+				$name {
+					index: Port::new($index),
+					data: Port::new($index + 1),
+				}
+			}
+
+			/// Unsafe because the registers change how the whole screen is driven.
+			pub unsafe fn read(&mut self, register: u8) -> u8 {
+				self.index.write(register);
+				self.data.read()
+			}
+
+			pub unsafe fn write(&mut self, register: u8, value: u8) {
+				self.index.write(register);
+				self.data.write(value);
+			}
+		}
+	};
+}
+
+indexed_registers!(
+	/// The CRT controller, it owns the cursor and the display timings.
+	/// The ports are at 0x3d4/0x3d5 as long as the colour emulation bit is set,
+	/// which it always is in text mode.
+	Crtc, 0x3d4
+);
+
+indexed_registers!(
+	/// The sequencer, it owns the dot clock and which planes the CPU writes to.
+	Sequencer, 0x3c4
+);
+
+indexed_registers!(
+	/// The graphics controller, it owns which plane the CPU reads from
+	/// and where the VGA memory shows up.
+	GraphicsController, 0x3ce
+);
+
+/// The attribute controller shares one port for the index and the data,
+/// a flip-flop that's reset by reading the input status register decides which.
+pub struct AttributeController {
+	port: PortWriteOnly<u8>,
+	status: PortReadOnly<u8>,
+}
+
+impl AttributeController {
+	pub const fn new() -> AttributeController {
+		AttributeController {
+			port: PortWriteOnly::new(0x3c0),
+			status: PortReadOnly::new(0x3da),
+		}
+	}
+
+	/// Leaves the palette unlocked, and so the screen blank, until `enable_display`.
+	pub unsafe fn write(&mut self, register: u8, value: u8) {
+		self.status.read();
+		self.port.write(register);
+		self.port.write(value);
+	}
+
+	pub unsafe fn enable_display(&mut self) {
+		self.status.read();
+		self.port.write(0x20);
+	}
+}
+
+/// Picks the dot clock and the sync polarities, which set the number of scan lines.
+pub unsafe fn write_misc_output(value: u8) {
+	PortWriteOnly::new(0x3c2).write(value);
+}
+
+/// Sets one of the 256 colours the DAC turns pixels into, 6 bits each of red,
+/// green and blue.
+pub unsafe fn write_dac(index: u8, [red, green, blue]: [u8; 3]) {
+	PortWriteOnly::new(0x3c8).write(index);
+	let mut data = PortWriteOnly::new(0x3c9);
+	data.write(red);
+	data.write(green);
+	data.write(blue);
+}
@@ -0,0 +1,51 @@
+// Lines that scroll off the top of the screen end up here
+// instead of being thrown away.
+
+use alloc::collections::VecDeque;
+use super::Line;
+
+pub struct Scrollback {
+	history: VecDeque<Line>,  // oldest line first
+	depth: usize,
+	offset: usize,  // how many lines we're looking back, 0 is live
+}
+
+impl Scrollback {
+	/// Allocates everything up front, so the writer never allocates while printing.
+	pub fn new(depth: usize) -> Scrollback {
+		Scrollback {
+			history: VecDeque::with_capacity(depth),
+			depth,
+			offset: 0,
+		}
+	}
+
+	/// Call with the top row of the screen right before it's overwritten.
+	pub fn push_line(&mut self, line: &Line) {
+		if self.depth == 0 {
+			return;
+		}
+		if self.history.len() == self.depth {
+			self.history.pop_front();
+		}
+		self.history.push_back(*line);
+	}
+
+	pub fn offset(&self) -> usize {
+		self.offset
+	}
+
+	/// Looks `offset` lines back, 0 goes back to live.
+	/// Returns whether the view changed, the screen needs redrawing if it did.
+	pub fn scroll_to(&mut self, offset: usize) -> bool {
+		let offset = offset.min(self.history.len());
+		let changed = offset != self.offset;
+		self.offset = offset;
+		changed
+	}
+
+	/// The line that went off the top `back` lines ago, 1 is the most recent.
+	pub fn line(&self, back: usize) -> &Line {
+		&self.history[self.history.len() - back]
+	}
+}
@@ -0,0 +1,311 @@
+// A copy of everything a console shows, glyphs and colours, to compare
+// against golden fixtures in tests.
+//
+// A fixture is the screen as text, one line per row, with the glyphs as
+// their Unicode characters. Blanks at the end of a row and blank rows at the
+// bottom can be left out. The colours are optional, after a `---` line comes
+// one line per row with a key for every cell:
+//
+//   ' '         the console's default colour, also for anything left out
+//   '0'..='f'   that foreground on the default background
+//   others      whatever the legend says
+//
+// The legend goes after another `---` line, a key and the attribute byte in
+// hex on each line, like `r 4f` for white on red.
+//
+// Without the colour part only the text is checked.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use super::{cp437, ColorCode, Writer};
+
+/// Everything a console shows, glyphs and colours, taken at one moment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+	width: usize,
+	height: usize,
+	cells: Vec<(u8, ColorCode)>,  // row after row
+	default_color: ColorCode,
+}
+
+impl Writer {
+	/// The whole screen as this console shows it, the status row too.
+	pub fn snapshot(&self) -> Snapshot {
+		let (width, height) = (self.width, self.screen_height());
+		let mut cells = Vec::with_capacity(width * height);
+		for row in 0..height {
+			let line = self.displayed_row(row);
+			cells.extend(line[..width].iter().map(|cell| (cell.ascii_character, cell.color_code)));
+		}
+		Snapshot {
+			width,
+			height,
+			cells,
+			default_color: self.default_color,
+		}
+	}
+}
+
+impl Snapshot {
+	pub fn width(&self) -> usize {
+		self.width
+	}
+
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	/// The glyph and colour of a cell, `None` off the screen.
+	pub fn cell(&self, row: usize, col: usize) -> Option<(u8, ColorCode)> {
+		if row >= self.height || col >= self.width {
+			return None;
+		}
+		Some(self.cells[row * self.width + col])
+	}
+
+	/// A row as Unicode, without the blanks at the end.
+	pub fn row_text(&self, row: usize) -> String {
+		let mut text: String = self.row(row).iter().map(|&(glyph, _)| cp437::to_char(glyph)).collect();
+		text.truncate(text.trim_end_matches(' ').len());
+		text
+	}
+
+	/// The whole screen as Unicode, a line per row, the way a fixture has it.
+	pub fn text(&self) -> String {
+		let mut text = String::new();
+		for row in 0..self.height {
+			text.push_str(&self.row_text(row));
+			text.push('\n');
+		}
+		text.truncate(text.trim_end_matches('\n').len());
+		text
+	}
+
+	fn row(&self, row: usize) -> &[(u8, ColorCode)] {
+		match row < self.height {
+			true => &self.cells[row * self.width..(row + 1) * self.width],
+			false => &[],
+		}
+	}
+
+	/// Checks the screen against a golden fixture, see the top of this file
+	/// for what one looks like. The `Diff` says which rows are different.
+	pub fn compare(&self, golden: &str) -> Result<(), Diff> {
+		let golden = Golden::parse(golden, self.default_color);
+		let mut diff = Diff { rows: Vec::new() };
+
+		for row in 0..self.height.max(golden.text.len()) {
+			let expected = golden.text.get(row).copied().unwrap_or("").trim_end_matches(' ');
+			let actual = self.row_text(row);
+			if expected != actual {
+				diff.rows.push(RowDiff {
+					row,
+					kind: "text",
+					expected: String::from(expected),
+					actual,
+					detail: None,
+				});
+			}
+		}
+
+		if let Some(colors) = &golden.colors {
+			for row in 0..self.height.max(colors.len()) {
+				let expected = colors.get(row).copied().unwrap_or("");
+				let cells = self.row(row);
+				let detail = cells.iter().enumerate().find_map(|(col, &(_, actual))| {
+					let key = expected.chars().nth(col).unwrap_or(' ');
+					let wanted = golden.color(key);
+					(wanted != Some(actual)).then_some(ColorDetail { col, expected: wanted, actual })
+				});
+				if detail.is_some() {
+					diff.rows.push(RowDiff {
+						row,
+						kind: "colours",
+						expected: String::from(expected.trim_end_matches(' ')),
+						actual: golden.keys(cells),
+						detail,
+					});
+				}
+			}
+		}
+
+		match diff.rows.is_empty() {
+			true => Ok(()),
+			false => Err(diff),
+		}
+	}
+}
+
+// a fixture taken apart
+struct Golden<'a> {
+	text: Vec<&'a str>,
+	colors: Option<Vec<&'a str>>,
+	legend: Vec<(char, ColorCode)>,
+	default_color: ColorCode,
+}
+
+impl<'a> Golden<'a> {
+	// a broken fixture is a broken test, so this panics
+	fn parse(golden: &'a str, default_color: ColorCode) -> Golden<'a> {
+		let mut parts = golden.split("\n---\n");
+		let text = parts.next().unwrap_or("").lines().collect();
+		let colors = parts.next().map(|colors| colors.lines().collect());
+		let legend = parts.next().unwrap_or("").lines()
+			.filter(|line| !line.trim().is_empty())
+			.map(|line| {
+				let mut chars = line.chars();
+				let key = chars.next().unwrap();
+				let attribute = u8::from_str_radix(chars.as_str().trim(), 16)
+					.unwrap_or_else(|_| panic!("bad legend line in golden fixture: {:?}", line));
+				(key, ColorCode(attribute))
+			})
+			.collect();
+		assert!(parts.next().is_none(), "golden fixture has more than three parts");
+
+		Golden {
+			text,
+			colors,
+			legend,
+			default_color,
+		}
+	}
+
+	// the colour a key stands for, `None` if it stands for nothing
+	fn color(&self, key: char) -> Option<ColorCode> {
+		if let Some(&(_, color)) = self.legend.iter().find(|&&(k, _)| k == key) {
+			return Some(color);
+		}
+		match key {
+			' ' => Some(self.default_color),
+			_ => key.to_digit(16).map(|foreground| self.default_color.with_foreground(foreground as u8)),
+		}
+	}
+
+	// the colours of a row as keys, the way the fixture would write them,
+	// with '?' for anything it has no key for
+	fn keys(&self, cells: &[(u8, ColorCode)]) -> String {
+		let mut keys: String = cells.iter().map(|&(_, color)| {
+			self.legend.iter().find(|&&(_, c)| c == color).map(|&(key, _)| key)
+				.or_else(|| (color == self.default_color).then_some(' '))
+				.or_else(|| {
+					let foreground = color.with_foreground(self.default_color.0 & 0x0f);
+					(foreground == self.default_color).then(|| char::from_digit(u32::from(color.0 & 0x0f), 16).unwrap())
+				})
+				.unwrap_or('?')
+		}).collect();
+		keys.truncate(keys.trim_end_matches(' ').len());
+		keys
+	}
+}
+
+/// How a screen differs from a golden fixture, printable as a diff.
+#[derive(Debug)]
+pub struct Diff {
+	rows: Vec<RowDiff>,
+}
+
+#[derive(Debug)]
+struct RowDiff {
+	row: usize,
+	kind: &'static str,  // "text" or "colours"
+	expected: String,
+	actual: String,
+	detail: Option<ColorDetail>,
+}
+
+// the first cell with the wrong colour, since '?' doesn't say much
+#[derive(Debug)]
+struct ColorDetail {
+	col: usize,
+	expected: Option<ColorCode>,
+	actual: ColorCode,
+}
+
+impl Diff {
+	/// The rows that are different, from the top.
+	pub fn rows(&self) -> impl Iterator<Item = usize> + '_ {
+		self.rows.iter().map(|row| row.row)
+	}
+}
+
+impl fmt::Display for Diff {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "the screen doesn't match the golden fixture:")?;
+		for row in &self.rows {
+			writeln!(f, "row {} {}:", row.row, row.kind)?;
+			writeln!(f, "\x1b[91m- |{}|\x1b[0m", row.expected)?;
+			writeln!(f, "\x1b[92m+ |{}|\x1b[0m", row.actual)?;
+			if let Some(detail) = &row.detail {
+				match detail.expected {
+					Some(expected) => writeln!(
+						f, "  column {} should be {:02x}, it's {:02x}",
+						detail.col, expected.0, detail.actual.0,
+					)?,
+					None => writeln!(
+						f, "  column {} has a key that isn't in the legend, it's {:02x}",
+						detail.col, detail.actual.0,
+					)?,
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Compares a `Snapshot` with a golden fixture. If they differ it prints the
+/// diff over serial and fails the test.
+#[macro_export]
+macro_rules! assert_screen {
+	($snapshot:expr, $golden:expr) => {
+		if let Err(diff) = $snapshot.compare($golden) {
+			$crate::serial_println!("\n{}", diff);
+			panic!("the screen doesn't match {}", stringify!($golden));
+		}
+	};
+}
+
+
+#[cfg(test)]
+fn fresh_console() -> spin::MutexGuard<'static, Writer> {
+	use core::fmt::Write;
+
+	// a console nobody looks at, so the screen is all ours
+	let mut writer = super::CONSOLES[super::KERNEL_CONSOLE + 4].lock();
+	write!(writer, "\x1b[0m\x1b[2J\x1b[1;1H").expect("could not write to vga buffer");
+	writer
+}
+
+#[test_case]
+fn test_golden_screen() {
+	use core::fmt::Write;
+	use x86_64::instructions::interrupts;
+
+	interrupts::without_interrupts(|| {
+		let mut writer = fresh_console();
+		write!(writer, "Hello, \x1b[93mworld\x1b[0m!\n\n  ┌─┐\n  └─┘ \x1b[97;41merror\x1b[0m")
+			.expect("could not write to vga buffer");
+		crate::assert_screen!(writer.snapshot(), include_str!("../../tests/golden/hello.txt"));
+	});
+}
+
+#[test_case]
+fn test_golden_diff() {
+	use core::fmt::Write;
+	use x86_64::instructions::interrupts;
+
+	let snapshot = interrupts::without_interrupts(|| {
+		let mut writer = fresh_console();
+		write!(writer, "one\ntwo\n\x1b[92mthree").expect("could not write to vga buffer");
+		writer.snapshot()
+	});
+	assert_eq!(snapshot.row_text(1), "two");
+	assert!(snapshot.compare("one\ntwo\nthree").is_ok());
+
+	let diff = snapshot.compare("one\ntoo\nthree\n---\n\n\n99999").unwrap_err();
+	assert!(diff.rows().eq([1, 2].iter().copied()));
+	let printed = alloc::format!("{}", diff);
+	assert!(printed.contains("- |too|"));
+	assert!(printed.contains("+ |two|"));
+}
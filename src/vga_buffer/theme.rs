@@ -0,0 +1,119 @@
+// Named sets of colours. The text colour is what every console resets to,
+// the others are for messages that should stand out.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{Color, ColorCode, CONSOLES, DEFAULT_COLOR};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+	pub name: &'static str,
+	pub text: ColorCode,
+	pub warning: ColorCode,
+	pub error: ColorCode,
+	/// For the chatty stuff, like debug messages.
+	pub muted: ColorCode,
+	/// The status row at the bottom.
+	pub status: ColorCode,
+}
+
+pub const THEMES: [Theme; 5] = [
+	// what the console always looked like
+	Theme {
+		name: "classic",
+		text: DEFAULT_COLOR,
+		warning: ColorCode::new(Color::Yellow, Color::Black),
+		error: ColorCode::new(Color::White, Color::Red),
+		muted: ColorCode::new(Color::DarkGrey, Color::Black),
+		status: ColorCode::new(Color::Black, Color::LightGrey),
+	},
+	Theme {
+		name: "light",
+		text: ColorCode::new(Color::Black, Color::LightGrey),
+		warning: ColorCode::new(Color::Brown, Color::LightGrey),
+		error: ColorCode::new(Color::Red, Color::LightGrey),
+		muted: ColorCode::new(Color::DarkGrey, Color::LightGrey),
+		status: ColorCode::new(Color::White, Color::DarkGrey),
+	},
+	Theme {
+		name: "green",
+		text: ColorCode::new(Color::LightGreen, Color::Black),
+		warning: ColorCode::new(Color::Yellow, Color::Black),
+		error: ColorCode::new(Color::LightRed, Color::Black),
+		muted: ColorCode::new(Color::Green, Color::Black),
+		status: ColorCode::new(Color::Black, Color::Green),
+	},
+	Theme {
+		name: "blue",
+		text: ColorCode::new(Color::White, Color::Blue),
+		warning: ColorCode::new(Color::Yellow, Color::Blue),
+		error: ColorCode::new(Color::LightRed, Color::Blue),
+		muted: ColorCode::new(Color::LightGrey, Color::Blue),
+		status: ColorCode::new(Color::Blue, Color::LightGrey),
+	},
+	Theme {
+		name: "mono",
+		text: ColorCode::new(Color::LightGrey, Color::Black),
+		warning: ColorCode::new(Color::White, Color::Black),
+		error: ColorCode::new(Color::Black, Color::LightGrey),
+		muted: ColorCode::new(Color::DarkGrey, Color::Black),
+		status: ColorCode::new(Color::Black, Color::LightGrey),
+	},
+];
+
+static THEME: AtomicUsize = AtomicUsize::new(0);  // an index into `THEMES`
+
+pub fn theme() -> &'static Theme {
+	&THEMES[THEME.load(Ordering::Relaxed)]
+}
+
+/// `set_theme` was given a name that isn't in `THEMES`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownTheme;
+
+/// Switches every console to another theme. Text in the old text colour is
+/// repainted, anything coloured on purpose keeps its colour.
+pub fn set_theme(name: &str) -> Result<(), UnknownTheme> {
+	use x86_64::instructions::interrupts;
+
+	let index = THEMES.iter().position(|theme| theme.name == name).ok_or(UnknownTheme)?;
+	interrupts::without_interrupts(|| {
+		THEME.store(index, Ordering::Relaxed);
+		for console in CONSOLES.iter() {
+			let mut writer = console.lock();
+			writer.set_default_color(THEMES[index].text);
+			writer.flush();
+		}
+	});
+	Ok(())
+}
+
+
+#[test_case]
+fn test_themes() {
+	use core::fmt::Write;
+	use super::KERNEL_CONSOLE;
+	use x86_64::instructions::interrupts;
+
+	assert_eq!(set_theme("no such theme"), Err(UnknownTheme));
+	assert_eq!(theme().name, "classic");
+
+	let old = theme().text;
+	interrupts::without_interrupts(|| {
+		write!(CONSOLES[KERNEL_CONSOLE].lock(), "\nplain\x1b[94mblue\x1b[0m")
+			.expect("could not write to vga buffer");
+	});
+
+	set_theme("light").expect("the light theme exists");
+	let light = theme().text;
+	interrupts::without_interrupts(|| {
+		let writer = CONSOLES[KERNEL_CONSOLE].lock();
+		let row = writer.row_position;
+		assert_eq!(writer.shadow[row][0].color_code, light);
+		assert_eq!(writer.shadow[row][5].color_code, old.with_foreground(Color::LightBlue as u8));
+		assert_eq!(writer.color(), light);
+	});
+
+	set_theme("classic").expect("the classic theme exists");
+	assert_eq!(theme().text, old);
+}
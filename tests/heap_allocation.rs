@@ -34,7 +34,7 @@ fn main(boot_info: &'static BootInfo) -> ! {
 	allocator::init_heap(&mut mapper, &mut frame_allocator).expect("Unable to allocate heap");
 
 	test_main();
-	loop {}
+	text_os::hlt_loop();
 }
 
 #[test_case]
@@ -78,3 +78,14 @@ fn many_boxes_long_lived() {
 	}
 	assert_eq!(*long_lived, 1);
 }
+
+#[test_case]
+fn heap_used_is_counted() {
+	use text_os::allocator::heap_used;
+	use alloc::vec::Vec;
+	let before = heap_used();
+	let vec: Vec<u8> = Vec::with_capacity(4000);
+	assert_eq!(heap_used(), before + 4000);
+	drop(vec);
+	assert_eq!(heap_used(), before);
+}
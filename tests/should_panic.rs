@@ -9,7 +9,7 @@ use text_os::{QemuExitCode, exit_qemu, serial_println, serial_print};
 fn panic(_info: &PanicInfo) -> ! {
     serial_println!("\x1b[92m[ok]\x1b[0m");
     exit_qemu(QemuExitCode::Success);
-    loop {}
+    text_os::hlt_loop();
 }
 
 
@@ -21,7 +21,7 @@ pub extern "C" fn _start() -> ! {
     should_fail();
     serial_println!("\x1b[91m[test did not panic]\x1b[0m");
     exit_qemu(QemuExitCode::Failed);
-    loop {}
+    text_os::hlt_loop();
 }
 
 
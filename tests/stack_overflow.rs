@@ -63,6 +63,6 @@ extern "x86-interrupt" fn test_double_fault_handler(
 ) -> ! {
 	serial_println!("\x1b[92m[ok]\x1b[0m");
 	exit_qemu(QemuExitCode::Success);
-	loop {}
+	text_os::hlt_loop();
 
 }